@@ -1,3 +1,5 @@
+use guard::guard;
+
 use crate::devices::DeviceTrait;
 
 use super::{
@@ -11,7 +13,13 @@ impl ExecutorLogic {
   pub(super) async fn execute_device(&mut self, target: Topic, cmd: DeviceCommand) {
     match cmd {
       DeviceCommand::UpdateState(state) => {
-        self.home.lock().await.find_device_mut(&target).unwrap().update_state(state);
+        let now = self.clock.now();
+        let mut home = self.home.lock().await;
+        guard!(let Some(device) = home.find_device_mut(&target) else {
+          eprintln!("Received a state of {}, which is no device.  Ignored.", target.to_str());
+          return;
+        });
+        device.update_state(state, now);
       }
       DeviceCommand::QueryUpdate => {
         let home = self.home.lock().await;
@@ -1,16 +1,18 @@
 use std::rc::Rc;
 
 use futures::{stream, StreamExt};
+use guard::guard;
 use tokio::sync::{
   mpsc::{UnboundedReceiver, UnboundedSender},
   Mutex,
 };
 
 use crate::{
-  convert::StateToMqtt, home::Home, mqtt::ProtectedClient, scenes::manager::SceneEvent, Result,
+  clock::SharedClock, convert::StateToMqtt, home::Home, mqtt::ProtectedClient,
+  scenes::manager::SceneEvent, watcher::OwnWrites, Result,
 };
 
-use super::{request::Request, topic::Topic};
+use super::{request::Request, topic::Topic, traits::ReadWriteHome};
 
 #[allow(missing_debug_implementations)]
 pub struct Executor {
@@ -24,11 +26,26 @@ impl Executor {
     scene_events: UnboundedSender<SceneEvent>,
     client: ProtectedClient,
     home: Rc<Mutex<Home>>,
+    clock: SharedClock,
   ) -> Self {
-    let inner = ExecutorLogic { client, home, scene_events };
+    let inner = ExecutorLogic {
+      client,
+      home,
+      scene_events,
+      clock,
+      home_path: None,
+      own_writes: OwnWrites::default(),
+    };
     Executor { requests, inner }
   }
 
+  /// Where edits made over the API are persisted.  The written files are noted in `own_writes`.
+  pub fn with_home_path(mut self, home_path: &str, own_writes: OwnWrites) -> Self {
+    self.inner.home_path = Some(home_path.to_string());
+    self.inner.own_writes = own_writes;
+    self
+  }
+
   pub async fn run(self) -> Result<()> {
     let Executor { mut requests, mut inner } = self;
     loop {
@@ -43,6 +60,9 @@ pub struct ExecutorLogic {
   pub(super) client: ProtectedClient,
   pub(super) home: Rc<Mutex<Home>>,
   pub(super) scene_events: UnboundedSender<SceneEvent>,
+  pub(super) clock: SharedClock,
+  pub(super) home_path: Option<String>,
+  pub(super) own_writes: OwnWrites,
 }
 
 impl ExecutorLogic {
@@ -50,7 +70,7 @@ impl ExecutorLogic {
     match req {
       Request::Query(query, resp) => self.respond(query, resp).await,
       Request::LightCommand(cmd, additional) => self.execute_light(cmd, additional).await,
-      Request::HomeEdit(he) => self.edit_home(he).await,
+      Request::HomeEdit(he, resp) => self.edit_home(he, resp).await,
       Request::General(general) => self.execute_general(general).await,
       Request::RemoteAction(ra) => self.remote_action(ra).await,
       Request::DeviceCommand(cmd, target) => self.execute_device(target, cmd).await,
@@ -58,9 +78,75 @@ impl ExecutorLogic {
     }
   }
 
+  /// Writes the home back to its file, which the watcher then leaves alone.
+  pub(super) fn persist(&self, home: &Home) {
+    guard!(let Some(path) = &self.home_path else { return });
+    match home.persist(path) {
+      Ok(()) => self.own_writes.note(path),
+      Err(err) => eprintln!("Cannot persist home: {err:?}."),
+    }
+  }
+
   pub(super) async fn send_mqtt_payloads(&mut self, payloads: Vec<(Topic, StateToMqtt)>) {
     stream::iter(payloads)
       .for_each_concurrent(None, |(t, p)| async { self.client.lock().await.publish(t, p).await })
       .await;
   }
 }
+
+#[cfg(test)]
+pub(super) mod test {
+  use std::rc::Rc;
+
+  use futures::{stream, FutureExt, StreamExt};
+  use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver},
+    Mutex,
+  };
+
+  use super::ExecutorLogic;
+  use crate::{
+    clock::FakeClock,
+    home::Home,
+    mqtt::{self, MessageStream},
+    scenes::manager::SceneEvent,
+    simulation::{fixture, FakeBroker},
+    watcher::OwnWrites,
+  };
+
+  /// An executor on a fake broker, subscribed to the devices of its home.  Nothing runs unless
+  /// the test calls into it.
+  pub struct Bench {
+    pub logic: ExecutorLogic,
+    pub broker: FakeBroker,
+    pub events: UnboundedReceiver<SceneEvent>,
+    messages: MessageStream,
+  }
+
+  impl Bench {
+    pub async fn new(home: Home, home_path: Option<&str>) -> Self {
+      let broker = FakeBroker::new();
+      let (connection, messages) = broker.connect();
+      let (queue, _) = unbounded_channel();
+      let (scene_events, events) = unbounded_channel();
+      let (client, _) =
+        mqtt::attach(Box::new(connection), stream::empty().boxed(), queue, scene_events.clone());
+      client.lock().await.subscribe_to_all(ExecutorLogic::device_topics(&home)).await;
+      let logic = ExecutorLogic {
+        client,
+        home: Rc::new(Mutex::new(home)),
+        scene_events,
+        clock: FakeClock::new(fixture::start()).shared(),
+        home_path: home_path.map(String::from),
+        own_writes: OwnWrites::default(),
+      };
+      Bench { logic, broker, events, messages }
+    }
+
+    /// Topics of the messages the executor received since the last call.
+    pub fn received(&mut self) -> Vec<String> {
+      let next = || self.messages.next().now_or_never().flatten().flatten();
+      std::iter::from_fn(next).map(|m| m.topic).collect()
+    }
+  }
+}
@@ -1,12 +1,118 @@
-use super::{executor::ExecutorLogic, request::General, traits::ReadWriteHome};
+use crate::{
+  home::Home,
+  scenes::{manager::SceneEvent, scene::Scene},
+};
+
+use super::{
+  executor::ExecutorLogic,
+  request::{DeviceCommand, General},
+  topic::{Topic, TopicMode},
+  traits::{Addressable, DeviceCollection, ReadWriteHome},
+};
 
 impl ExecutorLogic {
   pub(super) async fn execute_general(&mut self, cmd: General) {
     match cmd {
-      General::Shutdown { home_path } => {
+      General::Shutdown => {
         self.client.lock().await.disconnect().await;
-        self.home.lock().await.persist(&home_path).expect("Couldn't write home.")
+        if let Some(path) = &self.home_path {
+          self.home.lock().await.persist(path).expect("Couldn't write home.")
+        }
+      }
+      General::Reload { home, path } => {
+        // The config may have moved the home file.
+        if self.home_path.is_some() {
+          self.home_path = Some(path);
+        }
+        self.reload(*home).await
       }
     }
   }
+
+  async fn reload(&mut self, mut new: Home) {
+    let mut home = self.home.lock().await;
+    let old_topics = Self::device_topics(&home);
+    let new_topics = Self::device_topics(&new);
+    let added: Vec<Topic> =
+      new_topics.iter().filter(|t| !old_topics.contains(t)).cloned().collect();
+    let removed: Vec<Topic> =
+      old_topics.iter().filter(|t| !new_topics.contains(t)).cloned().collect();
+    // Scenes forget what they were doing when their definition changes or goes away.
+    let definition = |scene: &Scene| serde_json::to_value(scene).ok();
+    let changed = |old: &&Scene| {
+      let new = new.scenes.iter().find(|s| s.name == old.name);
+      new.map(definition) != Some(definition(old))
+    };
+    let redefined: Vec<String> =
+      home.scenes.iter().filter(changed).map(|s| s.name.clone()).collect();
+    new.inherit_states(&home);
+    *home = new; // Swaps devices and scenes in one go.
+    let scenes = home.scenes.len();
+    drop(home);
+
+    let client = self.client.lock().await;
+    client.unsubscribe_from_all(removed.clone()).await;
+    client.subscribe_to_all(added.clone()).await;
+    drop(client);
+    println!(
+      "Reloaded home: {} devices added, {} removed, {} scenes active.",
+      added.len(),
+      removed.len(),
+      scenes
+    );
+    for name in redefined {
+      self.scene_events.send(SceneEvent::Redefined(name)).unwrap();
+    }
+    for topic in added {
+      self.execute_device(topic, DeviceCommand::QueryUpdate).await;
+    }
+  }
+
+  pub(super) fn device_topics(home: &Home) -> Vec<Topic> {
+    home.flatten_devices().into_iter().map(|d| d.topic(TopicMode::Blank)).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    api::{executor::test::Bench, request::General},
+    mqtt::MqttMessage,
+    scenes::manager::SceneEvent,
+    simulation::fixture::{self, FLOOR},
+  };
+
+  #[tokio::test]
+  async fn test_reload_redefines_changed_scenes() {
+    let scene = |name: &str, command: &str| {
+      let effect = format!("!LightCommand {{ target: {FLOOR}, command: {command} }}");
+      format!("{{ name: {name}, trigger: ManualOnly, effect: {effect} }}")
+    };
+    let (kept, changed) = (scene("Kept", "Toggle"), scene("Changed", "Toggle"));
+    let old = format!("[{kept}, {changed}, {}]", scene("Removed", "Toggle"));
+    let mut bench = Bench::new(fixture::home(&old), None).await;
+    let new = format!("[{kept}, {}]", scene("Changed", "TurnOn"));
+    let home = Box::new(fixture::home(&new));
+    bench.logic.execute_general(General::Reload { home, path: String::from("home.yml") }).await;
+    let events: Vec<_> = std::iter::from_fn(|| bench.events.try_recv().ok()).collect();
+    let redefined = |e| if let SceneEvent::Redefined(name) = e { Some(name) } else { None };
+    let names: Vec<_> = events.into_iter().filter_map(redefined).collect();
+    assert_eq!(names, vec!["Changed", "Removed"]);
+  }
+
+  #[tokio::test]
+  async fn test_reload_moves_subscriptions() {
+    let mut bench = Bench::new(fixture::home("[]"), Some("home.yml")).await;
+    // The new home drops Floor and replaces Door by Porch.
+    let floor = "        - { name: Floor, model: IkeaDimmable, icon: bulb, room: Hall }\n";
+    let new = fixture::yaml("[]").replace(floor, "").replace("name: Door", "name: Porch");
+    let home = Box::new(serde_yaml::from_str(&new).unwrap());
+    bench.logic.execute_general(General::Reload { home, path: String::from("moved.yml") }).await;
+    assert_eq!(bench.logic.home_path.as_deref(), Some("moved.yml"), "Shutdown persists there.");
+    let porch = "zigbee2mqtt/Device/Sensor/Hall/Porch";
+    for topic in [FLOOR, fixture::DOOR, porch] {
+      bench.broker.publish(MqttMessage::new(topic.to_string(), String::from("{}")));
+    }
+    assert_eq!(bench.received(), vec![porch.to_string()]);
+  }
 }
@@ -1,11 +1,75 @@
-use crate::api::traits::EditableHome;
+use tokio::sync::oneshot::Sender;
+
+use crate::{api::traits::EditableHome, home::validation::Diagnostic, scenes::manager::SceneEvent};
 
 use super::{executor::ExecutorLogic, request::HomeEdit};
 
 impl ExecutorLogic {
-  pub(super) async fn edit_home(&mut self, edit: HomeEdit) {
-    match edit {
-      HomeEdit::AddRoom { name } => self.home.lock().await.add_room(name),
+  pub(super) async fn edit_home(&mut self, edit: HomeEdit, over: Sender<Vec<Diagnostic>>) {
+    let mut home = self.home.lock().await;
+    let exists = |name: &str| home.scenes.iter().any(|s| s.name == name);
+    let (scene, diagnostics) = match edit {
+      HomeEdit::AddRoom { name } => {
+        home.add_room(name);
+        (None, vec![])
+      }
+      HomeEdit::CreateScene(scene) if exists(&scene.name) => {
+        (None, vec![Diagnostic::error(format!("Scene {} already exists.", scene.name))])
+      }
+      HomeEdit::UpdateScene(scene) if !exists(&scene.name) => {
+        (None, vec![Diagnostic::error(format!("Scene {} does not exist.", scene.name))])
+      }
+      HomeEdit::CreateScene(scene) | HomeEdit::UpdateScene(scene) => {
+        let name = scene.name.clone();
+        let diagnostics = home.put_scene(*scene, self.clock.now());
+        let accepted = !diagnostics.iter().any(Diagnostic::is_error);
+        (accepted.then_some(name), diagnostics)
+      }
+      HomeEdit::DeleteScene(name) => match home.delete_scene(&name) {
+        Ok(()) => (Some(name), vec![]),
+        Err(_) => (None, vec![Diagnostic::error(format!("Scene {name} does not exist."))]),
+      },
+    };
+    if let Some(name) = scene {
+      println!("Scene {name} was redefined.");
+      self.scene_events.send(SceneEvent::Redefined(name)).unwrap();
+      self.persist(&home);
     }
+    drop(home);
+    over.send(diagnostics).expect("Failed to send response.");
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use tokio::sync::oneshot;
+
+  use crate::{
+    api::{executor::test::Bench, request::HomeEdit},
+    home::Home,
+    scenes::manager::SceneEvent,
+    simulation::fixture::{self, FLOOR},
+  };
+
+  #[tokio::test]
+  async fn test_edits_persist_to_the_home_file() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_edit_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("home.yml").to_string_lossy().to_string();
+    std::fs::write(&path, fixture::yaml("[]")).unwrap();
+    let mut bench = Bench::new(Home::load(&path, fixture::start()).unwrap(), Some(&path)).await;
+    let effect = format!("!LightCommand {{ target: {FLOOR}, command: Toggle }}");
+    let scene = format!("{{ name: Day, trigger: ManualOnly, effect: {effect} }}");
+    let (sender, receiver) = oneshot::channel();
+    let edit = HomeEdit::CreateScene(Box::new(serde_yaml::from_str(&scene).unwrap()));
+    bench.logic.edit_home(edit, sender).await;
+    assert!(receiver.await.unwrap().is_empty());
+    assert!(matches!(bench.events.try_recv(), Ok(SceneEvent::Redefined(name)) if name == "Day"));
+    // The next start finds the scene, the watcher does not reload the home over it.
+    let home = Home::load(&path, fixture::start()).unwrap();
+    assert_eq!(home.scenes.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["Day"]);
+    assert!(bench.logic.own_writes.wrote(&path));
+    assert!(!dir.join("home.yml.tmp").exists());
+    std::fs::remove_dir_all(dir).unwrap();
   }
 }
@@ -1,19 +1,27 @@
-use chrono::{Local, Timelike};
+use chrono::Timelike;
+use guard::guard;
 
 use crate::{
   common::Scalar,
-  convert::{RestApiPayload, Val},
+  convert::{Origin, RestApiPayload, Val},
 };
 
 use super::{executor::ExecutorLogic, request::LightCommand, traits::EffectiveLightCollection};
 
 impl ExecutorLogic {
   pub(super) async fn execute_light(&mut self, cmd: LightCommand, adds: RestApiPayload) {
-    let target = adds.topic.clone().unwrap();
+    guard!(let Some(target) = adds.topic.clone() else {
+      eprintln!("Light command {cmd:?} names no target.  Ignored.");
+      return;
+    });
+    let manual = adds.origin == Origin::Manual;
     let mut home = self.home.lock().await;
-    let light = home.find_effective_light_mut(&target).expect("Implement error handling.");
+    guard!(let Some(light) = home.find_effective_light_mut(&target) else {
+      eprintln!("Light command {cmd:?} for {}, which is no light.  Ignored.", target.to_str());
+      return;
+    });
     let payloads = match cmd {
-      LightCommand::TurnOn => light.turn_on(Some(Self::dynamic_brightness())),
+      LightCommand::TurnOn => light.turn_on(Some(self.dynamic_brightness())),
       LightCommand::TurnOff => light.turn_off(),
       LightCommand::Toggle => light.toggle(),
       LightCommand::DimUp => light.dim_up(),
@@ -23,12 +31,15 @@ impl ExecutorLogic {
       LightCommand::StopDim => light.stop_dim(),
       LightCommand::ChangeState => light.change_state(adds),
     };
+    if manual {
+      home.mark_overridden(&target, self.clock.now());
+    }
     drop(home);
     self.send_mqtt_payloads(payloads).await;
   }
 
-  fn dynamic_brightness() -> Val {
-    let (afternoon, hour) = Local::now().hour12();
+  fn dynamic_brightness(&self) -> Val {
+    let (afternoon, hour) = self.clock.now().hour12();
     Self::_dynamic_brightness(afternoon, hour)
   }
   fn _dynamic_brightness(afternoon: bool, hour: u32) -> Val {
@@ -43,15 +54,36 @@ impl ExecutorLogic {
 #[cfg(test)]
 mod test {
   use super::ExecutorLogic;
+  use crate::{
+    api::{executor::test::Bench, request::LightCommand},
+    convert::{Origin, RestApiPayload},
+    simulation::fixture::{self, CEILING, MOTION},
+  };
+
+  #[tokio::test]
+  async fn test_commands_for_no_light_are_ignored() {
+    let mut bench = Bench::new(fixture::home("[]"), None).await;
+    let payload = |target: &str| RestApiPayload {
+      topic: Some(fixture::topic(target)),
+      ..RestApiPayload::new(Origin::Manual)
+    };
+    for target in ["zigbee2mqtt/Room/Attic", MOTION] {
+      bench.logic.execute_light(LightCommand::TurnOn, payload(target)).await;
+    }
+    assert!(bench.broker.history().is_empty());
+    bench.logic.execute_light(LightCommand::TurnOn, payload(CEILING)).await;
+    assert_eq!(bench.broker.history().len(), 1);
+  }
+
   #[test]
   fn test_dynamic_brightness() {
     let res = ExecutorLogic::_dynamic_brightness(true, 0).to_rest().inner();
-    assert!(0.9 <= res && res <= 1.0);
+    assert!((0.9..=1.0).contains(&res));
     let res = ExecutorLogic::_dynamic_brightness(false, 0).to_rest().inner();
-    assert!(0.05 <= res && res <= 0.15);
+    assert!((0.05..=0.15).contains(&res));
     let res = ExecutorLogic::_dynamic_brightness(true, 11).to_rest().inner();
-    assert!(0.05 <= res && res <= 0.15);
+    assert!((0.05..=0.15).contains(&res));
     let res = ExecutorLogic::_dynamic_brightness(false, 11).to_rest().inner();
-    assert!(0.9 <= res && res <= 1.0);
+    assert!((0.9..=1.0).contains(&res));
   }
 }
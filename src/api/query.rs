@@ -1,4 +1,12 @@
-use super::{executor::ExecutorLogic, payload::JsonPayload, request::Query, traits::QueryableHome};
+use super::{
+  executor::ExecutorLogic,
+  payload::JsonPayload,
+  request::Query,
+  topic::TopicMode,
+  traits::{Addressable, DeviceCollection, QueryableHome},
+};
+use crate::{devices::DeviceTrait, scenes::manager::SceneEvent};
+use serde_json::json;
 use tokio::sync::oneshot::Sender;
 
 impl ExecutorLogic {
@@ -19,6 +27,63 @@ impl ExecutorLogic {
           .collect::<Vec<_>>();
         JsonPayload::from(&history)
       }
+      Query::Scenes => {
+        let home = self.home.lock().await;
+        let scenes: Vec<_> = home
+          .scenes
+          .iter()
+          .map(|scene| {
+            let mut value = json!(scene);
+            value["last_triggered"] = json!(scene.last_triggered.map(|time| time.to_rfc3339()));
+            value["runs"] = json!(scene.history.len());
+            value
+          })
+          .collect();
+        JsonPayload::from(&scenes)
+      }
+      Query::SceneHistory(name) => {
+        let home = self.home.lock().await;
+        match home.scenes.iter().find(|s| s.name == name) {
+          Some(scene) => JsonPayload::from(&scene.history),
+          None => JsonPayload::from(&json!({ "error": format!("Scene {name} does not exist.") })),
+        }
+      }
+      Query::Explain { scene, update, at } => {
+        // Only the scene manager knows what the triggers remember, so it answers itself.
+        let event =
+          update.map(|(topic, payload)| Box::new(SceneEvent::SensorUpdate(topic, payload)));
+        let explain = SceneEvent::Explain { scene, event, at, reply: over };
+        self.scene_events.send(explain).unwrap();
+        return;
+      }
+      Query::Overrides => {
+        let overrides = self.home.lock().await.overrides(self.clock.now());
+        let overrides: Vec<_> = overrides
+          .into_iter()
+          .map(|(light, until)| json!({ "light": light.to_str(), "until": until.to_rfc3339() }))
+          .collect();
+        JsonPayload::from(&overrides)
+      }
+      Query::Health => {
+        let home = self.home.lock().await;
+        let now = self.clock.now();
+        let health: Vec<_> = home
+          .flatten_devices()
+          .into_iter()
+          .map(|device| {
+            let health = device.health();
+            json!({
+              "device": device.topic(TopicMode::Blank).to_str(),
+              "battery": health.battery,
+              "linkquality": health.linkquality,
+              "last_seen": health.last_seen.map(|t| t.to_rfc3339()),
+              // Devices that never reported count as silent from now on.
+              "issues": health.issues(&home.health, now, now),
+            })
+          })
+          .collect();
+        JsonPayload::from(&health)
+      }
     };
     over.send(res).expect("Failed to send response.");
   }
@@ -1,14 +1,57 @@
-use crate::convert::RestApiPayload;
+use guard::guard;
+
+use crate::devices::{
+  remote::{ButtonAction, RemoteButton},
+  DeviceTrait,
+};
 
 use super::{executor::ExecutorLogic, request::RemoteAction, traits::DeviceCollection};
 
 impl ExecutorLogic {
   pub(super) async fn remote_action(&mut self, action: RemoteAction) {
-    let home = self.home.lock().await;
-    let remote = home.find_remote(&action.target).expect("Implement error handling.");
-    let cmd = remote.action(action.button).expect("Implement error handling");
-    let mqtt = RestApiPayload { topic: Some(remote.controls()), ..Default::default() };
+    let now = self.clock.now();
+    let mut home = self.home.lock().await;
+    guard!(let Some(remote) = home.find_remote_mut(&action.target) else {
+      eprintln!("Received an action from {}, which is no remote.  Ignored.", action.target.to_str());
+      return;
+    });
+    guard!(let Some(button) = RemoteButton::parse(&action.action, remote.model()) else {
+      eprintln!("Remote {} ({:?}) has no button {}.  Ignored.", remote.name(), remote.model(), action.action);
+      return;
+    });
+    let gesture = remote.recognise(button, now);
+    let actions = remote.actions(&gesture);
+    if actions.is_empty() {
+      println!("Remote {} does nothing on {gesture}.", remote.name());
+      return;
+    }
+    // Switching modes redirects the actions that follow, so each one is aimed before going on.
+    let mut aimed = vec![];
+    for action in actions {
+      let next_mode = action == ButtonAction::NextMode;
+      if next_mode {
+        remote.next_mode();
+      }
+      let controls = match remote.try_controls() {
+        Ok(controls) => controls,
+        Err(err) => {
+          eprintln!(
+            "Remote {} controls an invalid topic: {err:?}.  Skipped {action:?}.",
+            remote.name()
+          );
+          continue;
+        }
+      };
+      if next_mode {
+        println!("Remote {} now controls {}.", remote.name(), controls.to_str());
+      }
+      aimed.push((action, controls));
+    }
     drop(home);
-    self.execute_light(cmd, mqtt).await;
+    for (action, controls) in aimed {
+      if let Some(request) = action.request(controls) {
+        Box::pin(self.process(request)).await;
+      }
+    }
   }
 }
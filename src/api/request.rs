@@ -1,9 +1,14 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use tokio::sync::oneshot::Sender;
 
-use crate::{convert::RestApiPayload, convert::StateFromMqtt, devices::remote::RemoteButton};
+use crate::{
+  convert::RestApiPayload, convert::StateFromMqtt, home::validation::Diagnostic, home::Home,
+  scenes::scene::Scene,
+};
 
 use super::{payload::JsonPayload, topic::Topic};
 
@@ -15,7 +20,8 @@ pub enum Request {
   DeviceCommand(DeviceCommand, Topic),
   LightCommand(LightCommand, RestApiPayload),
   RemoteAction(RemoteAction),
-  HomeEdit(HomeEdit),
+  /// Answered with what speaks against the edit, which is only made if nothing is an error.
+  HomeEdit(HomeEdit, Sender<Vec<Diagnostic>>),
   General(General),
   SceneCommand(SceneCommand),
 }
@@ -25,6 +31,18 @@ pub enum Query {
   Architecture,
   DeviceState(Topic),
   DeviceHistory(Topic),
+  Overrides,
+  /// The health of every device and what is wrong with it.
+  Health,
+  Scenes,
+  /// The latest runs of the named scene.
+  SceneHistory(String),
+  /// How a scene would react to an update of a device, or to the clock, at a time or now.
+  Explain {
+    scene: String,
+    update: Option<(Topic, JsonValue)>,
+    at: Option<DateTime<Local>>,
+  },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -48,21 +66,52 @@ pub enum DeviceCommand {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RemoteAction {
-  pub button: RemoteButton,
+  /// What the remote reported, read according to its model.
+  pub action: String,
   pub target: Topic,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub enum HomeEdit {
   AddRoom { name: String },
+  CreateScene(Box<Scene>),
+  UpdateScene(Box<Scene>),
+  DeleteScene(String),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub enum General {
-  Shutdown { home_path: String },
+  /// Persists the home to where it was last read from.
+  Shutdown,
+  /// Swaps in the home read from `path`, which is where it is persisted from then on.
+  Reload { home: Box<Home>, path: String },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SceneCommand {
   Trigger(String),
+  SavePreset {
+    name: String,
+    target: Topic,
+  },
+  /// Like `SavePreset`, but kept in memory only and dropped once restored.
+  Snapshot {
+    name: String,
+    target: Topic,
+  },
+  RestorePreset(String),
+  /// Like `RestorePreset`, but only for the given lights, which scenes send when other lights of
+  /// the preset are taken.
+  RestoreLights {
+    name: String,
+    lights: Vec<Topic>,
+  },
+  DeletePreset(String),
+  SetEnabled {
+    name: String,
+    enabled: bool,
+  },
+  ToggleEnabled(String),
+  /// Hands the lights under the topic, or all lights, back to the scenes.
+  ClearOverrides(Option<Topic>),
 }
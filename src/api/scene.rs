@@ -1,6 +1,8 @@
+use guard::guard;
+
 use crate::scenes::manager::SceneEvent;
 
-use super::{executor::ExecutorLogic, request::SceneCommand};
+use super::{executor::ExecutorLogic, request::SceneCommand, topic::Topic};
 
 impl ExecutorLogic {
   pub(super) async fn execute_scene(&mut self, cmd: SceneCommand) {
@@ -8,6 +10,74 @@ impl ExecutorLogic {
       SceneCommand::Trigger(name) => {
         self.scene_events.send(SceneEvent::ManualTrigger(name)).unwrap();
       }
+      SceneCommand::SavePreset { name, target } => self.save_preset(name, target, false).await,
+      SceneCommand::Snapshot { name, target } => self.save_preset(name, target, true).await,
+      SceneCommand::RestorePreset(name) => self.restore_preset(&name, None).await,
+      SceneCommand::RestoreLights { name, lights } => {
+        self.restore_preset(&name, Some(&lights)).await
+      }
+      SceneCommand::SetEnabled { name, enabled } => self.set_enabled(&name, |_| enabled).await,
+      SceneCommand::ToggleEnabled(name) => self.set_enabled(&name, |enabled| !enabled).await,
+      SceneCommand::ClearOverrides(target) => {
+        self.home.lock().await.clear_overrides(target.as_ref());
+      }
+      SceneCommand::DeletePreset(name) => {
+        if let Err(err) = self.home.lock().await.delete_preset(&name) {
+          eprintln!("Cannot delete preset {name}: {err:?}.");
+        }
+      }
+    }
+  }
+
+  /// Whether a scene is enabled is part of its definition, so the change goes to the home file.
+  async fn set_enabled(&mut self, name: &str, enabled: impl FnOnce(bool) -> bool) {
+    let mut home = self.home.lock().await;
+    guard!(let Some(scene) = home.scenes.iter_mut().find(|s| s.name == name) else {
+      eprintln!("Cannot enable or disable scene {name}, which does not exist.");
+      return;
+    });
+    scene.enabled = enabled(scene.enabled);
+    self.persist(&home);
+  }
+
+  async fn restore_preset(&mut self, name: &str, only: Option<&[Topic]>) {
+    let payloads = self.home.lock().await.restore_preset(name, only);
+    match payloads {
+      Ok(payloads) => self.send_mqtt_payloads(payloads).await,
+      Err(err) => eprintln!("Cannot restore preset {name}: {err:?}."),
+    }
+  }
+
+  async fn save_preset(&mut self, name: String, target: Topic, temporary: bool) {
+    if let Err(err) = self.home.lock().await.save_preset(name.clone(), target, temporary) {
+      eprintln!("Cannot save preset {name}: {err:?}.");
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    api::{executor::test::Bench, request::SceneCommand},
+    home::Home,
+    simulation::fixture::{self, FLOOR},
+  };
+
+  #[tokio::test]
+  async fn test_disabling_persists_to_the_home_file() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_enable_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("home.yml").to_string_lossy().to_string();
+    let effect = format!("!LightCommand {{ target: {FLOOR}, command: Toggle }}");
+    let scenes = format!("[{{ name: Day, trigger: ManualOnly, effect: {effect} }}]");
+    std::fs::write(&path, fixture::yaml(&scenes)).unwrap();
+    let mut bench = Bench::new(Home::load(&path, fixture::start()).unwrap(), Some(&path)).await;
+    bench.logic.execute_scene(SceneCommand::ToggleEnabled("Day".to_string())).await;
+    assert!(!Home::load(&path, fixture::start()).unwrap().scenes[0].enabled);
+    let enable = SceneCommand::SetEnabled { name: "Day".to_string(), enabled: true };
+    bench.logic.execute_scene(enable).await;
+    assert!(Home::load(&path, fixture::start()).unwrap().scenes[0].enabled);
+    assert!(bench.logic.own_writes.wrote(&path));
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
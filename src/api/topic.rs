@@ -170,7 +170,7 @@ impl Topic {
     self.components().join(Self::SEPARATOR)
   }
 
-  const REGEX_HOME: &str = r"^zigbee2mqtt/Home(?:/(P?<mode>set|get))?$";
+  const REGEX_HOME: &str = r"^zigbee2mqtt/Home(?:/(?P<mode>set|get))?$";
   const REGEX_BRIDGE: &str = r"^zigbee2mqtt/bridge/event$";
   const REGEX_ROOM: &str = r"^zigbee2mqtt/Room/(?P<name>(?:\w| )+)(?:/(?P<mode>set|get))?$";
   const REGEX_GROUP: &str = r"^zigbee2mqtt/Group/(?P<room>(?:\w| )+)(?:/(?:\w| )+)*?/(?P<name>(?:\w| )+)(?:/(?P<mode>set|get))?$";
@@ -246,7 +246,9 @@ impl<'de> Deserialize<'de> for Topic {
   where
     D: serde::Deserializer<'de>,
   {
-    String::deserialize(deserializer).map(|s| Topic::try_from(s).unwrap())
+    let s = String::deserialize(deserializer)?;
+    Topic::try_from(s.clone())
+      .map_err(|_| serde::de::Error::custom(format!("{s} is not a valid topic")))
   }
 }
 
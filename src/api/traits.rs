@@ -6,7 +6,7 @@ use crate::api::topic::Topic;
 use crate::convert::RestApiPayload;
 use crate::convert::StateToMqtt;
 use crate::convert::Val;
-use crate::devices::{Device, Light, Remote, Sensor};
+use crate::devices::{Device, Light, LightSnapshot, LightState, Remote, Sensor};
 use crate::Result;
 
 use super::payload::JsonPayload;
@@ -81,6 +81,18 @@ pub trait EffectiveLightCollection {
 }
 
 impl<T: DeviceCollection> EffectiveLight for T {
+  fn light_states(&self) -> Vec<&LightState> {
+    self.flatten_lights().into_iter().map(Light::state).collect()
+  }
+
+  fn snapshot(&self) -> Vec<LightSnapshot> {
+    self.flatten_lights().into_iter().flat_map(|l| l.snapshot()).collect()
+  }
+
+  fn restore(&mut self, snapshot: &[LightSnapshot]) -> Vec<(Topic, StateToMqtt)> {
+    self.flatten_lights_mut().into_iter().flat_map(|l| l.restore(snapshot)).collect()
+  }
+
   fn turn_on(&mut self, brightness: Option<Val>) -> Vec<(Topic, StateToMqtt)> {
     self.flatten_lights_mut().into_iter().flat_map(|l| l.turn_on(brightness)).collect()
   }
@@ -122,12 +134,17 @@ pub trait EditableHome {
   fn add_room(&mut self, name: String);
 }
 
-pub trait ReadWriteHome {
-  fn read(from: &str) -> Self;
+pub trait ReadWriteHome: Sized {
+  fn read(from: &str) -> Result<Self>;
   fn persist(&self, to: &str) -> Result<()>;
 }
 
 pub trait EffectiveLight: Debug {
+  /// The cached states of all lights it consists of.
+  fn light_states(&self) -> Vec<&LightState>;
+  fn snapshot(&self) -> Vec<LightSnapshot>;
+  /// Brings the lights it consists of into their state in `snapshot`, as far as it has one.
+  fn restore(&mut self, snapshot: &[LightSnapshot]) -> Vec<(Topic, StateToMqtt)>;
   fn turn_on(&mut self, brightness: Option<Val>) -> Vec<(Topic, StateToMqtt)>;
   fn turn_off(&mut self) -> Vec<(Topic, StateToMqtt)>;
   fn toggle(&mut self) -> Vec<(Topic, StateToMqtt)>;
@@ -0,0 +1,116 @@
+#![deny(unsafe_code, unused_qualifications)]
+
+//! Impersonates the zigbee2mqtt installation of a home, so the controller can run on machines
+//! without a zigbee stick.  Connects to a local mosquitto and answers for every device.
+
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::Local;
+use futures::{future::BoxFuture, FutureExt};
+use rusty_home::{
+  home::Home,
+  mqtt::PahoBackend,
+  simulation::{
+    control::ControlServer,
+    script::{RandomEvents, Rng, Script},
+    Zigbee2Mqtt,
+  },
+  Error, Result,
+};
+
+const USAGE: &str = "\
+Usage: simulator <home.yml> [options]
+
+Options:
+  --mosquitto-ip <ip>        Broker to connect to, defaults to localhost.
+  --mosquitto-port <port>    Defaults to 1883.
+  --control-port <port>      Port of the control API on localhost, defaults to 8089.
+  --script <script.yml>      Play scripted readings and button presses.
+  --random <seconds>         Let a random sensor or remote report every so many seconds.
+  --seed <n>                 Seed for --random.";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+  home: String,
+  mosquitto_ip: String,
+  mosquitto_port: u16,
+  control_port: u16,
+  script: Option<String>,
+  random: Option<f64>,
+  seed: Option<u64>,
+}
+
+impl Options {
+  fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options> {
+    let mut home = None;
+    let mut options = Options {
+      home: String::new(),
+      mosquitto_ip: String::from("localhost"),
+      mosquitto_port: 1883,
+      control_port: 8089,
+      script: None,
+      random: None,
+      seed: None,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      let mut value = || args.next().ok_or_else(|| usage(&format!("{arg} needs a value")));
+      match arg.as_str() {
+        "--mosquitto-ip" => options.mosquitto_ip = value()?,
+        "--mosquitto-port" => options.mosquitto_port = number(&arg, value()?)?,
+        "--control-port" => options.control_port = number(&arg, value()?)?,
+        "--script" => options.script = Some(value()?),
+        "--random" => options.random = Some(number(&arg, value()?)?),
+        "--seed" => options.seed = Some(number(&arg, value()?)?),
+        _ if arg.starts_with("--") => return Err(usage(&format!("unknown option {arg}"))),
+        _ if home.is_none() => home = Some(arg),
+        _ => return Err(usage(&format!("unexpected argument {arg}"))),
+      }
+    }
+    options.home = home.ok_or_else(|| usage("missing home file"))?;
+    Ok(options)
+  }
+}
+
+fn number<T: FromStr>(arg: &str, value: String) -> Result<T> {
+  value.parse().map_err(|_| usage(&format!("{arg} needs a number, not {value}")))
+}
+
+fn usage(msg: &str) -> Error {
+  Error::Usage(format!("{msg}\n\n{USAGE}"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  let options = match Options::parse(std::env::args().skip(1)) {
+    Ok(options) => options,
+    Err(Error::Usage(msg)) => {
+      eprintln!("{msg}");
+      std::process::exit(2);
+    }
+    Err(err) => return Err(err),
+  };
+  let home = Home::load(&options.home, Local::now())?;
+  let (backend, stream) =
+    PahoBackend::connect(&options.mosquitto_ip, options.mosquitto_port, "Simulator").await?;
+  let zigbee = Zigbee2Mqtt::new(&home, Arc::new(backend));
+  zigbee.subscribe().await?;
+  println!("Simulating {} devices.", zigbee.devices().len());
+
+  let addr = SocketAddr::from(([127, 0, 0, 1], options.control_port));
+  let mut tasks: Vec<BoxFuture<'static, Result<()>>> = vec![
+    zigbee.clone().run(stream).boxed(),
+    ControlServer::new(zigbee.clone(), addr).run().map(|r| r.map(|_| ())).boxed(),
+  ];
+  if let Some(path) = &options.script {
+    tasks.push(Script::read(path)?.play(zigbee.clone()).boxed());
+  }
+  if let Some(seconds) = options.random {
+    let rng = options.seed.map(Rng::new).unwrap_or_else(Rng::from_time);
+    let events = RandomEvents::new(&home, Duration::from_secs_f64(seconds), rng);
+    tasks.push(events.run(zigbee.clone()).boxed());
+  }
+  // A finished script is no reason to stop answering.
+  futures::future::try_join_all(tasks).await?;
+  Ok(())
+}
@@ -0,0 +1,407 @@
+use chrono::Local;
+use hyper::{body, Body, Client, Method, Request as HyperRequest, StatusCode, Uri};
+use serde_json::json;
+use url::Url;
+
+use crate::{
+  api::request::LightCommand,
+  config::GlobalConfig,
+  controller::Controller,
+  home::{validation, Home},
+  simulation::replay::Replay,
+  Error, Result,
+};
+
+const USAGE: &str = "\
+Usage: rusty_home [<command>] [options]
+
+Commands:
+  run                                 Start the controller (default).
+  validate [<home.yml>]               Check the config and home files.
+  query structure                     Print the home of a running instance.
+  query state|history <topic>         Print the state or history of a device.
+  query overrides                     Print the lights that scenes leave alone for now.
+  query scenes                        Print the scenes and when they were last triggered.
+  query health                        Print the battery, signal and last message of every device.
+  command <LightCommand> <topic>      Send a light command, e.g. TurnOn or ChangeState.
+  scene trigger|enable|disable|toggle <name>
+                                      Fire, enable, disable or toggle a scene.
+  scene delete <name>                 Remove a scene from a running instance.
+  scene history <name>                Print the latest runs of a scene.
+  scene explain <name> [<topic> <payload>]
+                                      Show how a scene would react to an update of a device, or
+                                      to the clock, without running it.
+  preset save <name> <topic>          Save the lights under a room, group or light as a preset.
+  preset restore|delete <name>        Restore or delete a preset.
+  override clear [<topic>]            Let scenes control a light, or all lights, again.
+  export <home.yml>                   Save the home of a running instance.
+  import <home.yml>                   Validate a home file and make it the active one.
+  replay <capture.jsonl>...           Feed captured MQTT traffic to the configured home and
+                                      report where its output differs from the capture.
+
+Options:
+  --config <path>                     Global config, defaults to $RUSTY_HOME_CONFIG or
+                                      config/global.yml.
+  --set <key>=<value>                 Override a config key, e.g. mosquitto.port=1883.
+                                      Takes precedence over RUSTY_HOME_* variables.
+  --mosquitto-ip, --mosquitto-port, --home, --web-port <value>
+                                      Shorthands for the respective --set.
+  --host <url>                        Web API of the running instance.
+  --value <v> --hue <h> --saturation <s>
+                                      Payload for `command`, each in [0, 1].
+  --at <time>                         RFC 3339 time for `scene explain`, defaults to now.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+  pub command: Command,
+  pub config: Option<String>,
+  pub overrides: Vec<(String, String)>,
+  pub host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Run,
+  Validate { home: Option<String> },
+  Query(QueryKind),
+  Command { command: LightCommand, topic: String, payload: Vec<(String, String)> },
+  TriggerScene { name: String },
+  EnableScene { name: String, enabled: bool },
+  ToggleScene { name: String },
+  DeleteScene { name: String },
+  Explain { name: String, update: Option<(String, String)>, at: Option<String> },
+  Preset(PresetAction),
+  ClearOverrides { topic: Option<String> },
+  Export { to: String },
+  Import { from: String },
+  Replay { captures: Vec<String> },
+  Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresetAction {
+  Save { name: String, topic: String },
+  Restore { name: String },
+  Delete { name: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryKind {
+  Structure,
+  State(String),
+  History(String),
+  Overrides,
+  Scenes,
+  SceneHistory(String),
+  Health,
+}
+
+impl Cli {
+  pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli> {
+    let mut positional = vec![];
+    let mut config = None;
+    let mut overrides = vec![];
+    let mut host = None;
+    let mut payload = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      let mut value =
+        |name: &str| args.next().ok_or_else(|| usage(&format!("{name} needs a value")));
+      match arg.as_str() {
+        "--config" => config = Some(value("--config")?),
+        "--set" => {
+          let setting = value("--set")?;
+          let (key, val) = setting.split_once('=').ok_or_else(|| usage("--set needs key=value"))?;
+          overrides.push((key.to_string(), val.to_string()))
+        }
+        "--mosquitto-ip" => overrides.push((String::from("mosquitto.ip"), value(&arg)?)),
+        "--mosquitto-port" => overrides.push((String::from("mosquitto.port"), value(&arg)?)),
+        "--home" => overrides.push((String::from("home.dir"), value(&arg)?)),
+        "--web-port" => overrides.push((String::from("web.port"), value(&arg)?)),
+        "--host" => host = Some(value("--host")?),
+        "--value" | "--hue" | "--saturation" | "--at" => {
+          payload.push((arg.trim_start_matches("--").to_string(), value(&arg)?))
+        }
+        "-h" | "--help" => positional.insert(0, String::from("help")),
+        _ if arg.starts_with("--") => return Err(usage(&format!("unknown option {arg}"))),
+        _ => positional.push(arg),
+      }
+    }
+    let command = Self::command(positional, payload)?;
+    Ok(Cli { command, config, overrides, host })
+  }
+
+  fn command(positional: Vec<String>, payload: Vec<(String, String)>) -> Result<Command> {
+    let words: Vec<&str> = positional.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+      [] | ["run"] => Command::Run,
+      ["help"] | ["help", ..] => Command::Help,
+      ["validate"] => Command::Validate { home: None },
+      ["validate", home] => Command::Validate { home: Some(home.to_string()) },
+      ["query", "structure"] => Command::Query(QueryKind::Structure),
+      ["query", "state", topic] => Command::Query(QueryKind::State(topic.to_string())),
+      ["query", "history", topic] => Command::Query(QueryKind::History(topic.to_string())),
+      ["query", "overrides"] => Command::Query(QueryKind::Overrides),
+      ["query", "scenes"] => Command::Query(QueryKind::Scenes),
+      ["query", "health"] => Command::Query(QueryKind::Health),
+      ["command", command, topic] => {
+        let command = serde_json::from_value::<LightCommand>(json!(command))
+          .map_err(|_| usage(&format!("unknown light command {command}")))?;
+        Command::Command { command, topic: topic.to_string(), payload }
+      }
+      ["scene", "trigger", name] => Command::TriggerScene { name: name.to_string() },
+      ["scene", "enable", name] => Command::EnableScene { name: name.to_string(), enabled: true },
+      ["scene", "disable", name] => Command::EnableScene { name: name.to_string(), enabled: false },
+      ["scene", "toggle", name] => Command::ToggleScene { name: name.to_string() },
+      ["scene", "delete", name] => Command::DeleteScene { name: name.to_string() },
+      ["scene", "history", name] => Command::Query(QueryKind::SceneHistory(name.to_string())),
+      ["scene", "explain", name, update @ ..] if matches!(update, [] | [_, _]) => {
+        let update = update.first().zip(update.get(1));
+        let update = update.map(|(topic, payload)| (topic.to_string(), payload.to_string()));
+        let at = payload.iter().find(|(key, _)| key == "at").map(|(_, at)| at.clone());
+        Command::Explain { name: name.to_string(), update, at }
+      }
+      ["preset", "save", name, topic] => {
+        Command::Preset(PresetAction::Save { name: name.to_string(), topic: topic.to_string() })
+      }
+      ["preset", "restore", name] => {
+        Command::Preset(PresetAction::Restore { name: name.to_string() })
+      }
+      ["preset", "delete", name] => {
+        Command::Preset(PresetAction::Delete { name: name.to_string() })
+      }
+      ["override", "clear"] => Command::ClearOverrides { topic: None },
+      ["override", "clear", topic] => Command::ClearOverrides { topic: Some(topic.to_string()) },
+      ["export", to] => Command::Export { to: to.to_string() },
+      ["import", from] => Command::Import { from: from.to_string() },
+      ["replay", captures @ ..] if !captures.is_empty() => {
+        Command::Replay { captures: captures.iter().map(ToString::to_string).collect() }
+      }
+      _ => return Err(usage(&format!("cannot make sense of `{}`", words.join(" ")))),
+    };
+    Ok(command)
+  }
+
+  /// Executes the command and returns the process' exit code.
+  pub async fn execute(self) -> Result<i32> {
+    match self.command {
+      Command::Run => {
+        let controller: Controller = self.global_config()?.try_into().await?;
+        controller.run().await
+      }
+      Command::Help => {
+        println!("{USAGE}");
+        Ok(0)
+      }
+      Command::Validate { ref home } => self.validate(home.clone()),
+      Command::Query(ref kind) => {
+        let (path, params) = match kind {
+          QueryKind::Structure => ("query/Structure", vec![]),
+          QueryKind::State(topic) => ("query/DeviceState", vec![("topic", topic.clone())]),
+          QueryKind::History(topic) => ("query/DeviceHistory", vec![("topic", topic.clone())]),
+          QueryKind::Overrides => ("query/Overrides", vec![]),
+          QueryKind::Scenes => ("query/Scenes", vec![]),
+          QueryKind::SceneHistory(name) => ("query/SceneHistory", vec![("name", name.clone())]),
+          QueryKind::Health => ("query/Health", vec![]),
+        };
+        println!("{}", self.request(path, &params).await?);
+        Ok(0)
+      }
+      Command::Command { command, ref topic, ref payload } => {
+        let path = format!("command/{}", json!(command).as_str().unwrap());
+        let mut params = vec![("topic", topic.clone())];
+        params.extend(payload.iter().map(|(k, v)| (k.as_str(), v.clone())));
+        println!("{}", self.request(&path, &params).await?);
+        Ok(0)
+      }
+      Command::TriggerScene { ref name } => {
+        println!("{}", self.request("scene/TriggerScene", &[("name", name.clone())]).await?);
+        Ok(0)
+      }
+      Command::EnableScene { ref name, enabled } => {
+        let path = if enabled { "scene/EnableScene" } else { "scene/DisableScene" };
+        println!("{}", self.request(path, &[("name", name.clone())]).await?);
+        Ok(0)
+      }
+      Command::ToggleScene { ref name } => {
+        println!("{}", self.request("scene/ToggleScene", &[("name", name.clone())]).await?);
+        Ok(0)
+      }
+      Command::DeleteScene { ref name } => {
+        println!(
+          "{}",
+          self.send(Method::DELETE, "scene/DeleteScene", &[("name", name.clone())]).await?
+        );
+        Ok(0)
+      }
+      Command::Explain { ref name, ref update, ref at } => {
+        let mut params = vec![("name", name.clone())];
+        if let Some((topic, payload)) = update {
+          params.extend([("topic", topic.clone()), ("payload", payload.clone())]);
+        }
+        params.extend(at.iter().map(|at| ("at", at.clone())));
+        println!("{}", self.request("query/Explain", &params).await?);
+        Ok(0)
+      }
+      Command::Preset(ref action) => {
+        let (path, params) = match action {
+          PresetAction::Save { name, topic } => {
+            ("scene/SavePreset", vec![("name", name.clone()), ("topic", topic.clone())])
+          }
+          PresetAction::Restore { name } => ("scene/RestorePreset", vec![("name", name.clone())]),
+          PresetAction::Delete { name } => ("scene/DeletePreset", vec![("name", name.clone())]),
+        };
+        println!("{}", self.request(path, &params).await?);
+        Ok(0)
+      }
+      Command::ClearOverrides { ref topic } => {
+        let params: Vec<_> = topic.iter().map(|t| ("topic", t.clone())).collect();
+        println!("{}", self.request("scene/ClearOverrides", &params).await?);
+        Ok(0)
+      }
+      Command::Export { ref to } => {
+        let structure = self.request("query/Structure", &[]).await?;
+        let home: Home =
+          serde_json::from_str(&structure).map_err(|_| Error::UnexpectedApiResponse)?;
+        std::fs::write(to, serde_yaml::to_string(&home)?)?;
+        println!("Exported home to {to}.");
+        Ok(0)
+      }
+      Command::Import { ref from } => {
+        let (_, diagnostics) = validation::check(from, Local::now());
+        diagnostics.iter().for_each(|d| println!("{d}"));
+        if diagnostics.iter().any(|d| d.is_error()) {
+          println!("Not importing {from}.");
+          return Ok(1);
+        }
+        let target = self.global_config()?.home.dir;
+        std::fs::copy(from, &target)?;
+        println!("Imported {from} as {target}.  A running instance picks it up by itself.");
+        Ok(0)
+      }
+      Command::Replay { ref captures } => {
+        let home = Home::load(&self.global_config()?.home.dir, Local::now())?;
+        let replay = Replay::read(captures)?;
+        let mismatches = replay.run(home).await?;
+        mismatches.iter().for_each(|m| println!("{m}"));
+        println!("Replayed {} messages, {} mismatches.", replay.len(), mismatches.len());
+        Ok(if mismatches.is_empty() { 0 } else { 1 })
+      }
+    }
+  }
+
+  /// Prints all diagnostics for the config and home file.
+  fn validate(&self, home: Option<String>) -> Result<i32> {
+    let home = match home {
+      Some(home) => home,
+      None => match self.global_config() {
+        Ok(config) => config.home.dir,
+        Err(Error::Config { key, msg }) => {
+          println!("error: config key {key}: {msg}");
+          return Ok(1);
+        }
+        Err(err) => {
+          println!("error: cannot read config: {err:?}");
+          return Ok(1);
+        }
+      },
+    };
+    let (_, diagnostics) = validation::check(&home, Local::now());
+    diagnostics.iter().for_each(|d| println!("{d}"));
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    println!("{home}: {errors} errors, {} warnings.", diagnostics.len() - errors);
+    Ok(if errors > 0 { 1 } else { 0 })
+  }
+
+  /// The layered config: file, then `RUSTY_HOME_*` variables, then flags.
+  fn global_config(&self) -> Result<GlobalConfig> {
+    let path = match &self.config {
+      Some(path) => path.clone(),
+      None => std::env::var(GlobalConfig::ENV_PATH).unwrap_or(GlobalConfig::PATH.to_string()),
+    };
+    let mut overrides = GlobalConfig::env_overrides(std::env::vars());
+    overrides.extend(self.overrides.iter().cloned());
+    GlobalConfig::load(&path, overrides)
+  }
+
+  async fn request(&self, path: &str, params: &[(&str, String)]) -> Result<String> {
+    self.send(Method::GET, path, params).await
+  }
+
+  async fn send(&self, method: Method, path: &str, params: &[(&str, String)]) -> Result<String> {
+    let host = match &self.host {
+      Some(host) => host.clone(),
+      None => {
+        let addr = self.global_config().and_then(|c| c.web.address());
+        format!("http://{}", addr.map_err(|_| usage("no address for the web API, pass --host"))?)
+      }
+    };
+    let url = Url::parse(&host).and_then(|h| h.join(path)).map_err(|_| usage("invalid host"))?;
+    let url = Url::parse_with_params(url.as_str(), params).map_err(|_| usage("invalid host"))?;
+    let uri: Uri = url.as_str().parse().map_err(|_| usage("invalid host"))?;
+    let request = HyperRequest::builder().method(method).uri(uri).body(Body::empty());
+    let response = Client::new().request(request.map_err(|_| usage("invalid host"))?).await?;
+    let status = response.status();
+    let body = body::to_bytes(response.into_body()).await?;
+    let body = String::from_utf8_lossy(&body).to_string();
+    if status == StatusCode::ACCEPTED {
+      Ok(body)
+    } else {
+      Err(Error::Api(body))
+    }
+  }
+}
+
+fn usage(msg: &str) -> Error {
+  Error::Usage(format!("{msg}\n\n{USAGE}"))
+}
+
+#[cfg(test)]
+mod test {
+  use crate::api::request::LightCommand;
+
+  use super::{Cli, Command, PresetAction, QueryKind};
+
+  fn parse(args: &str) -> Cli {
+    Cli::parse(args.split(' ').filter(|a| !a.is_empty()).map(String::from)).unwrap()
+  }
+
+  #[test]
+  fn test_parse_commands() {
+    assert_eq!(parse("").command, Command::Run);
+    let cli = parse("run --config x.yml --set log.dir=/var/log --web-port 80");
+    assert_eq!(cli.config.as_deref(), Some("x.yml"));
+    let overrides = vec![("log.dir".into(), "/var/log".into()), ("web.port".into(), "80".into())];
+    assert_eq!(cli.overrides, overrides);
+    assert_eq!(parse("query structure").command, Command::Query(QueryKind::Structure));
+    assert_eq!(
+      parse("scene trigger Night").command,
+      Command::TriggerScene { name: "Night".into() }
+    );
+    assert_eq!(parse("scene toggle Night").command, Command::ToggleScene { name: "Night".into() });
+    let cli = parse("command ChangeState topic --value 0.5 --host http://pi:8088");
+    let payload = vec![(String::from("value"), String::from("0.5"))];
+    let expected =
+      Command::Command { command: LightCommand::ChangeState, topic: "topic".into(), payload };
+    assert_eq!(cli.command, expected);
+    assert_eq!(cli.host.as_deref(), Some("http://pi:8088"));
+    let save = PresetAction::Save { name: "Cozy".into(), topic: "zigbee2mqtt/Room/Hall".into() };
+    assert_eq!(parse("preset save Cozy zigbee2mqtt/Room/Hall").command, Command::Preset(save));
+    let clear = Command::ClearOverrides { topic: Some("zigbee2mqtt/Hall".into()) };
+    assert_eq!(parse("override clear zigbee2mqtt/Hall").command, clear);
+    assert_eq!(parse("override clear").command, Command::ClearOverrides { topic: None });
+    let update = Some((String::from("zigbee2mqtt/Motion"), String::from("{}")));
+    let explain = Command::Explain { name: "Welcome".into(), update, at: Some("2024".into()) };
+    assert_eq!(parse("scene explain Welcome zigbee2mqtt/Motion {} --at 2024").command, explain);
+    let captures = vec![String::from("a.jsonl"), String::from("b.jsonl")];
+    assert_eq!(parse("replay a.jsonl b.jsonl").command, Command::Replay { captures });
+  }
+
+  #[test]
+  fn test_parse_rejects_nonsense() {
+    assert!(Cli::parse(["command", "Explode", "topic"].map(String::from)).is_err());
+    assert!(Cli::parse(["query"].map(String::from)).is_err());
+    assert!(Cli::parse(["replay"].map(String::from)).is_err());
+    assert!(Cli::parse(["run", "--port"].map(String::from)).is_err());
+  }
+}
@@ -0,0 +1,60 @@
+use std::{
+  fmt::Debug,
+  sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Local};
+
+/// Source of the current time for everything time-dependent: scene triggers, dynamic brightness
+/// and sensor timestamps.
+pub trait Clock: Debug + Send + Sync {
+  fn now(&self) -> DateTime<Local>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+  pub fn shared() -> SharedClock {
+    Arc::new(SystemClock)
+  }
+}
+
+impl Clock for SystemClock {
+  fn now(&self) -> DateTime<Local> {
+    Local::now()
+  }
+}
+
+/// Stands still until told otherwise.  Clones share the time, so a test can keep one to move the
+/// time of the components it handed the others to.
+#[derive(Debug, Clone)]
+pub struct FakeClock {
+  now: Arc<Mutex<DateTime<Local>>>,
+}
+
+impl FakeClock {
+  pub fn new(start: DateTime<Local>) -> Self {
+    FakeClock { now: Arc::new(Mutex::new(start)) }
+  }
+
+  pub fn shared(&self) -> SharedClock {
+    Arc::new(self.clone())
+  }
+
+  pub fn set(&self, time: DateTime<Local>) {
+    *self.now.lock().unwrap() = time;
+  }
+
+  pub fn advance(&self, by: Duration) {
+    *self.now.lock().unwrap() += by;
+  }
+}
+
+impl Clock for FakeClock {
+  fn now(&self) -> DateTime<Local> {
+    *self.now.lock().unwrap()
+  }
+}
@@ -24,7 +24,7 @@ impl Scalar {
   }
 
   fn bounded(value: f64) -> Self {
-    Self(value.min(1f64).max(0f64))
+    Self(value.clamp(0f64, 1f64))
   }
 }
 
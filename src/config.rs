@@ -1,39 +1,295 @@
+use std::net::{IpAddr, SocketAddr};
+
+use guard::guard;
+use local_ip_address::local_ip;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 
-use crate::{controller::Controller, Result};
+use crate::{controller::Controller, Error, Result};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The configuration is layered: the file comes first, then `RUSTY_HOME_*` environment variables,
+/// then command-line flags.  Later layers override single keys such as `mosquitto.port`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct GlobalConfig {
   pub mosquitto: MosquittoConfig,
+  #[serde(default)]
   pub log: LogConfig,
   pub home: HomeConfig,
+  #[serde(default)]
+  pub web: WebConfig,
+  /// Captures all MQTT traffic if present.
+  #[serde(default)]
+  pub record: Option<RecordConfig>,
+  #[serde(skip)]
+  pub path: String,
+  /// Overrides on top of the file, in the order they were applied.
+  #[serde(skip)]
+  pub overrides: Vec<(String, String)>,
 }
 
 impl GlobalConfig {
+  pub const PATH: &'static str = "config/global.yml";
+  pub const ENV_PREFIX: &'static str = "RUSTY_HOME_";
+  /// Variable that points to the config file rather than overriding a key.
+  pub const ENV_PATH: &'static str = "RUSTY_HOME_CONFIG";
+  pub const KEYS: [&'static str; 10] = [
+    "mosquitto.ip",
+    "mosquitto.port",
+    "log.dir",
+    "log.format",
+    "home.dir",
+    "web.ip",
+    "web.port",
+    "record.dir",
+    "record.max_kb",
+    "record.keep",
+  ];
+  /// Settings without a default.
+  const REQUIRED: [&'static str; 2] = ["mosquitto.ip", "home.dir"];
+
   pub fn read() -> Result<GlobalConfig> {
-    let content = std::fs::read_to_string("config/global.yml")?;
-    let cfg: GlobalConfig = serde_yaml::from_str(&content).unwrap();
+    Self::read_from(Self::PATH)
+  }
+
+  pub fn read_from(path: &str) -> Result<GlobalConfig> {
+    Self::load(path, Self::env_overrides(std::env::vars()))
+  }
+
+  /// Reads the file at `path` if it exists and applies the overrides in order.
+  pub fn load(path: &str, overrides: Vec<(String, String)>) -> Result<GlobalConfig> {
+    let content = match std::fs::read_to_string(path) {
+      Ok(content) => Some(content),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        eprintln!("No config file at {path}.  Relying on environment and flags.");
+        None
+      }
+      Err(err) => return Err(err.into()),
+    };
+    let mut cfg = Self::from_layers(content.as_deref(), &overrides)?;
+    cfg.path = path.to_string();
+    cfg.overrides = overrides;
     Ok(cfg)
   }
 
+  fn from_layers(content: Option<&str>, overrides: &[(String, String)]) -> Result<GlobalConfig> {
+    let mut value = match content {
+      Some(content) => serde_yaml::from_str(content)?,
+      None => Value::Mapping(Mapping::new()),
+    };
+    if value.is_null() {
+      value = Value::Mapping(Mapping::new()); // An empty file.
+    }
+    for (key, raw) in overrides {
+      Self::apply(&mut value, key, raw)?;
+    }
+    Self::check_settings(&value)?;
+    serde_yaml::from_value(value)
+      .map_err(|err| Error::Config { key: String::new(), msg: err.to_string() })
+  }
+
+  fn apply(value: &mut Value, key: &str, raw: &str) -> Result<()> {
+    let error = |msg: &str| Error::Config { key: key.to_string(), msg: msg.to_string() };
+    if !Self::KEYS.contains(&key) {
+      return Err(error("unknown setting"));
+    }
+    let (section, field) = key.split_once('.').unwrap();
+    let parsed = serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    let root = value.as_mapping_mut().ok_or_else(|| error("config is not a mapping"))?;
+    let section = root
+      .entry(Value::String(section.to_string()))
+      .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if section.is_null() {
+      *section = Value::Mapping(Mapping::new());
+    }
+    let section = section.as_mapping_mut().ok_or_else(|| error("section is not a mapping"))?;
+    section.insert(Value::String(field.to_string()), parsed);
+    Ok(())
+  }
+
+  /// Checks every known setting on its own, so that an error names the offending key.
+  fn check_settings(value: &Value) -> Result<()> {
+    let error = |key: &str, msg: String| Error::Config { key: key.to_string(), msg };
+    for section in Self::REQUIRED.iter().filter_map(|key| key.split_once('.')).map(|(s, _)| s) {
+      if value.get(section).is_none_or(Value::is_null) {
+        return Err(error(section, String::from("missing section")));
+      }
+    }
+    for key in Self::KEYS {
+      let (section, field) = key.split_once('.').unwrap();
+      guard!(let Some(setting) = value.get(section).and_then(|s| s.get(field)) else {
+        if Self::REQUIRED.contains(&key) {
+          return Err(error(key, String::from("missing setting")));
+        }
+        continue;
+      });
+      let setting = setting.clone();
+      let checked = match key {
+        "mosquitto.port" | "web.port" => serde_yaml::from_value::<u16>(setting).map(drop),
+        "record.max_kb" => serde_yaml::from_value::<u64>(setting).map(drop),
+        "record.keep" => serde_yaml::from_value::<usize>(setting).map(drop),
+        _ => serde_yaml::from_value::<Option<String>>(setting).map(drop),
+      };
+      checked.map_err(|err| error(key, err.to_string()))?;
+    }
+    Ok(())
+  }
+
+  /// Maps variables such as `RUSTY_HOME_MOSQUITTO_PORT` to keys such as `mosquitto.port`.
+  pub fn env_overrides<I: IntoIterator<Item = (String, String)>>(vars: I) -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = vars
+      .into_iter()
+      .filter(|(name, _)| name != Self::ENV_PATH)
+      .filter_map(|(name, value)| {
+        let key = name.strip_prefix(Self::ENV_PREFIX)?.to_lowercase();
+        let key = key.replacen('_', ".", 1);
+        if !Self::KEYS.contains(&key.as_str()) {
+          eprintln!("Ignoring {name}, which sets no known setting.");
+          return None;
+        }
+        Some((key, value))
+      })
+      .collect();
+    overrides.sort(); // Environment order is arbitrary.
+    overrides
+  }
+
   pub async fn try_into(self) -> Result<Controller> {
     Controller::new(self).await
   }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MosquittoConfig {
   pub ip: String,
+  #[serde(default = "MosquittoConfig::default_port")]
   pub port: u16,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl MosquittoConfig {
+  fn default_port() -> u16 {
+    1883
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct LogConfig {
   pub dir: String,
   pub format: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Default for LogConfig {
+  fn default() -> Self {
+    LogConfig { dir: String::from("log/"), format: String::from("plain") }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct HomeConfig {
   pub dir: String,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WebConfig {
+  /// Address to bind to.  Defaults to the machine's address in the local network.
+  pub ip: Option<String>,
+  pub port: u16,
+}
+
+impl Default for WebConfig {
+  fn default() -> Self {
+    WebConfig { ip: None, port: 8088 }
+  }
+}
+
+impl WebConfig {
+  pub fn address(&self) -> Result<SocketAddr> {
+    let ip = match &self.ip {
+      Some(ip) => ip.parse::<IpAddr>().map_err(|_| Error::Config {
+        key: String::from("web.ip"),
+        msg: format!("{ip} is not an IP address"),
+      })?,
+      None => local_ip().map_err(|_| Error::Config {
+        key: String::from("web.ip"),
+        msg: String::from("cannot determine the local address"),
+      })?,
+    };
+    Ok(SocketAddr::new(ip, self.port))
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RecordConfig {
+  pub dir: String,
+  /// Size in kilobytes after which the capture file rotates.
+  pub max_kb: u64,
+  /// Number of rotated files to keep.
+  pub keep: usize,
+}
+
+impl Default for RecordConfig {
+  fn default() -> Self {
+    RecordConfig { dir: String::from("captures/"), max_kb: 10 * 1024, keep: 5 }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::Error;
+
+  use super::GlobalConfig;
+
+  const FILE: &str = "mosquitto:\n  ip: \"10.0.0.2\"\nhome:\n  dir: home.yml\n";
+
+  fn overrides(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+  }
+
+  #[test]
+  fn test_defaults() {
+    let cfg = GlobalConfig::from_layers(Some(FILE), &[]).unwrap();
+    assert_eq!(cfg.mosquitto.port, 1883);
+    assert_eq!(cfg.web.port, 8088);
+    assert_eq!(cfg.log.dir, "log/");
+  }
+
+  #[test]
+  fn test_layers_override_in_order() {
+    let env = GlobalConfig::env_overrides(overrides(&[
+      ("RUSTY_HOME_MOSQUITTO_PORT", "4242"),
+      ("RUSTY_HOME_CONFIG", "elsewhere.yml"),
+      ("RUSTY_HOME_MOSQUITTO_PASSWORD", "secret"),
+      ("PATH", "/bin"),
+    ]));
+    assert_eq!(env, overrides(&[("mosquitto.port", "4242")]));
+    let flags = overrides(&[("mosquitto.port", "1234"), ("web.ip", "127.0.0.1")]);
+    let cfg = GlobalConfig::from_layers(Some(FILE), &[env, flags].concat()).unwrap();
+    assert_eq!(cfg.mosquitto.port, 1234);
+    assert_eq!(cfg.web.ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(cfg.record, None);
+    let cfg =
+      GlobalConfig::from_layers(None, &overrides(&[("mosquitto.ip", "x"), ("home.dir", "h")]));
+    assert_eq!(cfg.unwrap().mosquitto.ip, "x");
+    let cfg = GlobalConfig::from_layers(Some(FILE), &overrides(&[("record.keep", "2")])).unwrap();
+    assert_eq!(cfg.record.map(|r| (r.dir, r.keep)), Some((String::from("captures/"), 2)));
+  }
+
+  #[test]
+  fn test_errors_name_the_key() {
+    let key_of = |content: Option<&str>, pairs: &[(&str, &str)]| match GlobalConfig::from_layers(
+      content,
+      &overrides(pairs),
+    ) {
+      Err(Error::Config { key, .. }) => key,
+      other => panic!("Expected config error, got {other:?}"),
+    };
+    assert_eq!(key_of(Some(FILE), &[("mosquitto.port", "many")]), "mosquitto.port");
+    assert_eq!(key_of(Some(FILE), &[("mosquito.ip", "x")]), "mosquito.ip");
+    assert_eq!(key_of(Some("home:\n  dir: h\n"), &[]), "mosquitto");
+    assert_eq!(key_of(Some("mosquitto: {}\nhome:\n  dir: h\n"), &[]), "mosquitto.ip");
+    assert_eq!(key_of(Some(FILE), &[("record.keep", "-1")]), "record.keep");
+    assert_eq!(key_of(Some(&format!("{FILE}web:\n  ip: [1]\n")), &[]), "web.ip");
+  }
+}
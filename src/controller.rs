@@ -2,14 +2,13 @@ use std::rc::Rc;
 use std::thread::sleep;
 use std::time::Duration;
 
+use crate::api::request::{General, Request};
 use crate::api::topic::TopicMode;
 use crate::api::traits::{Addressable, DeviceCollection};
-use crate::api::{
-  request::{General, Request},
-  traits::ReadWriteHome,
-};
 use crate::home::Home;
 use crate::scenes::manager::{SceneEvent, SceneManager};
+use crate::scenes::schedule::RunLog;
+use crate::watcher::ConfigWatcher;
 use crate::web_server::WebServer;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::Mutex;
@@ -17,8 +16,9 @@ use tokio::{join, pin, select};
 
 use crate::{
   api::Executor,
+  clock::{SharedClock, SystemClock},
   config::GlobalConfig,
-  mqtt::{self, MqttReceiver, ProtectedClient},
+  mqtt::{self, recorder::Recorder, MqttReceiver, ProtectedClient},
   Error,
 };
 
@@ -28,31 +28,35 @@ pub struct Controller {
   executor: Executor,
   web_server: WebServer,
   scene_manager: SceneManager,
+  watcher: ConfigWatcher,
 }
 
 impl Controller {
   pub async fn new(config: GlobalConfig) -> Result<Self, Error> {
-    let home = Home::read(&config.home.dir);
+    let clock = SystemClock::shared();
+    let home = Home::load(&config.home.dir, clock.now())?;
     let (q_send, q_recv) = unbounded_channel();
     let (scene_send, scene_recv) = unbounded_channel();
     let (client, mqtt_receiver) =
-      Self::setup_client(&config, &home, q_send.clone(), scene_send.clone()).await?;
+      Self::setup_client(&config, &home, q_send.clone(), scene_send.clone(), clock.clone()).await?;
 
     let home = Rc::new(Mutex::new(home));
-    let executor = Executor::new(q_recv, scene_send, client, home.clone());
-    let web_server = WebServer::new(q_send.clone());
-    let scene_manager = SceneManager::new(home, q_send.clone(), scene_recv);
+    let watcher = ConfigWatcher::new(config.path.clone(), config.clone(), q_send.clone());
+    let executor = Executor::new(q_recv, scene_send, client, home.clone(), clock.clone())
+      .with_home_path(&config.home.dir, watcher.own_writes());
+    let web_server = WebServer::new(q_send.clone(), config.web.address()?);
+    let scene_manager = SceneManager::new(home, q_send.clone(), scene_recv, clock)
+      .with_run_log(RunLog::path(&config.home.dir));
 
-    Self::startup(q_send, &config.home.dir);
+    Self::startup(q_send);
 
-    Ok(Self { mqtt_receiver, executor, web_server, scene_manager })
+    Ok(Self { mqtt_receiver, executor, web_server, scene_manager, watcher })
   }
 
-  pub fn startup(queue: UnboundedSender<Request>, home_path: &str) {
-    let s = home_path.to_string();
+  pub fn startup(queue: UnboundedSender<Request>) {
     ctrlc::set_handler(move || {
       eprintln!("Detected shutdown. Initiating procedure.");
-      let req = Request::General(General::Shutdown { home_path: s.clone() });
+      let req = Request::General(General::Shutdown);
       queue.send(req).expect("Cannot send.");
       sleep(Duration::from_secs(1));
       std::process::exit(0);
@@ -65,35 +69,45 @@ impl Controller {
     home: &Home,
     queue: UnboundedSender<Request>,
     updates: UnboundedSender<SceneEvent>,
+    clock: SharedClock,
   ) -> Result<(ProtectedClient, MqttReceiver), Error> {
     let (client, receiver) =
       mqtt::setup_client(&config.mosquitto.ip, config.mosquitto.port, queue.clone(), updates)
         .await?;
-    {
-      let client = client.lock().await;
-      let subscribe = home.flatten_devices().into_iter().map(|d| d.topic(TopicMode::Blank));
-      let sub = client.subscribe_to_all(subscribe);
-      let que = client.query_states(home.flatten_devices(), queue);
-      join!(sub, que);
+    if let Some(record) = &config.record {
+      println!("Capturing MQTT traffic in {}.", record.dir);
+      client.lock().await.record_to(Recorder::new(record, clock)?.shared());
     }
+    Self::subscribe(&client, home, queue).await;
     Ok((client, receiver))
   }
 
+  /// Subscribes to all devices of the home and queries their current state.
+  pub async fn subscribe(client: &ProtectedClient, home: &Home, queue: UnboundedSender<Request>) {
+    let client = client.lock().await;
+    let subscribe = home.flatten_devices().into_iter().map(|d| d.topic(TopicMode::Blank));
+    let sub = client.subscribe_to_all(subscribe);
+    let que = client.query_states(home.flatten_devices(), queue);
+    join!(sub, que);
+  }
+
   pub async fn run(self) -> ! {
     println!("Running controller.");
-    let Controller { mqtt_receiver, executor, web_server, scene_manager, .. } = self;
+    let Controller { mqtt_receiver, executor, web_server, scene_manager, watcher } = self;
     let mqtt_recv = mqtt_receiver.run();
     let requ_exec = executor.run();
     let web_serve = web_server.run();
     let scene_mgr = scene_manager.run();
+    let cfg_watch = watcher.run();
 
-    pin!(mqtt_recv, requ_exec, web_serve, scene_mgr);
+    pin!(mqtt_recv, requ_exec, web_serve, scene_mgr, cfg_watch);
 
     select! {
       err = mqtt_recv => eprintln!("{:?}", err.unwrap_err()),
       err = requ_exec => eprintln!("{:?}", err.unwrap_err()),
       err = web_serve => eprintln!("{:?}", err.unwrap_err()),
       err = scene_mgr => eprintln!("{:?}", err.unwrap_err()),
+      err = cfg_watch => eprintln!("{:?}", err.unwrap_err()),
     }; // Todo: Handle if one of them returned.  Re-use all but the crashed one.
     unreachable!()
   }
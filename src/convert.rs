@@ -171,6 +171,12 @@ pub struct StateFromMqtt {
   pub humidity: Option<f64>,
   #[serde(default)]
   pub occupancy: Option<bool>,
+  #[serde(default)]
+  pub illuminance: Option<f64>,
+  #[serde(default)]
+  pub battery: Option<f64>,
+  #[serde(default)]
+  pub linkquality: Option<f64>,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq)]
@@ -216,12 +222,14 @@ pub struct StateToMqtt {
   brightness: Tertiary<Val>,
   color: Option<MqttColorOut>,
   state: Tertiary<MqttOnOff>,
+  color_temp: Option<u16>,
   transition: Option<i8>,
   brightness_move: Option<i8>,
   battery: Option<()>,
   temperature: Option<f64>,
   humidity: Option<f64>,
   occupancy: Option<bool>,
+  illuminance: Option<f64>,
   time: Option<i64>,
 }
 
@@ -265,6 +273,10 @@ impl StateToMqtt {
       obj.as_object_mut().unwrap().insert(String::from("state"), json);
     }
 
+    if let Some(v) = self.color_temp {
+      obj.as_object_mut().unwrap().insert(String::from("color_temp"), json!(v));
+    }
+
     if let Some(v) = self.transition {
       obj.as_object_mut().unwrap().insert(String::from("transition"), json!(v));
     }
@@ -289,6 +301,10 @@ impl StateToMqtt {
       obj.as_object_mut().unwrap().insert(String::from("occupancy"), json!(v));
     }
 
+    if let Some(v) = self.illuminance {
+      obj.as_object_mut().unwrap().insert(String::from("illuminance"), json!(v));
+    }
+
     if let Some(t) = self.time {
       obj.as_object_mut().unwrap().insert(String::from("time"), json!(t));
     }
@@ -337,6 +353,16 @@ impl StateToMqtt {
     self
   }
 
+  pub fn with_transition_of(mut self, seconds: i8) -> Self {
+    self.transition = Some(seconds);
+    self
+  }
+
+  pub fn with_color_temp(mut self, mireds: u16) -> Self {
+    self.color_temp = Some(mireds);
+    self
+  }
+
   pub fn with_battery_query(mut self) -> Self {
     self.battery = Some(());
     self
@@ -357,6 +383,11 @@ impl StateToMqtt {
     self
   }
 
+  pub fn with_illuminance(mut self, lux: f64) -> Self {
+    self.illuminance = Some(lux);
+    self
+  }
+
   pub fn with_time<T: TimeZone>(mut self, time: DateTime<T>) -> Self {
     self.time = Some(time.timestamp());
     self
@@ -376,11 +407,41 @@ impl MqttColorOut {
   }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct RestApiPayload {
   pub topic: Option<Topic>,
   pub val: Option<Val>,
   pub hue: Option<Hue>,
   pub sat: Option<Sat>,
   pub name: Option<String>,
+  pub on: Option<bool>,
+  /// In mireds.
+  pub color_temp: Option<u16>,
+  /// In seconds.
+  pub transition: Option<i8>,
+  pub origin: Origin,
+}
+
+impl RestApiPayload {
+  /// A payload that changes nothing by itself.  Whoever builds one says where it comes from.
+  pub fn new(origin: Origin) -> Self {
+    RestApiPayload {
+      topic: None,
+      val: None,
+      hue: None,
+      sat: None,
+      name: None,
+      on: None,
+      color_temp: None,
+      transition: None,
+      origin,
+    }
+  }
+}
+
+/// Who asked for a light command.  Manual commands pause scenes on the lights they touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+  Manual,
+  Scene,
 }
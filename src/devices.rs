@@ -2,7 +2,10 @@ pub mod light;
 pub mod remote;
 pub mod sensor;
 
-pub use light::{Light, LightGroup};
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Local};
+pub use light::{Light, LightGroup, LightSnapshot, LightState};
 pub use remote::Remote;
 pub use sensor::Sensor;
 use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize};
@@ -20,10 +23,11 @@ pub trait DeviceTrait: Addressable {
   fn model(&self) -> DeviceModel;
   fn name(&self) -> &str;
   fn room(&self) -> &str;
-  fn update_state(&mut self, state: StateFromMqtt);
+  fn update_state(&mut self, state: StateFromMqtt, now: DateTime<Local>);
   fn query_state(&self) -> StateToMqtt; // todo: rest payload
   fn query_update(&self) -> StateToMqtt;
   fn query_history(&self) -> Vec<StateToMqtt>;
+  fn health(&self) -> &DeviceHealth;
   fn physical_kind(&self) -> DeviceKind {
     self.model().kind()
   }
@@ -38,6 +42,93 @@ pub enum Capability {
   Humidity,
   Temperature,
   Occupancy,
+  Illuminance,
+}
+
+impl Capability {
+  /// The field under which zigbee2mqtt reports this capability.
+  pub fn field(&self) -> &'static str {
+    match self {
+      Capability::Brightness => "brightness",
+      Capability::Color => "color",
+      Capability::Transition => "transition",
+      Capability::State => "state",
+      Capability::Humidity => "humidity",
+      Capability::Temperature => "temperature",
+      Capability::Occupancy => "occupancy",
+      Capability::Illuminance => "illuminance",
+    }
+  }
+}
+
+/// How a device is doing, judging by what it reports besides its state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DeviceHealth {
+  /// In percent, for devices on battery.
+  pub battery: Option<f64>,
+  /// Signal strength from 0 to 255.
+  pub linkquality: Option<f64>,
+  /// When the home last heard from the device.
+  pub last_seen: Option<DateTime<Local>>,
+}
+
+impl DeviceHealth {
+  pub fn update(&mut self, state: &StateFromMqtt, now: DateTime<Local>) {
+    self.battery = state.battery.or(self.battery);
+    self.linkquality = state.linkquality.or(self.linkquality);
+    self.last_seen = Some(now);
+  }
+
+  /// What is wrong with the device according to `limits`.  A device that never reported counts
+  /// as silent since `since`.
+  pub fn issues(
+    &self,
+    limits: &HealthLimits,
+    since: DateTime<Local>,
+    now: DateTime<Local>,
+  ) -> Vec<HealthIssue> {
+    let mut issues = vec![];
+    if self.battery.is_some_and(|b| b <= limits.low_battery) {
+      issues.push(HealthIssue::LowBattery);
+    }
+    let silent = now - self.last_seen.unwrap_or(since);
+    if limits.unseen_hours > 0 && silent >= Duration::hours(limits.unseen_hours) {
+      issues.push(HealthIssue::Unseen);
+    }
+    issues
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum HealthIssue {
+  LowBattery,
+  Unseen,
+}
+
+impl Display for HealthIssue {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      HealthIssue::LowBattery => write!(f, "runs low on battery"),
+      HealthIssue::Unseen => write!(f, "has not been heard from for too long"),
+    }
+  }
+}
+
+/// When a device counts as unhealthy.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HealthLimits {
+  /// Battery level in percent at or below which the battery counts as low.
+  pub low_battery: f64,
+  /// Hours without a message after which a device counts as gone.  Zero disables the check, which
+  /// suits homes whose lights only report when they change.
+  pub unseen_hours: i64,
+}
+
+impl Default for HealthLimits {
+  fn default() -> Self {
+    HealthLimits { low_battery: 20.0, unseen_hours: 0 }
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -84,6 +175,17 @@ impl Device {
       Device::Light(_) | Device::Sensor(_) => None,
     }
   }
+  pub fn inherit_state(&mut self, previous: &Device) {
+    if self.model() != previous.model() {
+      return;
+    }
+    match (self, previous) {
+      (Device::Light(l), Device::Light(p)) => l.inherit_state(p),
+      (Device::Sensor(s), Device::Sensor(p)) => s.inherit_state(p),
+      (Device::Remote(r), Device::Remote(p)) => r.inherit_state(p),
+      _ => {}
+    }
+  }
   fn inner(&self) -> &dyn DeviceTrait {
     match self {
       Device::Light(l) => l,
@@ -135,8 +237,8 @@ impl DeviceTrait for Device {
     self.inner().room()
   }
 
-  fn update_state(&mut self, state: StateFromMqtt) {
-    self.inner_mut().update_state(state);
+  fn update_state(&mut self, state: StateFromMqtt, now: DateTime<Local>) {
+    self.inner_mut().update_state(state, now);
   }
 
   fn query_state(&self) -> StateToMqtt {
@@ -150,6 +252,10 @@ impl DeviceTrait for Device {
   fn query_history(&self) -> Vec<StateToMqtt> {
     self.inner().query_history()
   }
+
+  fn health(&self) -> &DeviceHealth {
+    self.inner().health()
+  }
 }
 
 impl<T: DeviceTrait> Addressable for T {
@@ -173,7 +279,14 @@ pub enum DeviceModel {
   IkeaMultiButton,
   IkeaDimmer,
   IkeaMotion,
+  HueMotion,
   HueButton,
+  HueDimmer,
+  IkeaStyrbar,
+  IkeaRodret,
+  IkeaShortcut,
+  AqaraOpple,
+  TuyaSceneSwitch,
 }
 
 impl DeviceModel {
@@ -186,19 +299,33 @@ impl DeviceModel {
       DeviceModel::IkeaMultiButton => DeviceKind::Remote,
       DeviceModel::IkeaDimmer => DeviceKind::Remote,
       DeviceModel::IkeaMotion => DeviceKind::Sensor,
-      DeviceModel::HueButton => DeviceKind::Remote,
+      DeviceModel::HueMotion => DeviceKind::Sensor,
+      DeviceModel::HueButton
+      | DeviceModel::HueDimmer
+      | DeviceModel::IkeaStyrbar
+      | DeviceModel::IkeaRodret
+      | DeviceModel::IkeaShortcut
+      | DeviceModel::AqaraOpple
+      | DeviceModel::TuyaSceneSwitch => DeviceKind::Remote,
     }
   }
 
   pub fn vendor(&self) -> Vendor {
     match self {
-      DeviceModel::TuyaHumidity => Vendor::Tuya,
+      DeviceModel::TuyaHumidity | DeviceModel::TuyaSceneSwitch => Vendor::Tuya,
       DeviceModel::IkeaOutlet
       | DeviceModel::IkeaDimmable
       | DeviceModel::IkeaMultiButton
       | DeviceModel::IkeaDimmer
-      | DeviceModel::IkeaMotion => Vendor::Ikea,
-      DeviceModel::HueColor | DeviceModel::HueButton => Vendor::Philips,
+      | DeviceModel::IkeaMotion
+      | DeviceModel::IkeaStyrbar
+      | DeviceModel::IkeaRodret
+      | DeviceModel::IkeaShortcut => Vendor::Ikea,
+      DeviceModel::HueColor
+      | DeviceModel::HueMotion
+      | DeviceModel::HueButton
+      | DeviceModel::HueDimmer => Vendor::Philips,
+      DeviceModel::AqaraOpple => Vendor::Aqara,
     }
   }
 
@@ -209,9 +336,18 @@ impl DeviceModel {
       DeviceModel::IkeaDimmable => vec![Capability::State, Capability::Brightness],
       DeviceModel::HueColor => vec![Capability::State, Capability::Brightness, Capability::Color],
       DeviceModel::IkeaMotion => vec![Capability::Occupancy],
-      DeviceModel::IkeaMultiButton => vec![],
-      DeviceModel::IkeaDimmer => vec![],
-      DeviceModel::HueButton => vec![],
+      DeviceModel::HueMotion => {
+        vec![Capability::Occupancy, Capability::Illuminance, Capability::Temperature]
+      }
+      DeviceModel::IkeaMultiButton
+      | DeviceModel::IkeaDimmer
+      | DeviceModel::HueButton
+      | DeviceModel::HueDimmer
+      | DeviceModel::IkeaStyrbar
+      | DeviceModel::IkeaRodret
+      | DeviceModel::IkeaShortcut
+      | DeviceModel::AqaraOpple
+      | DeviceModel::TuyaSceneSwitch => vec![],
     }
   }
 
@@ -225,6 +361,7 @@ pub enum Vendor {
   Ikea,
   Philips,
   Tuya,
+  Aqara,
 }
 
 pub fn serialize_light_sequence<S>(val: &Vec<Device>, serializer: S) -> Result<S::Ok, S::Error>
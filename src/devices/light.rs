@@ -1,10 +1,11 @@
+use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 
 use crate::api::topic::{DeviceKind, Topic, TopicMode};
 use crate::api::traits::{Addressable, DeviceCollection, EffectiveLight, EffectiveLightCollection};
 use crate::convert::{HsvColor, RestApiPayload, StateFromMqtt, StateToMqtt, Val};
 
-use super::{Capability, Device, DeviceModel, DeviceTrait};
+use super::{Capability, Device, DeviceHealth, DeviceModel, DeviceTrait};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Light {
@@ -15,6 +16,8 @@ pub struct Light {
   pseudo_kind: Option<DeviceKind>,
   #[serde(skip)]
   state: LightState,
+  #[serde(skip)]
+  health: DeviceHealth,
 }
 
 impl DeviceTrait for Light {
@@ -34,7 +37,8 @@ impl DeviceTrait for Light {
     &self.room
   }
 
-  fn update_state(&mut self, state: StateFromMqtt) {
+  fn update_state(&mut self, state: StateFromMqtt, now: DateTime<Local>) {
+    self.health.update(&state, now);
     self.state.with_mqtt_state(self.model(), state);
   }
 
@@ -49,9 +53,60 @@ impl DeviceTrait for Light {
   fn query_history(&self) -> Vec<StateToMqtt> {
     vec![self.query_state()]
   }
+
+  fn health(&self) -> &DeviceHealth {
+    &self.health
+  }
+}
+
+impl Light {
+  pub fn inherit_state(&mut self, previous: &Light) {
+    self.state = previous.state.clone();
+    self.health = previous.health;
+  }
+
+  pub fn state(&self) -> &LightState {
+    &self.state
+  }
+
+  /// Brings the light into `state`, sending only what differs from its current one.
+  fn restore_state(&mut self, state: &LightState) -> Vec<(Topic, StateToMqtt)> {
+    if &self.state == state {
+      return vec![];
+    }
+    if !state.on {
+      return self.turn_off(); // The color of a light that is off does not matter.
+    }
+    let mut mqtt = StateToMqtt::empty().with_state(Some(true));
+    if self.state.color != state.color {
+      if self.model.capable_of(Capability::Color) {
+        mqtt = mqtt.with_color_change(&state.color);
+      } else if self.model.capable_of(Capability::Brightness) {
+        mqtt = mqtt.with_value(Some(state.color.val()));
+      }
+    }
+    self.state = state.clone();
+    vec![(self.topic(TopicMode::Set), mqtt.with_transition())]
+  }
 }
 
 impl EffectiveLight for Light {
+  fn light_states(&self) -> Vec<&LightState> {
+    vec![&self.state]
+  }
+
+  fn snapshot(&self) -> Vec<LightSnapshot> {
+    vec![LightSnapshot { light: self.topic(TopicMode::Blank), state: self.state.clone() }]
+  }
+
+  fn restore(&mut self, snapshot: &[LightSnapshot]) -> Vec<(Topic, StateToMqtt)> {
+    let topic = self.topic(TopicMode::Blank);
+    match snapshot.iter().find(|s| s.light == topic) {
+      Some(saved) => self.restore_state(&saved.state),
+      None => vec![],
+    }
+  }
+
   fn turn_on(&mut self, brightness: Option<Val>) -> Vec<(Topic, StateToMqtt)> {
     if self.state.on {
       return vec![];
@@ -112,18 +167,30 @@ impl EffectiveLight for Light {
   }
 
   fn change_state(&mut self, payload: RestApiPayload) -> Vec<(Topic, StateToMqtt)> {
-    let mqtt = if payload.hue.is_some() {
+    let mut mqtt = if let Some(hue) = payload.hue {
       assert!(payload.sat.is_some());
       assert!(payload.val.is_some());
-      self.state.color =
-        HsvColor::new(payload.hue.unwrap(), payload.sat.unwrap(), payload.val.unwrap());
-      StateToMqtt::empty().with_color_change(&self.state.color).with_transition()
+      self.state.color = HsvColor::new(hue, payload.sat.unwrap(), payload.val.unwrap());
+      StateToMqtt::empty().with_color_change(&self.state.color)
     } else if let Some(val) = payload.val {
       self.state.color.with_val(val);
-      StateToMqtt::empty().with_value(Some(val)).with_transition()
+      StateToMqtt::empty().with_value(Some(val))
+    } else if payload.on.is_some() || payload.color_temp.is_some() {
+      StateToMqtt::empty()
     } else {
       return vec![];
     };
+    if let Some(on) = payload.on {
+      self.state.on = on;
+      mqtt = mqtt.with_state(Some(on));
+    }
+    if let (Some(mireds), true) = (payload.color_temp, self.model.capable_of(Capability::Color)) {
+      mqtt = mqtt.with_color_temp(mireds);
+    }
+    mqtt = match payload.transition {
+      Some(seconds) => mqtt.with_transition_of(seconds),
+      None => mqtt.with_transition(),
+    };
     vec![(self.topic(TopicMode::Set), mqtt)]
   }
 }
@@ -170,7 +237,7 @@ impl EffectiveLightCollection for LightGroup {
     if let Some(res) = self.atomics.iter().find(|l| &l.topic(topic.mode()) == topic) {
       return Some(res.as_light().unwrap());
     }
-    self.subgroups.iter().filter_map(|grp| grp.find_effective_light(topic)).last()
+    self.subgroups.iter().filter_map(|grp| grp.find_effective_light(topic)).next_back()
   }
 
   fn find_effective_light_mut(&mut self, topic: &Topic) -> Option<&mut dyn EffectiveLight> {
@@ -184,6 +251,13 @@ impl EffectiveLightCollection for LightGroup {
   }
 }
 
+/// The state of a single light at some point, to restore it later.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LightSnapshot {
+  pub light: Topic,
+  pub state: LightState,
+}
+
 #[allow(missing_copy_implementations)] // Avoid accidental copying.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 pub struct LightState {
@@ -1,90 +1,452 @@
-use std::collections::HashMap;
+use std::{
+  collections::HashMap,
+  fmt::{Display, Formatter},
+  str::FromStr,
+};
 
-use crate::{api::request::LightCommand, convert::StateFromMqtt, convert::StateToMqtt};
-use serde::{Deserialize, Serialize};
+use crate::{
+  api::request::{LightCommand, Request, SceneCommand},
+  convert::{Origin, RestApiPayload, StateFromMqtt, StateToMqtt},
+  scenes::scene::TargetState,
+  Result,
+};
+use chrono::{DateTime, Duration, Local};
+use guard::guard;
+use serde::{
+  de::{
+    self,
+    value::{EnumAccessDeserializer, SeqAccessDeserializer},
+    DeserializeOwned, EnumAccess, IntoDeserializer, SeqAccess, Visitor,
+  },
+  Deserialize, Deserializer, Serialize,
+};
+use serde_json::Value as JsonValue;
 
 use crate::api::topic::{DeviceKind, Topic};
 
-use super::{DeviceModel, DeviceTrait};
+use super::{DeviceHealth, DeviceModel, DeviceTrait};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
-#[serde(untagged)]
+/// What a remote reports in the `action` field of its messages, e.g. `on`, `arrow_left_hold` or
+/// `button_2_double`.  Every model sends a part of this vocabulary, see `suits`, and the same name
+/// means the same button on every model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub enum RemoteButton {
-  IkeaMulti(IkeaMulti),
-  IkeaDimmer(IkeaDimmer),
-  HueButton(HueButton),
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
-pub enum IkeaMulti {
-  #[serde(rename = "toggle")]
+  On,
+  Off,
   Toggle,
-  #[serde(rename = "arrow_left_click")]
+  BriMoveUp,
+  BriMoveDown,
+  BriMoveStop,
+  BriUpClick,
+  BriUpHold,
+  BriUpRelease,
+  BriDownClick,
+  BriDownHold,
+  BriDownRelease,
   ArrLeftClick,
-  #[serde(rename = "arrow_left_hold")]
   ArrLeftHold,
-  #[serde(rename = "arrow_left_release")]
   ArrLeftRelease,
-  #[serde(rename = "arrow_right_click")]
   ArrRightClick,
-  #[serde(rename = "arrow_right_hold")]
   ArrRightHold,
-  #[serde(rename = "arrow_right_release")]
   ArrRightRelease,
-  #[serde(rename = "brightness_down_click")]
-  BriDownClick,
-  #[serde(rename = "brightness_down_hold")]
-  BriDownHold,
-  #[serde(rename = "brightness_down_release")]
-  BriDownRelease,
-  #[serde(rename = "brightness_up_click")]
-  BriUpClick,
-  #[serde(rename = "brightness_up_hold")]
-  BriUpHold,
-  #[serde(rename = "brightness_up_release")]
-  BriUpRelease,
+  SkipBack,
+  SkipForward,
+  Press,
+  Hold,
+  Release,
+  /// A key of the Hue dimmer switch, e.g. `up_hold_release`.
+  HueKey(HueKey, HuePhase),
+  /// A numbered button of an Aqara Opple switch, e.g. `button_3_single`.
+  Opple(u8, Click),
+  /// A numbered button of a Tuya scene switch, e.g. `2_double`.
+  Tuya(u8, Click),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
-pub enum IkeaDimmer {
-  #[serde(rename = "on")]
-  On,
-  #[serde(rename = "off")]
-  Off,
-  #[serde(rename = "brightness_move_up")]
-  BriMoveUp,
-  #[serde(rename = "brightness_move_down")]
-  BriMoveDown,
-  #[serde(rename = "brightness_stop")]
-  BriMoveStop,
+impl RemoteButton {
+  const NAMED: [(RemoteButton, &'static str); 23] = [
+    (RemoteButton::On, "on"),
+    (RemoteButton::Off, "off"),
+    (RemoteButton::Toggle, "toggle"),
+    (RemoteButton::BriMoveUp, "brightness_move_up"),
+    (RemoteButton::BriMoveDown, "brightness_move_down"),
+    (RemoteButton::BriMoveStop, "brightness_stop"),
+    (RemoteButton::BriUpClick, "brightness_up_click"),
+    (RemoteButton::BriUpHold, "brightness_up_hold"),
+    (RemoteButton::BriUpRelease, "brightness_up_release"),
+    (RemoteButton::BriDownClick, "brightness_down_click"),
+    (RemoteButton::BriDownHold, "brightness_down_hold"),
+    (RemoteButton::BriDownRelease, "brightness_down_release"),
+    (RemoteButton::ArrLeftClick, "arrow_left_click"),
+    (RemoteButton::ArrLeftHold, "arrow_left_hold"),
+    (RemoteButton::ArrLeftRelease, "arrow_left_release"),
+    (RemoteButton::ArrRightClick, "arrow_right_click"),
+    (RemoteButton::ArrRightHold, "arrow_right_hold"),
+    (RemoteButton::ArrRightRelease, "arrow_right_release"),
+    (RemoteButton::SkipBack, "skip_backward"),
+    (RemoteButton::SkipForward, "skip_forward"),
+    (RemoteButton::Press, "press"),
+    (RemoteButton::Hold, "hold"),
+    (RemoteButton::Release, "release"),
+  ];
+
+  /// Reads the action a remote of the given model reported, if the model sends it.
+  pub fn parse(action: &str, model: DeviceModel) -> Option<RemoteButton> {
+    action.parse().ok().filter(|b: &RemoteButton| b.suits(model))
+  }
+
+  /// Whether a remote of the given model can send this button.
+  pub fn suits(&self, model: DeviceModel) -> bool {
+    use RemoteButton::*;
+    match model {
+      DeviceModel::IkeaMultiButton => matches!(
+        self,
+        Toggle
+          | BriUpClick
+          | BriUpHold
+          | BriUpRelease
+          | BriDownClick
+          | BriDownHold
+          | BriDownRelease
+          | ArrLeftClick
+          | ArrLeftHold
+          | ArrLeftRelease
+          | ArrRightClick
+          | ArrRightHold
+          | ArrRightRelease
+      ),
+      DeviceModel::IkeaDimmer | DeviceModel::IkeaRodret => {
+        matches!(self, On | Off | BriMoveUp | BriMoveDown | BriMoveStop)
+      }
+      DeviceModel::IkeaStyrbar => matches!(
+        self,
+        On | Off
+          | BriMoveUp
+          | BriMoveDown
+          | BriMoveStop
+          | ArrLeftClick
+          | ArrLeftHold
+          | ArrLeftRelease
+          | ArrRightClick
+          | ArrRightHold
+          | ArrRightRelease
+      ),
+      DeviceModel::IkeaShortcut => matches!(self, On | BriMoveUp | BriMoveStop),
+      DeviceModel::HueButton => {
+        matches!(self, On | Off | SkipBack | SkipForward | Press | Hold | Release)
+      }
+      DeviceModel::HueDimmer => matches!(self, HueKey(..)),
+      DeviceModel::AqaraOpple => matches!(self, Opple(1..=6, _)),
+      DeviceModel::TuyaSceneSwitch => {
+        matches!(self, Tuya(1..=4, Click::Single | Click::Double | Click::Hold))
+      }
+      _ => false,
+    }
+  }
+
+  /// The button the remote sends when this one is let go after holding it, if it can be held.
+  pub fn release(&self) -> Option<RemoteButton> {
+    use RemoteButton::*;
+    match *self {
+      ArrLeftHold => Some(ArrLeftRelease),
+      ArrRightHold => Some(ArrRightRelease),
+      BriDownHold => Some(BriDownRelease),
+      BriUpHold => Some(BriUpRelease),
+      BriMoveUp | BriMoveDown => Some(BriMoveStop),
+      Hold => Some(Release),
+      HueKey(key, HuePhase::Hold) => Some(HueKey(key, HuePhase::HoldRelease)),
+      Opple(number, Click::Hold) => Some(Opple(number, Click::Release)),
+      _ => None,
+    }
+  }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
-pub enum HueButton {
-  #[serde(rename = "on")]
+impl FromStr for RemoteButton {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    if let Some((button, _)) = Self::NAMED.iter().find(|(_, name)| *name == s) {
+      return Ok(*button);
+    }
+    let error = || format!("{s} is no button");
+    if let Some((number, click)) = s.strip_prefix("button_").and_then(|s| s.split_once('_')) {
+      return Ok(RemoteButton::Opple(number.parse().map_err(|_| error())?, read_part(click)?));
+    }
+    guard!(let Some((first, rest)) = s.split_once('_') else { return Err(error()) });
+    match first.parse::<u8>() {
+      Ok(number) => Ok(RemoteButton::Tuya(number, read_part(rest)?)),
+      Err(_) => Ok(RemoteButton::HueKey(read_part(first)?, read_part(rest)?)),
+    }
+  }
+}
+
+impl TryFrom<String> for RemoteButton {
+  type Error = String;
+
+  fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<RemoteButton> for String {
+  fn from(value: RemoteButton) -> Self {
+    value.to_string()
+  }
+}
+
+impl Display for RemoteButton {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RemoteButton::HueKey(key, phase) => write!(f, "{}_{}", write_part(key), write_part(phase)),
+      RemoteButton::Opple(number, click) => write!(f, "button_{number}_{}", write_part(click)),
+      RemoteButton::Tuya(number, click) => write!(f, "{number}_{}", write_part(click)),
+      named => {
+        let name = Self::NAMED.iter().find(|(b, _)| b == named).map(|(_, name)| *name);
+        write!(f, "{}", name.unwrap_or_default())
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HueKey {
   On,
-  #[serde(rename = "off")]
   Off,
-  #[serde(rename = "skip_backward")]
-  SkipBack,
-  #[serde(rename = "skip_forward")]
-  SkipForward,
-  #[serde(rename = "press")]
+  Up,
+  Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HuePhase {
   Press,
-  #[serde(rename = "hold")]
+  PressRelease,
+  Hold,
+  HoldRelease,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Click {
+  Single,
+  Double,
+  Triple,
   Hold,
-  #[serde(rename = "release")]
   Release,
 }
 
+/// Reads a part of a button name, such as the `hold` of `button_1_hold`.
+fn read_part<T: DeserializeOwned>(part: &str) -> std::result::Result<T, String> {
+  serde_json::from_value(JsonValue::from(part)).map_err(|_| format!("{part} is no button"))
+}
+
+fn write_part<T: Serialize>(part: &T) -> String {
+  let value = serde_json::to_value(part).unwrap_or_default();
+  value.as_str().unwrap_or_default().to_string()
+}
+
+/// What a remote reacts to: a single button, the same button twice in quick succession, a button
+/// held for some seconds, or two buttons pressed together.  Written as `on`, `double on`,
+/// `long brightness_move_up 2` (the seconds default to one) and `on + off`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Gesture {
+  Press(RemoteButton),
+  DoubleClick(RemoteButton),
+  LongPress(RemoteButton, u32),
+  Chord(RemoteButton, RemoteButton),
+}
+
+impl Gesture {
+  /// The buttons involved.
+  pub fn buttons(&self) -> Vec<RemoteButton> {
+    match self {
+      Gesture::Press(b) | Gesture::DoubleClick(b) | Gesture::LongPress(b, _) => vec![*b],
+      Gesture::Chord(a, b) => vec![*a, *b],
+    }
+  }
+}
+
+impl FromStr for Gesture {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    if let Some((a, b)) = s.split_once('+') {
+      return Ok(Gesture::Chord(a.trim().parse()?, b.trim().parse()?));
+    }
+    let words: Vec<&str> = s.split_whitespace().collect();
+    match words.as_slice() {
+      [button] => Ok(Gesture::Press(button.parse()?)),
+      ["double", button] => Ok(Gesture::DoubleClick(button.parse()?)),
+      ["long", button] => Ok(Gesture::LongPress(button.parse()?, 1)),
+      ["long", button, seconds] => {
+        let seconds = seconds.parse().map_err(|_| format!("{seconds} is no number of seconds"))?;
+        Ok(Gesture::LongPress(button.parse()?, seconds))
+      }
+      _ => Err(format!("{s} is no gesture")),
+    }
+  }
+}
+
+impl TryFrom<String> for Gesture {
+  type Error = String;
+
+  fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Gesture> for String {
+  fn from(value: Gesture) -> Self {
+    value.to_string()
+  }
+}
+
+impl Display for Gesture {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Gesture::Press(button) => write!(f, "{button}"),
+      Gesture::DoubleClick(button) => write!(f, "double {button}"),
+      Gesture::LongPress(button, 1) => write!(f, "long {button}"),
+      Gesture::LongPress(button, seconds) => write!(f, "long {button} {seconds}"),
+      Gesture::Chord(a, b) => write!(f, "{a} + {b}"),
+    }
+  }
+}
+
+/// What a gesture does: a single command on what the remote controls, or a list of actions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Binding {
+  Command(LightCommand),
+  Actions(Vec<ButtonAction>),
+}
+
+impl<'de> Deserialize<'de> for Binding {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    // Untagged enums cannot read the YAML tags of the actions, so the value is told apart by hand.
+    struct BindingVisitor;
+
+    impl<'de> Visitor<'de> for BindingVisitor {
+      type Value = Binding;
+
+      fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a light command or a list of actions")
+      }
+
+      fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Binding, E> {
+        LightCommand::deserialize(v.into_deserializer()).map(Binding::Command)
+      }
+
+      fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> std::result::Result<Binding, A::Error> {
+        LightCommand::deserialize(EnumAccessDeserializer::new(data)).map(Binding::Command)
+      }
+
+      fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> std::result::Result<Binding, A::Error> {
+        Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Binding::Actions)
+      }
+    }
+
+    deserializer.deserialize_any(BindingVisitor)
+  }
+}
+
+/// One thing a gesture does.  Commands, states and presets go to what the remote controls, unless
+/// they name a target of their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ButtonAction {
+  Command {
+    command: LightCommand,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<Topic>,
+  },
+  SetState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<Topic>,
+    state: TargetState,
+  },
+  Scene {
+    name: String,
+  },
+  SetEnabled {
+    scene: String,
+    enabled: bool,
+  },
+  ToggleEnabled {
+    scene: String,
+  },
+  Preset {
+    name: String,
+  },
+  SavePreset {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<Topic>,
+  },
+  /// Hands the lights under the target, or all lights, back to the scenes.
+  ClearOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<Topic>,
+  },
+  /// Makes the remote control the next of its modes.
+  NextMode,
+}
+
+impl ButtonAction {
+  /// The request that carries out the action while the remote controls `controls`.  Switching
+  /// modes is up to the remote itself.
+  pub fn request(self, controls: Topic) -> Option<Request> {
+    let command = match self {
+      ButtonAction::Command { command, target } => {
+        let topic = Some(target.unwrap_or(controls));
+        let payload = RestApiPayload { topic, ..RestApiPayload::new(Origin::Manual) };
+        return Some(Request::LightCommand(command, payload));
+      }
+      ButtonAction::SetState { target, state } => {
+        let payload = state.payload(&target.unwrap_or(controls), Origin::Manual);
+        return Some(Request::LightCommand(LightCommand::ChangeState, payload));
+      }
+      ButtonAction::Scene { name } => SceneCommand::Trigger(name),
+      ButtonAction::SetEnabled { scene, enabled } => {
+        SceneCommand::SetEnabled { name: scene, enabled }
+      }
+      ButtonAction::ToggleEnabled { scene } => SceneCommand::ToggleEnabled(scene),
+      ButtonAction::Preset { name } => SceneCommand::RestorePreset(name),
+      ButtonAction::SavePreset { name, target } => {
+        SceneCommand::SavePreset { name, target: target.unwrap_or(controls) }
+      }
+      ButtonAction::ClearOverrides { target } => SceneCommand::ClearOverrides(target),
+      ButtonAction::NextMode => return None,
+    };
+    Some(Request::SceneCommand(command))
+  }
+}
+
+/// The presses a remote saw lately, to tell gestures apart.
+#[derive(Debug, Clone, Copy, Default)]
+struct Presses {
+  last: Option<(RemoteButton, DateTime<Local>)>,
+  held: Option<(RemoteButton, DateTime<Local>)>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Remote {
   name: String,
   model: DeviceModel,
   icon: String,
   controls: String,
+  /// Further lights the remote cycles through on `NextMode`, after `controls`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  modes: Vec<String>,
   room: String,
-  actions: HashMap<RemoteButton, LightCommand>, // ToDo: Map to Api Command.
+  actions: HashMap<Gesture, Binding>,
+  #[serde(skip)]
+  mode: usize,
+  #[serde(skip)]
+  presses: Presses,
+  #[serde(skip)]
+  health: DeviceHealth,
 }
 
 impl DeviceTrait for Remote {
@@ -104,27 +466,252 @@ impl DeviceTrait for Remote {
     &self.room
   }
 
-  fn update_state(&mut self, _state: StateFromMqtt) {}
+  fn update_state(&mut self, state: StateFromMqtt, now: DateTime<Local>) {
+    self.health.update(&state, now);
+  }
 
   fn query_state(&self) -> StateToMqtt {
     StateToMqtt::empty()
   }
 
   fn query_update(&self) -> StateToMqtt {
-    StateToMqtt::empty().with_battery_query() // Makes no sense, but it has to query _something_.
+    StateToMqtt::empty().with_battery_query()
   }
 
   fn query_history(&self) -> Vec<StateToMqtt> {
     vec![]
   }
+
+  fn health(&self) -> &DeviceHealth {
+    &self.health
+  }
 }
 
 impl Remote {
-  pub fn action(&self, button: RemoteButton) -> Option<LightCommand> {
-    self.actions.get(&button).copied()
+  /// How soon a second press of the same button has to follow to make a double click.
+  const DOUBLE_CLICK: Duration = Duration::milliseconds(400);
+  /// How close together two buttons have to be pressed to make a chord.
+  const CHORD: Duration = Duration::milliseconds(200);
+
+  /// Turns a press of `button` at `now` into the gesture it completes.  A press counts as itself
+  /// unless it completes a double click, chord or long press that the remote has actions for.
+  /// The first press of a double click or chord already does what it does on its own.
+  pub fn recognise(&mut self, button: RemoteButton, now: DateTime<Local>) -> Gesture {
+    let last = self.presses.last.replace((button, now));
+    if let Some((held, since)) =
+      self.presses.held.take().filter(|(h, _)| h.release() == Some(button))
+    {
+      let long = self.actions.keys().filter_map(|g| match g {
+        Gesture::LongPress(b, seconds) if *b == held => Some(*seconds),
+        _ => None,
+      });
+      if let Some(seconds) = long.filter(|s| now - since >= Duration::seconds(*s as i64)).max() {
+        return Gesture::LongPress(held, seconds);
+      }
+    }
+    if button.release().is_some() {
+      self.presses.held = Some((button, now));
+    }
+    guard!(let Some((last, at)) = last else { return Gesture::Press(button) });
+    let candidates = if last == button && now - at <= Self::DOUBLE_CLICK {
+      vec![Gesture::DoubleClick(button)]
+    } else if last != button && now - at <= Self::CHORD {
+      vec![Gesture::Chord(last, button), Gesture::Chord(button, last)]
+    } else {
+      vec![]
+    };
+    match candidates.into_iter().find(|g| self.actions.contains_key(g)) {
+      Some(gesture) => {
+        self.presses.last = None;
+        gesture
+      }
+      None => Gesture::Press(button),
+    }
+  }
+
+  /// What the remote does on `gesture`, in order.
+  pub fn actions(&self, gesture: &Gesture) -> Vec<ButtonAction> {
+    match self.actions.get(gesture) {
+      Some(Binding::Command(command)) => {
+        vec![ButtonAction::Command { command: *command, target: None }]
+      }
+      Some(Binding::Actions(actions)) => actions.clone(),
+      None => vec![],
+    }
+  }
+
+  pub fn gestures(&self) -> impl Iterator<Item = &Gesture> {
+    self.actions.keys()
+  }
+
+  /// Every button that is part of some gesture.
+  pub fn buttons(&self) -> Vec<RemoteButton> {
+    let mut buttons: Vec<RemoteButton> = vec![];
+    for button in self.gestures().flat_map(Gesture::buttons) {
+      if !buttons.contains(&button) {
+        buttons.push(button);
+      }
+    }
+    buttons
   }
 
+  pub fn has_modes(&self) -> bool {
+    !self.modes.is_empty()
+  }
+
+  /// Makes the remote control the next light of `controls` and `modes`.
+  pub fn next_mode(&mut self) {
+    self.mode = (self.mode + 1) % (self.modes.len() + 1);
+  }
+
+  /// The light the remote controls in its current mode.
   pub fn controls(&self) -> Topic {
-    Topic::try_from(self.controls.clone()).expect("Implement error handling.")
+    self.try_controls().expect("Implement error handling.")
+  }
+
+  pub fn try_controls(&self) -> Result<Topic> {
+    let controls = if self.mode == 0 { &self.controls } else { &self.modes[self.mode - 1] };
+    Topic::try_from(controls.clone())
+  }
+
+  /// The lights the remote controls in each of its modes.
+  pub fn try_targets(&self) -> Vec<Result<Topic>> {
+    let targets = std::iter::once(&self.controls).chain(&self.modes);
+    targets.map(|t| Topic::try_from(t.clone())).collect()
+  }
+
+  pub fn inherit_state(&mut self, previous: &Remote) {
+    self.health = previous.health;
+    if previous.mode <= self.modes.len() {
+      self.mode = previous.mode;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::Duration;
+
+  use crate::{
+    api::request::{LightCommand, Request, SceneCommand},
+    convert::Origin,
+    devices::DeviceModel,
+    simulation::fixture,
+  };
+
+  use super::{Binding, ButtonAction, Click, Gesture, HueKey, HuePhase, Remote, RemoteButton};
+
+  fn dimmer() -> Remote {
+    serde_yaml::from_str(
+      r#"
+name: Dimmer
+model: IkeaDimmer
+icon: remote
+controls: zigbee2mqtt/Group/Hall/Main
+modes: [zigbee2mqtt/Device/Light/Hall/Floor]
+room: Hall
+actions:
+  "on": TurnOn
+  "off": TurnOff
+  "double on": [!SetState { state: { brightness: 100 } }]
+  "on + off": [NextMode]
+  "long brightness_move_up 2": [!Scene { name: Bright }]
+"#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn test_gestures_and_modes() {
+    let mut remote = dimmer();
+    let mut now = fixture::start();
+    let mut press = |button: &str, millis: i64| {
+      now += Duration::milliseconds(millis);
+      remote.recognise(button.parse().unwrap(), now)
+    };
+    assert_eq!(press("on", 0), Gesture::Press(RemoteButton::On));
+    assert_eq!(press("on", 300), Gesture::DoubleClick(RemoteButton::On));
+    assert_eq!(press("on", 300), Gesture::Press(RemoteButton::On), "A double click is over.");
+    assert_eq!(press("on", 500), Gesture::Press(RemoteButton::On));
+    assert_eq!(press("off", 100), Gesture::Chord(RemoteButton::On, RemoteButton::Off));
+    assert_eq!(press("off", 5000), Gesture::Press(RemoteButton::Off));
+    // Holding a button briefly makes no long press, holding it long enough does.
+    assert_eq!(press("brightness_move_up", 5000), Gesture::Press(RemoteButton::BriMoveUp));
+    assert_eq!(press("brightness_stop", 1000), Gesture::Press(RemoteButton::BriMoveStop));
+    press("brightness_move_up", 5000);
+    let long = Gesture::LongPress(RemoteButton::BriMoveUp, 2);
+    assert_eq!(press("brightness_stop", 3000), long);
+    assert_eq!(remote.actions(&long), vec![ButtonAction::Scene { name: String::from("Bright") }]);
+    let main = remote.controls();
+    remote.next_mode();
+    assert_eq!(remote.controls().to_str(), "zigbee2mqtt/Device/Light/Hall/Floor");
+    remote.next_mode();
+    assert_eq!(remote.controls(), main);
+  }
+
+  #[test]
+  fn test_actions_aim_at_the_controlled_light() {
+    let remote = dimmer();
+    let controls = remote.controls();
+    let on = remote.actions(&Gesture::Press(RemoteButton::On)).pop().unwrap();
+    let Some(Request::LightCommand(LightCommand::TurnOn, payload)) = on.request(controls.clone())
+    else {
+      panic!("TurnOn makes a light command.")
+    };
+    assert_eq!((payload.topic, payload.origin), (Some(controls.clone()), Origin::Manual));
+    let scene_command = |action: ButtonAction| match action.request(controls.clone()) {
+      Some(Request::SceneCommand(command)) => Some(command),
+      _ => None,
+    };
+    let save = ButtonAction::SavePreset { name: String::from("Lit"), target: None };
+    let expected = SceneCommand::SavePreset { name: String::from("Lit"), target: controls.clone() };
+    assert_eq!(scene_command(save), Some(expected));
+    let toggle = ButtonAction::ToggleEnabled { scene: String::from("Welcome") };
+    assert_eq!(scene_command(toggle), Some(SceneCommand::ToggleEnabled(String::from("Welcome"))));
+    assert!(ButtonAction::NextMode.request(controls.clone()).is_none());
+  }
+
+  #[test]
+  fn test_parse_by_model() {
+    let parse = RemoteButton::parse;
+    assert_eq!(parse("on", DeviceModel::HueButton), Some(RemoteButton::On));
+    assert_eq!(parse("on", DeviceModel::IkeaMultiButton), None);
+    let up = RemoteButton::HueKey(HueKey::Up, HuePhase::HoldRelease);
+    assert_eq!(parse("up_hold_release", DeviceModel::HueDimmer), Some(up));
+    assert_eq!(
+      parse("button_6_triple", DeviceModel::AqaraOpple),
+      Some(RemoteButton::Opple(6, Click::Triple))
+    );
+    assert_eq!(
+      parse("3_hold", DeviceModel::TuyaSceneSwitch),
+      Some(RemoteButton::Tuya(3, Click::Hold))
+    );
+    assert_eq!(parse("3_release", DeviceModel::TuyaSceneSwitch), None);
+    assert_eq!(parse("arrow_left_hold", DeviceModel::IkeaStyrbar), Some(RemoteButton::ArrLeftHold));
+    assert_eq!(parse("off", DeviceModel::IkeaShortcut), None);
+    for name in ["brightness_stop", "on_press_release", "button_2_hold", "4_double"] {
+      assert_eq!(name.parse::<RemoteButton>().unwrap().to_string(), name);
+    }
+  }
+
+  #[test]
+  fn test_binding_formats() {
+    let actions = Binding::Actions(vec![
+      ButtonAction::Scene { name: String::from("X") },
+      ButtonAction::NextMode,
+    ]);
+    let yaml: Binding = serde_yaml::from_str("[!Scene { name: X }, NextMode]").unwrap();
+    assert_eq!(yaml, actions);
+    let json: Binding = serde_json::from_str(r#"[{"Scene":{"name":"X"}},"NextMode"]"#).unwrap();
+    assert_eq!(json, actions);
+    let command = Binding::Command(LightCommand::Toggle);
+    assert_eq!(serde_yaml::from_str::<Binding>("Toggle").unwrap(), command);
+    assert_eq!(serde_yaml::from_str::<Binding>("!Toggle").unwrap(), command);
+    for binding in [actions, command] {
+      let json = serde_json::to_string(&binding).unwrap();
+      assert_eq!(serde_json::from_str::<Binding>(&json).unwrap(), binding);
+      let yaml = serde_yaml::to_string(&binding).unwrap();
+      assert_eq!(serde_yaml::from_str::<Binding>(&yaml).unwrap(), binding);
+    }
   }
 }
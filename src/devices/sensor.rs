@@ -9,7 +9,7 @@ use crate::{
   convert::{StateFromMqtt, StateToMqtt},
 };
 
-use super::{Capability, DeviceModel, DeviceTrait};
+use super::{Capability, DeviceHealth, DeviceModel, DeviceTrait};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sensor {
@@ -19,6 +19,8 @@ pub struct Sensor {
   room: String,
   #[serde(skip)]
   states: VecDeque<SensorState>,
+  #[serde(skip)]
+  health: DeviceHealth,
 }
 
 impl DeviceTrait for Sensor {
@@ -38,9 +40,10 @@ impl DeviceTrait for Sensor {
     &self.room
   }
 
-  fn update_state(&mut self, state: StateFromMqtt) {
+  fn update_state(&mut self, state: StateFromMqtt, now: DateTime<Local>) {
+    self.health.update(&state, now);
     let mut new = self.states.back().cloned().unwrap_or_default();
-    new.with_mqtt_state(self.model(), state);
+    new.with_mqtt_state(self.model(), state, now);
     self.states.push_back(new);
     if self.states.len() > 100 {
       self.states.pop_front();
@@ -62,6 +65,22 @@ impl DeviceTrait for Sensor {
   fn query_history(&self) -> Vec<StateToMqtt> {
     self.states.iter().map(|s| s.to_mqtt_state(self.model)).collect()
   }
+
+  fn health(&self) -> &DeviceHealth {
+    &self.health
+  }
+}
+
+impl Sensor {
+  pub fn inherit_state(&mut self, previous: &Sensor) {
+    self.states = previous.states.clone();
+    self.health = previous.health;
+  }
+
+  /// The last reported state, if the sensor reported anything yet.
+  pub fn latest(&self) -> Option<&SensorState> {
+    self.states.back()
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
@@ -71,23 +90,35 @@ pub struct SensorState {
   humidity: f64,
   temp: f64,
   occupancy: bool,
+  #[serde(default)]
+  illuminance: f64,
 }
 
 impl SensorState {
-  pub fn with_mqtt_state(&mut self, model: DeviceModel, state: StateFromMqtt) {
+  /// Takes over what the report holds.  Reports may leave fields out, e.g. when only the battery
+  /// level changed.
+  pub fn with_mqtt_state(
+    &mut self,
+    model: DeviceModel,
+    state: StateFromMqtt,
+    now: DateTime<Local>,
+  ) {
     if model.capable_of(Capability::State) {
-      self.active = state.state().unwrap();
+      self.active = state.state().unwrap_or(self.active);
     }
     if model.capable_of(Capability::Humidity) {
-      self.humidity = state.humidity.unwrap();
+      self.humidity = state.humidity.unwrap_or(self.humidity);
     }
     if model.capable_of(Capability::Temperature) {
-      self.temp = state.temperature.unwrap();
+      self.temp = state.temperature.unwrap_or(self.temp);
     }
     if model.capable_of(Capability::Occupancy) {
-      self.occupancy = state.occupancy.unwrap();
+      self.occupancy = state.occupancy.unwrap_or(self.occupancy);
+    }
+    if model.capable_of(Capability::Illuminance) {
+      self.illuminance = state.illuminance.unwrap_or(self.illuminance);
     }
-    self.time = Local::now();
+    self.time = now;
   }
 
   pub fn to_mqtt_state(&self, model: DeviceModel) -> StateToMqtt {
@@ -104,14 +135,32 @@ impl SensorState {
     if model.capable_of(Capability::Occupancy) {
       res = res.with_occupancy(self.occupancy);
     }
+    if model.capable_of(Capability::Illuminance) {
+      res = res.with_illuminance(self.illuminance);
+    }
     res = res.with_time(self.time);
     res
   }
 }
 
+impl SensorState {
+  /// The reading for a capability, or `None` if sensors do not measure it.
+  pub fn reading(&self, capability: Capability) -> Option<Value> {
+    match capability {
+      Capability::State => Some(Value::Bool(self.active)),
+      Capability::Humidity => Some(self.humidity.into()),
+      Capability::Temperature => Some(self.temp.into()),
+      Capability::Occupancy => Some(Value::Bool(self.occupancy)),
+      Capability::Illuminance => Some(self.illuminance.into()),
+      Capability::Brightness | Capability::Color | Capability::Transition => None,
+    }
+  }
+}
+
 impl Default for SensorState {
   fn default() -> Self {
-    Self { time: Local::now(), active: false, humidity: 0.0, temp: 0.0, occupancy: false }
+    let time = Local::now();
+    Self { time, active: false, humidity: 0.0, temp: 0.0, occupancy: false, illuminance: 0.0 }
   }
 }
 
@@ -126,6 +175,29 @@ impl From<Value> for SensorState {
     let humidity = value.get("humid").and_then(Value::as_f64).unwrap_or(0.0);
     let temp = value.get("temperature").and_then(Value::as_f64).unwrap_or(0.0);
     let occupancy = value.get("occupancy").and_then(Value::as_bool).unwrap_or(false);
-    SensorState { time, active, humidity, temp, occupancy }
+    let illuminance = value.get("illuminance").and_then(Value::as_f64).unwrap_or(0.0);
+    SensorState { time, active, humidity, temp, occupancy, illuminance }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::{Local, TimeZone};
+  use serde_json::json;
+
+  use super::Sensor;
+  use crate::devices::DeviceTrait;
+
+  #[test]
+  fn test_reports_may_leave_fields_out() {
+    let yaml = "{ name: Motion, model: HueMotion, icon: motion, room: Hall }";
+    let mut sensor: Sensor = serde_yaml::from_str(yaml).unwrap();
+    let now = Local.with_ymd_and_hms(2024, 3, 1, 14, 0, 0).unwrap();
+    let report = json!({ "occupancy": true, "illuminance": 30, "temperature": 21.5 });
+    sensor.update_state(serde_json::from_value(report).unwrap(), now);
+    sensor.update_state(serde_json::from_value(json!({ "battery": 80 })).unwrap(), now);
+    let latest = sensor.latest().unwrap();
+    assert_eq!((latest.occupancy, latest.illuminance, latest.temp), (true, 30.0, 21.5));
+    assert_eq!(sensor.health().battery, Some(80.0));
   }
 }
@@ -1,15 +1,27 @@
 // use crate::api::HomeEditError;
 
+use crate::home::validation::Diagnostic;
+
 #[derive(Debug)]
 pub enum HomeBaseError {
   Io(std::io::Error),
   Serde(serde_yaml::Error),
   Paho(paho_mqtt::Error),
+  ConnectionLost,
   CtrlC(ctrlc::Error),
   ImpossibleStrConversion,
   InvalidTopic,
   UnexpectedMqttPayload,
   InvalidLightState,
+  InvalidHome(Vec<Diagnostic>),
+  Http(hyper::Error),
+  Api(String),
+  UnexpectedApiResponse,
+  Usage(String),
+  Config { key: String, msg: String },
+  UnknownDevice(String),
+  UnknownPreset(String),
+  UnknownScene(String),
   // HomeEdit(crate:::api::HomeEditError),
 }
 
@@ -36,3 +48,9 @@ impl From<ctrlc::Error> for HomeBaseError {
     Self::CtrlC(value)
   }
 }
+
+impl From<hyper::Error> for HomeBaseError {
+  fn from(value: hyper::Error) -> Self {
+    Self::Http(value)
+  }
+}
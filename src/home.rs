@@ -1,5 +1,7 @@
 use std::fs::File;
 
+use chrono::{DateTime, Duration, Local};
+use guard::guard;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -12,20 +14,198 @@ use crate::{
     },
   },
   convert::StateToMqtt,
-  devices::{Device, DeviceTrait},
-  scenes::scene::Scene,
-  Result,
+  devices::{Device, DeviceTrait, HealthLimits},
+  scenes::{
+    preset::Preset,
+    scene::{ConflictPolicy, Scene},
+    sun::GeoLocation,
+  },
+  Error, Result,
 };
 
 use room::Room;
+use validation::Diagnostic;
 
 mod room;
+pub mod validation;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Home {
   name: String,
   rooms: Vec<Room>,
   pub scenes: Vec<Scene>,
+  /// Required for scenes that follow the sun.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub location: Option<GeoLocation>,
+  #[serde(default)]
+  pub conflicts: ConflictPolicy,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub presets: Vec<Preset>,
+  /// Temporary presets, e.g. to restore the lights after flashing them.
+  #[serde(skip)]
+  pub snapshots: Vec<Preset>,
+  /// How long scenes leave a light alone after it was controlled manually.  Zero disables it.
+  #[serde(default = "Home::default_override_minutes")]
+  pub override_minutes: i64,
+  /// When devices count as unhealthy, which scenes can react to.
+  #[serde(default)]
+  pub health: HealthLimits,
+  /// Manually controlled lights and until when scenes leave them alone.
+  #[serde(skip)]
+  overrides: Vec<(Topic, DateTime<Local>)>,
+}
+
+impl Home {
+  /// Reads and validates the home as of `now`.  Warnings are printed, errors reject the home.
+  pub fn load(path: &str, now: DateTime<Local>) -> Result<Home> {
+    let (home, diagnostics) = validation::check(path, now);
+    diagnostics.iter().filter(|d| !d.is_error()).for_each(|d| eprintln!("{d}"));
+    match home {
+      Some(home) if !diagnostics.iter().any(Diagnostic::is_error) => Ok(home),
+      _ => Err(Error::InvalidHome(diagnostics.into_iter().filter(Diagnostic::is_error).collect())),
+    }
+  }
+
+  /// Carries the runtime state of every device and scene that survives a reload over from
+  /// `previous`.  Whether a scene is enabled is up to the file.
+  pub fn inherit_states(&mut self, previous: &Home) {
+    for device in self.flatten_devices_mut() {
+      if let Some(old) = previous.find_device(&device.topic(TopicMode::Blank)) {
+        device.inherit_state(old);
+      }
+    }
+    for scene in &mut self.scenes {
+      if let Some(old) = previous.scenes.iter().find(|s| s.name == scene.name) {
+        scene.last_run = old.last_run;
+        scene.last_triggered = old.last_triggered;
+        scene.history = old.history.clone();
+      }
+    }
+    // Presets saved since the home was loaded are not in the file yet.
+    for preset in &previous.presets {
+      if !self.presets.iter().any(|p| p.name == preset.name) {
+        self.presets.push(preset.clone());
+      }
+    }
+    self.snapshots = previous.snapshots.clone();
+    self.overrides = previous.overrides.clone();
+  }
+
+  fn default_override_minutes() -> i64 {
+    30
+  }
+
+  /// Pauses scenes on the lights under `target`.
+  pub fn mark_overridden(&mut self, target: &Topic, now: DateTime<Local>) {
+    guard!(let Some(light) = self.find_effective_light(target) else { return });
+    let until = now + Duration::minutes(self.override_minutes);
+    for light in light.snapshot().into_iter().map(|s| s.light) {
+      self.overrides.retain(|(l, _)| *l != light);
+      self.overrides.push((light, until));
+    }
+  }
+
+  /// Lights that are still overridden at `now`, with the end of their override.
+  pub fn overrides(&self, now: DateTime<Local>) -> Vec<(Topic, DateTime<Local>)> {
+    self.overrides.iter().filter(|(_, until)| *until > now).cloned().collect()
+  }
+
+  pub fn is_overridden(&self, light: &Topic, now: DateTime<Local>) -> bool {
+    self.overrides.iter().any(|(l, until)| l == light && *until > now)
+  }
+
+  /// Hands the lights under `target`, or all lights, back to the scenes.
+  pub fn clear_overrides(&mut self, target: Option<&Topic>) {
+    let lights: Option<Vec<Topic>> = target.map(|t| match self.find_effective_light(t) {
+      Some(light) => light.snapshot().into_iter().map(|s| s.light).collect(),
+      None => vec![],
+    });
+    match lights {
+      Some(lights) => self.overrides.retain(|(l, _)| !lights.contains(l)),
+      None => self.overrides.clear(),
+    }
+  }
+
+  /// Captures the lights under `target` as a preset, replacing one of the same name.
+  pub fn save_preset(&mut self, name: String, target: Topic, temporary: bool) -> Result<()> {
+    guard!(let Some(light) = self.find_effective_light(&target) else {
+      return Err(Error::UnknownDevice(target.to_str()));
+    });
+    let preset = Preset { name, target, lights: light.snapshot() };
+    let presets = if temporary { &mut self.snapshots } else { &mut self.presets };
+    presets.retain(|p| p.name != preset.name);
+    presets.push(preset);
+    Ok(())
+  }
+
+  /// The snapshot or else the preset of the name.
+  pub fn preset(&self, name: &str) -> Option<&Preset> {
+    let snapshot = self.snapshots.iter().find(|p| p.name == name);
+    snapshot.or_else(|| self.presets.iter().find(|p| p.name == name))
+  }
+
+  /// Brings the lights back into the preset's state, all of them or `only` those given.
+  /// Snapshots take precedence over presets of the same name and are dropped once restored.
+  pub fn restore_preset(
+    &mut self,
+    name: &str,
+    only: Option<&[Topic]>,
+  ) -> Result<Vec<(Topic, StateToMqtt)>> {
+    let mut preset = match self.snapshots.iter().position(|p| p.name == name) {
+      Some(index) => self.snapshots.remove(index),
+      None => self.preset(name).cloned().ok_or_else(|| Error::UnknownPreset(name.to_string()))?,
+    };
+    if let Some(only) = only {
+      preset.lights.retain(|saved| only.contains(&saved.light));
+    }
+    guard!(let Some(light) = self.find_effective_light_mut(&preset.target) else {
+      return Err(Error::UnknownDevice(preset.target.to_str()));
+    });
+    Ok(light.restore(&preset.lights))
+  }
+
+  /// Adds the scene, or replaces the one of the same name.  Leaves the home as it was if the
+  /// scene does not fit it.
+  pub fn put_scene(&mut self, mut scene: Scene, now: DateTime<Local>) -> Vec<Diagnostic> {
+    let name = scene.name.clone();
+    // What happened is up to the home, not to the definition.
+    let old = self.scenes.iter().find(|s| s.name == name);
+    scene.last_run = old.and_then(|s| s.last_run);
+    scene.last_triggered = old.and_then(|s| s.last_triggered);
+    scene.history = old.map(|s| s.history.clone()).unwrap_or_default();
+    let previous = match self.scenes.iter().position(|s| s.name == name) {
+      Some(index) => Some(std::mem::replace(&mut self.scenes[index], scene)),
+      None => {
+        self.scenes.push(scene);
+        None
+      }
+    };
+    let index = self.scenes.iter().position(|s| s.name == name).unwrap();
+    let diagnostics = validation::check_scene_of(self, &self.scenes[index], now);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+      match previous {
+        Some(previous) => self.scenes[index] = previous,
+        None => _ = self.scenes.remove(index),
+      }
+    }
+    diagnostics
+  }
+
+  pub fn delete_scene(&mut self, name: &str) -> Result<()> {
+    guard!(let Some(index) = self.scenes.iter().position(|s| s.name == name) else {
+      return Err(Error::UnknownScene(name.to_string()));
+    });
+    self.scenes.remove(index);
+    Ok(())
+  }
+
+  pub fn delete_preset(&mut self, name: &str) -> Result<()> {
+    guard!(let Some(index) = self.presets.iter().position(|p| p.name == name) else {
+      return Err(Error::UnknownPreset(name.to_string()));
+    });
+    self.presets.remove(index);
+    Ok(())
+  }
 }
 
 impl Addressable for Home {
@@ -61,16 +241,17 @@ impl EffectiveLightCollection for Home {
 }
 
 impl ReadWriteHome for Home {
-  fn read(from: &str) -> Self {
-    let content = std::fs::read_to_string(from).expect("Cannot open file.");
-    let home: Home = serde_yaml::from_str(&content).expect("Cannot read home.");
-    home
+  fn read(from: &str) -> Result<Self> {
+    let content = std::fs::read_to_string(from)?;
+    let home: Home = serde_yaml::from_str(&content)?;
+    Ok(home)
   }
 
+  /// Replaces the file at `to` in one step, so that no reader ever sees half a home.
   fn persist(&self, to: &str) -> Result<()> {
-    let mut path = to.to_string();
-    path.push_str(".out");
-    serde_yaml::to_writer(&File::create(path)?, self)?;
+    let temporary = format!("{to}.tmp");
+    serde_yaml::to_writer(&File::create(&temporary)?, self)?;
+    std::fs::rename(temporary, to)?;
     Ok(())
   }
 }
@@ -94,3 +275,109 @@ impl QueryableHome for Home {
     self.find_device(&topic).unwrap().query_history()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use chrono::Duration;
+  use serde_json::{json, Value as JsonValue};
+
+  use crate::{
+    api::traits::{DeviceCollection, QueryableHome},
+    devices::DeviceTrait,
+    scenes::scene::Scene,
+    simulation::fixture::{self, topic, CEILING, FLOOR, HALL},
+  };
+
+  use super::Home;
+
+  /// Lets the light report a state, like the executor would.
+  fn report(home: &mut Home, light: &str, state: JsonValue) {
+    let device = home.find_device_mut(&topic(light)).unwrap();
+    device.update_state(serde_json::from_value(state).unwrap(), fixture::start());
+  }
+
+  #[test]
+  fn test_snapshots_restore_lights() {
+    let mut home = fixture::home("[]");
+    let hall = topic(HALL);
+    report(&mut home, FLOOR, json!({ "state": "ON", "brightness": 127 }));
+    home.save_preset(String::from("Before"), hall.clone(), true).unwrap();
+    report(&mut home, FLOOR, json!({ "state": "OFF", "brightness": 20 }));
+    home.save_preset(String::from("Before"), hall, false).unwrap();
+    // The snapshot takes precedence over the preset of the same name, once.
+    let sent = home.restore_preset("Before", None).unwrap();
+    let floor = sent.into_iter().find(|(t, _)| t.to_str().starts_with(FLOOR)).unwrap();
+    let floor = floor.1.to_json_value(false);
+    assert_eq!((&floor["state"], &floor["brightness"]), (&json!("ON"), &json!(127.0)));
+    assert!(home.snapshots.is_empty());
+    let sent = home.restore_preset("Before", None).unwrap();
+    let floor = sent.into_iter().find(|(t, _)| t.to_str().starts_with(FLOOR)).unwrap();
+    assert_eq!(floor.1.to_json_value(false)["state"], json!("OFF"));
+    assert!(home.restore_preset("After", None).is_err());
+    // Restoring some lights leaves the others as they are.
+    report(&mut home, FLOOR, json!({ "state": "ON", "brightness": 127 }));
+    assert!(home.restore_preset("Before", Some(&[topic(CEILING)])).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_reload_keeps_runtime_state() {
+    let mut old = fixture::home("[]");
+    let now = fixture::start();
+    let effect = format!("!LightCommand {{ target: {FLOOR}, command: Toggle }}");
+    let scene = format!("{{ name: Day, trigger: ManualOnly, effect: {effect} }}");
+    assert!(old.put_scene(serde_yaml::from_str(&scene).unwrap(), now).is_empty());
+    report(&mut old, FLOOR, json!({ "state": "ON", "brightness": 127 }));
+    let day = &mut old.scenes[0];
+    (day.enabled, day.last_run, day.last_triggered) = (false, Some(now), Some(now));
+    let mut new: Home = serde_yaml::from_str(&serde_yaml::to_string(&old).unwrap()).unwrap();
+    new.scenes[0].enabled = true;
+    new.inherit_states(&old);
+    let day = &new.scenes[0];
+    assert_eq!((day.last_run, day.last_triggered), (Some(now), Some(now)));
+    assert!(day.enabled, "The file decides whether the scene is enabled.");
+    assert_eq!(new.query_device(topic(FLOOR)).to_json_value(false)["state"], json!("ON"));
+  }
+
+  #[test]
+  fn test_overrides_expire() {
+    let mut home = fixture::home("[]");
+    let now = fixture::start();
+    let (floor, ceiling) = (topic(FLOOR), topic(CEILING));
+    home.mark_overridden(&topic("zigbee2mqtt/Group/Hall/Main"), now);
+    assert!(home.is_overridden(&floor, now) && home.is_overridden(&ceiling, now));
+    assert!(!home.is_overridden(&floor, now + Duration::minutes(30)), "Overrides last 30 minutes.");
+    home.clear_overrides(Some(&floor));
+    assert_eq!(home.overrides(now), vec![(ceiling, now + Duration::minutes(30))]);
+    home.clear_overrides(None);
+    assert!(home.overrides(now).is_empty());
+  }
+
+  #[test]
+  fn test_put_scene() {
+    let mut home = fixture::home("[]");
+    let now = fixture::start();
+    let scene = |target: &str| -> Scene {
+      let effect = format!("!LightCommand {{ target: {target}, command: Toggle }}");
+      serde_yaml::from_str(&format!("{{ name: Day, trigger: ManualOnly, effect: {effect} }}"))
+        .unwrap()
+    };
+    let invalid = home.put_scene(scene("zigbee2mqtt/Room/Attic"), now);
+    assert!(invalid.iter().any(|d| d.is_error()), "Attic is not in the home.");
+    assert!(home.scenes.is_empty());
+    assert!(home.put_scene(scene(FLOOR), now).is_empty());
+    home.scenes[0].last_triggered = Some(now);
+    home.scenes[0].last_run = Some(now);
+    // What happened to the scene survives its redefinition, an invalid one leaves it as it was.
+    assert!(home.put_scene(scene("zigbee2mqtt/Device/Light/Hall/Ceiling"), now).is_empty());
+    assert!(!home.put_scene(scene("zigbee2mqtt/Room/Attic"), now).is_empty());
+    assert_eq!(home.scenes.len(), 1);
+    assert_eq!(home.scenes[0].last_triggered, Some(now));
+    assert_eq!(home.scenes[0].last_run, Some(now));
+    // Runtime state stays out of the home file.
+    assert!(!serde_yaml::to_string(&home).unwrap().contains("last_triggered"));
+    let effect = serde_json::to_value(&home.scenes[0].effect).unwrap();
+    assert_eq!(effect["LightCommand"]["target"], json!("zigbee2mqtt/Device/Light/Hall/Ceiling"));
+    home.delete_scene("Day").unwrap();
+    assert!(home.delete_scene("Day").is_err());
+  }
+}
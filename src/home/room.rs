@@ -33,6 +33,12 @@ impl Room {
   }
 }
 
+impl Room {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
+
 impl Addressable for Room {
   fn topic(&self, mode: TopicMode) -> Topic {
     Topic::Room { name: self.name.clone(), mode }
@@ -0,0 +1,783 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Duration, Local};
+use guard::guard;
+use serde::Serialize;
+
+use crate::{
+  api::{
+    request::LightCommand,
+    topic::{DeviceKind, Topic, TopicMode},
+    traits::{Addressable, DeviceCollection, EffectiveLightCollection},
+  },
+  devices::{
+    remote::{ButtonAction, Gesture},
+    Device, DeviceTrait, HealthLimits, Remote,
+  },
+  scenes::{
+    scene::{Comparison, Effect, LightCheck, Scene, TargetState, Trigger},
+    schedule::Schedule,
+    sun::GeoLocation,
+  },
+};
+
+use super::Home;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+  pub file: String,
+  pub line: usize,
+  pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub location: Option<Location>,
+}
+
+impl Diagnostic {
+  pub fn error(message: String) -> Self {
+    Diagnostic { severity: Severity::Error, message, location: None }
+  }
+
+  pub fn is_error(&self) -> bool {
+    self.severity == Severity::Error
+  }
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    if let Some(Location { file, line, column }) = &self.location {
+      write!(f, "{file}:{line}:{column}: ")?;
+    }
+    let severity = match self.severity {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+    };
+    write!(f, "{severity}: {}", self.message)
+  }
+}
+
+/// A problem found in a parsed home.  The anchors are snippets of the source file that lead to the
+/// offending line when searched for one after another, each within the block of the one before.
+/// Repeating the first anchor asks for one of its later occurrences, e.g. a duplicate's.
+#[derive(Debug, Clone)]
+struct Issue {
+  severity: Severity,
+  message: String,
+  anchors: Vec<String>,
+}
+
+impl Issue {
+  fn error(message: String, anchors: Vec<String>) -> Self {
+    Issue { severity: Severity::Error, message, anchors }
+  }
+
+  fn warning(message: String, anchors: Vec<String>) -> Self {
+    Issue { severity: Severity::Warning, message, anchors }
+  }
+
+  /// Leaves the location out unless the anchors lead to exactly one place, rather than guessing.
+  fn locate(self, file: &str, source: &str) -> Diagnostic {
+    let location = Self::find(&self.anchors, source).map(|pos| {
+      let before = &source[..pos];
+      let line = before.matches('\n').count() + 1;
+      let column = pos - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+      Location { file: file.to_string(), line, column }
+    });
+    Diagnostic { severity: self.severity, message: self.message, location }
+  }
+
+  fn find(anchors: &[String], source: &str) -> Option<usize> {
+    let first = anchors.first()?;
+    let repeated = anchors.iter().take_while(|a| *a == first).count();
+    let found = occurrences(source, first);
+    if found.len() != repeated {
+      return None;
+    }
+    let mut pos = found[repeated - 1];
+    let mut start = pos + first.len();
+    for anchor in &anchors[repeated..] {
+      let block = &source[start..block_end(source, pos)];
+      guard!(let [at] = occurrences(block, anchor)[..] else { return None });
+      pos = start + at;
+      start = pos + anchor.len();
+    }
+    Some(pos)
+  }
+}
+
+/// Where `anchor` occurs in `text` as a whole, e.g. `name: Hall` but not in `name: Hallway`.
+fn occurrences(text: &str, anchor: &str) -> Vec<usize> {
+  let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '/');
+  let open = word(anchor.chars().next());
+  let close = word(anchor.chars().next_back());
+  let whole = |&pos: &usize| {
+    let after = &text[pos + anchor.len()..];
+    // Names may contain spaces, so a word after a space continues the name.
+    let continued =
+      word(after.chars().next()) || word(after.strip_prefix(' ').and_then(|a| a.chars().next()));
+    let preceded = word(text[..pos].chars().next_back());
+    !(open && preceded || close && continued)
+  };
+  text.match_indices(anchor).map(|(pos, _)| pos).filter(whole).collect()
+}
+
+/// The end of the line at `pos` and of the lines indented deeper, which YAML nests below it.
+fn block_end(source: &str, pos: usize) -> usize {
+  let indent = |line: &str| line.len() - line.trim_start_matches(' ').len();
+  let line = &source[source[..pos].rfind('\n').map_or(0, |i| i + 1)..];
+  let (base, item) = (indent(line), line.trim_start().starts_with('-'));
+  let mut end = source[pos..].find('\n').map_or(source.len(), |i| pos + i);
+  while end < source.len() {
+    let line = source[end + 1..].split('\n').next().unwrap_or_default();
+    let content = line.trim_start_matches(' ');
+    let nested = content.is_empty()
+      || content.starts_with('#')
+      || indent(line) > base
+      || (indent(line) == base && !item && content.starts_with('-'));
+    if !nested {
+      break;
+    }
+    end += 1 + line.len();
+  }
+  end
+}
+
+/// Reads and validates the home file at `path` as of `now`.  Returns the home if it could be
+/// parsed at all.
+pub fn check(path: &str, now: DateTime<Local>) -> (Option<Home>, Vec<Diagnostic>) {
+  let source = match std::fs::read_to_string(path) {
+    Ok(source) => source,
+    Err(err) => {
+      let message = format!("Cannot read {path}: {err}.");
+      return (None, vec![Diagnostic::error(message)]);
+    }
+  };
+  let home: Home = match serde_yaml::from_str(&source) {
+    Ok(home) => home,
+    Err(err) => {
+      let location = err.location().map(|l| Location {
+        file: path.to_string(),
+        line: l.line(),
+        column: l.column(),
+      });
+      let message = format!("Cannot parse home: {err}.");
+      return (None, vec![Diagnostic { severity: Severity::Error, message, location }]);
+    }
+  };
+  let diagnostics = validate(&home, now).into_iter().map(|i| i.locate(path, &source)).collect();
+  (Some(home), diagnostics)
+}
+
+/// Validates one scene of the home, e.g. one defined over the web API.  There is no file to locate
+/// the issues in.
+pub fn check_scene_of(home: &Home, scene: &Scene, now: DateTime<Local>) -> Vec<Diagnostic> {
+  let mut issues = vec![];
+  check_scene(home, scene, now, &mut issues);
+  let diagnostic =
+    |i: Issue| Diagnostic { severity: i.severity, message: i.message, location: None };
+  issues.into_iter().map(diagnostic).collect()
+}
+
+fn validate(home: &Home, now: DateTime<Local>) -> Vec<Issue> {
+  let mut issues = vec![];
+  check_duplicates(home, &mut issues);
+  home.flatten_remotes().into_iter().for_each(|r| check_remote(home, r, &mut issues));
+  if let Some(location) = &home.location {
+    check_location(location, &mut issues);
+  }
+  check_health(&home.health, &mut issues);
+  home.scenes.iter().for_each(|s| check_scene(home, s, now, &mut issues));
+  issues
+}
+
+fn check_duplicates(home: &Home, issues: &mut Vec<Issue>) {
+  let rooms: Vec<&str> = home.rooms.iter().map(|r| r.name()).collect();
+  for (ix, name) in rooms.iter().enumerate() {
+    if rooms[..ix].contains(name) {
+      let msg = format!("Room {name} is defined more than once.");
+      issues.push(Issue::error(msg, vec![format!("name: {name}"); 2]));
+    }
+  }
+  let devices = home.flatten_devices();
+  for (ix, device) in devices.iter().enumerate() {
+    let topic = device.topic(TopicMode::Blank);
+    if devices[..ix].iter().any(|d| d.topic(TopicMode::Blank) == topic) {
+      let msg = format!("Device {} is defined more than once in {}.", device.name(), device.room());
+      issues.push(Issue::error(msg, vec![format!("name: {}", device.name()); 2]));
+    }
+  }
+  let scenes: Vec<&str> = home.scenes.iter().map(|s| s.name.as_str()).collect();
+  for (ix, name) in scenes.iter().enumerate() {
+    if scenes[..ix].contains(name) {
+      let msg = format!("Scene {name} is defined more than once.");
+      issues.push(Issue::error(msg, vec![format!("name: {name}"); 2]));
+    }
+  }
+}
+
+fn check_remote(home: &Home, remote: &Remote, issues: &mut Vec<Issue>) {
+  let name = format!("name: {}", remote.name());
+  let model = remote.model();
+  if model.kind() != DeviceKind::Remote {
+    let msg = format!("Remote {} has model {:?}, which is not a remote.", remote.name(), model);
+    issues.push(Issue::error(msg, vec![name.clone(), format!("{model:?}")]));
+  }
+  for target in remote.try_targets() {
+    match target {
+      Ok(topic) if home.find_effective_light(&topic).is_none() => {
+        let msg =
+          format!("Remote {} controls {}, which is not a light.", remote.name(), topic.to_str());
+        issues.push(Issue::error(msg, vec![name.clone(), topic.to_str()]));
+      }
+      Ok(_) => {}
+      Err(_) => {
+        let msg = format!("Remote {} controls an invalid topic.", remote.name());
+        issues.push(Issue::error(msg, vec![name.clone(), String::from("controls:")]));
+      }
+    }
+  }
+  for gesture in remote.gestures() {
+    let key = gesture.to_string();
+    let anchors = vec![name.clone(), String::from("actions:"), key.clone()];
+    for button in gesture.buttons() {
+      if !button.suits(model) {
+        let msg = format!("Remote {} ({model:?}) has no button {button}.", remote.name());
+        issues.push(Issue::error(msg, anchors.clone()));
+      }
+    }
+    if let Gesture::LongPress(button, _) = gesture {
+      if button.release().is_none() {
+        let msg =
+          format!("Remote {} reacts to holding {button}, which is never let go.", remote.name());
+        issues.push(Issue::error(msg, anchors.clone()));
+      }
+    }
+    for action in remote.actions(gesture) {
+      check_button_action(home, remote, &action, &anchors, issues);
+    }
+  }
+}
+
+fn check_button_action(
+  home: &Home,
+  remote: &Remote,
+  action: &ButtonAction,
+  anchors: &[String],
+  issues: &mut Vec<Issue>,
+) {
+  let (modes, remote) = (remote.has_modes(), remote.name());
+  match action {
+    ButtonAction::Command { target: Some(target), .. }
+    | ButtonAction::SetState { target: Some(target), .. }
+    | ButtonAction::SavePreset { target: Some(target), .. }
+    | ButtonAction::ClearOverrides { target: Some(target) }
+      if home.find_effective_light(target).is_none() =>
+    {
+      let msg = format!("Remote {remote} controls {}, which is not a light.", target.to_str());
+      issues.push(Issue::error(msg, anchors.to_vec()));
+    }
+    ButtonAction::Scene { name: scene }
+    | ButtonAction::SetEnabled { scene, .. }
+    | ButtonAction::ToggleEnabled { scene }
+      if !home.scenes.iter().any(|s| &s.name == scene) =>
+    {
+      let msg = format!("Remote {remote} uses scene {scene}, which does not exist.");
+      issues.push(Issue::error(msg, anchors.to_vec()));
+    }
+    ButtonAction::Preset { name } if !home.presets.iter().any(|p| &p.name == name) => {
+      let msg = format!("Remote {remote} restores {name}, which is no preset.");
+      issues.push(Issue::warning(msg, anchors.to_vec()));
+    }
+    ButtonAction::NextMode if !modes => {
+      let msg = format!("Remote {remote} switches modes, but has none.");
+      issues.push(Issue::warning(msg, anchors.to_vec()));
+    }
+    _ => {}
+  }
+}
+
+fn check_scene(home: &Home, scene: &Scene, now: DateTime<Local>, issues: &mut Vec<Issue>) {
+  let name = format!("name: {}", scene.name);
+  if scene.name.contains('.') {
+    // The manager remembers the parts of a trigger under the scene's name followed by dots.
+    let msg =
+      format!("Scene {} has a dot in its name, which scene names may not contain.", scene.name);
+    issues.push(Issue::error(msg, vec![name.clone()]));
+  }
+  check_trigger(home, &scene.name, &name, &scene.trigger, now, issues);
+  check_negations(&scene.name, &name, &scene.trigger, false, issues);
+  check_effect(home, &scene.name, &name, &scene.effect, issues);
+  if scene.effect.steps() > Effect::MAX_STEPS {
+    let msg = format!(
+      "Scene {} takes more than {} steps in one run, counting all repetitions.",
+      scene.name,
+      Effect::MAX_STEPS
+    );
+    issues.push(Issue::error(msg, vec![name]));
+  }
+}
+
+fn check_trigger(
+  home: &Home,
+  scene: &str,
+  name: &str,
+  trigger: &Trigger,
+  now: DateTime<Local>,
+  issues: &mut Vec<Issue>,
+) {
+  match trigger {
+    Trigger::And(a, b) | Trigger::Or(a, b) => {
+      check_trigger(home, scene, name, a, now, issues);
+      check_trigger(home, scene, name, b, now, issues);
+    }
+    Trigger::Not { trigger } => check_trigger(home, scene, name, trigger, now, issues),
+    Trigger::Debounce { trigger, duration } | Trigger::Throttle { trigger, duration } => {
+      check_duration(scene, name, *duration, issues);
+      check_trigger(home, scene, name, trigger, now, issues);
+    }
+    Trigger::Held { target, field, op, duration } => {
+      check_duration(scene, name, *duration, issues);
+      check_comparison(scene, name, op, issues);
+      check_reported(home, scene, name, target, Some(field), issues);
+    }
+    Trigger::Silence { target, duration } => {
+      check_duration(scene, name, *duration, issues);
+      check_reported(home, scene, name, target, None, issues);
+    }
+    Trigger::Health { target: Some(target), .. } if find_device(home, target).is_none() => {
+      let msg =
+        format!("Scene {scene} watches the health of {}, which does not exist.", target.to_str());
+      issues.push(Issue::error(msg, vec![name.to_string(), target.to_str()]));
+    }
+    Trigger::Health { .. } => {}
+    Trigger::Any(triggers) | Trigger::All(triggers) => {
+      triggers.iter().for_each(|t| check_trigger(home, scene, name, t, now, issues));
+    }
+    Trigger::DeviceState(dst) | Trigger::Condition(dst) => {
+      check_comparison(scene, name, &dst.op, issues);
+      check_reported(home, scene, name, &dst.target, Some(&dst.field), issues);
+    }
+    Trigger::Lights { target, check } => {
+      if let LightCheck::Brightness { op } = check {
+        check_comparison(scene, name, op, issues);
+      }
+      if home.find_effective_light(target).is_none() {
+        let msg = format!("Scene {scene} checks {}, which is not a light.", target.to_str());
+        issues.push(Issue::error(msg, vec![name.to_string(), target.to_str()]));
+      }
+    }
+    Trigger::Reading { target, reading, op } => {
+      check_comparison(scene, name, op, issues);
+      let anchors = vec![name.to_string(), target.to_str()];
+      match home.find_sensor(target) {
+        None => {
+          let msg = format!("Scene {scene} reads {}, which is not a sensor.", target.to_str());
+          issues.push(Issue::error(msg, anchors));
+        }
+        Some(sensor) if !sensor.model().capable_of(*reading) => {
+          let msg = format!(
+            "Scene {scene} reads {} of {}, which it does not measure.",
+            reading.field(),
+            target.to_str()
+          );
+          issues.push(Issue::error(msg, anchors));
+        }
+        Some(_) => {}
+      }
+    }
+    Trigger::Schedule(schedule) => {
+      let (key, never) = match &schedule.when {
+        Schedule::Cron(_) => ("cron:", "never matches a date"),
+        Schedule::At(_) => ("at:", "lies in the past"),
+        Schedule::Every(_) => ("every:", "needs an interval of at least one second"),
+      };
+      let anchors = vec![name.to_string(), key.to_string()];
+      if schedule.next_after(now).is_none() {
+        let msg = format!("The schedule of scene {scene} {never}, so it never fires.");
+        match schedule.when {
+          Schedule::Every(_) => issues.push(Issue::error(msg, anchors)),
+          Schedule::Cron(_) | Schedule::At(_) => issues.push(Issue::warning(msg, anchors)),
+        }
+      }
+    }
+    Trigger::Sun(_) | Trigger::SunWindow { .. } if home.location.is_none() => {
+      let msg = format!("Scene {scene} follows the sun, but the home has no location.");
+      issues.push(Issue::error(msg, vec![name.to_string(), String::from("event:")]));
+    }
+    Trigger::Time(_)
+    | Trigger::Sun(_)
+    | Trigger::SunWindow { .. }
+    | Trigger::ManualOnly
+    | Trigger::Script(_) => {}
+  }
+}
+
+/// Checks that the target exists and reports the field, if any.
+fn check_reported(
+  home: &Home,
+  scene: &str,
+  name: &str,
+  target: &Topic,
+  field: Option<&str>,
+  issues: &mut Vec<Issue>,
+) {
+  let anchors = vec![name.to_string(), target.to_str()];
+  match (find_device(home, target), field) {
+    (None, _) => {
+      let msg = format!("Scene {scene} reacts to {}, which does not exist.", target.to_str());
+      issues.push(Issue::error(msg, anchors));
+    }
+    (Some(Device::Remote(_)), _) => {
+      let msg = format!("Scene {scene} reacts to remote {}.", target.to_str());
+      issues.push(Issue::error(msg, anchors));
+    }
+    (Some(device), Some(field)) if !has_field(device, field) => {
+      let msg = format!(
+        "Scene {scene} reads field {field} of {}, which it does not have.",
+        target.to_str()
+      );
+      issues.push(Issue::error(msg, vec![name.to_string(), format!("field: {field}")]));
+    }
+    (Some(_), _) => {}
+  }
+}
+
+/// Warns of negations that never hold because nothing next to them fires the scene.
+fn check_negations(
+  scene: &str,
+  name: &str,
+  trigger: &Trigger,
+  condition: bool,
+  issues: &mut Vec<Issue>,
+) {
+  let all = |triggers: &[&Trigger], issues: &mut Vec<Issue>| {
+    let fires = triggers.iter().any(|t| !matches!(t, Trigger::Not { .. }));
+    triggers.iter().for_each(|t| check_negations(scene, name, t, fires, issues));
+  };
+  match trigger {
+    Trigger::And(a, b) => all(&[a, b], issues),
+    Trigger::All(triggers) => all(&triggers.iter().collect::<Vec<_>>(), issues),
+    Trigger::Or(a, b) => [a, b].iter().for_each(|t| check_negations(scene, name, t, false, issues)),
+    Trigger::Any(triggers) => {
+      triggers.iter().for_each(|t| check_negations(scene, name, t, false, issues));
+    }
+    Trigger::Debounce { trigger, .. } | Trigger::Throttle { trigger, .. } => {
+      check_negations(scene, name, trigger, false, issues);
+    }
+    Trigger::Not { trigger } => {
+      if !condition {
+        let msg = format!(
+          "Scene {scene} negates a trigger outside an And or All with a trigger that fires, so the \
+           negation never holds."
+        );
+        issues.push(Issue::warning(msg, vec![name.to_string(), String::from("!Not")]));
+      }
+      check_negations(scene, name, trigger, false, issues);
+    }
+    _ => {}
+  }
+}
+
+fn check_duration(scene: &str, name: &str, duration: Duration, issues: &mut Vec<Issue>) {
+  if duration <= Duration::zero() {
+    let msg =
+      format!("Scene {scene} waits for {}s, but needs at least a second.", duration.num_seconds());
+    issues.push(Issue::error(msg, vec![name.to_string(), String::from("duration:")]));
+  }
+}
+
+fn check_comparison(scene: &str, name: &str, op: &Comparison, issues: &mut Vec<Issue>) {
+  let anchors = vec![name.to_string(), String::from("op:")];
+  match op {
+    Comparison::Above { hysteresis, .. }
+    | Comparison::Below { hysteresis, .. }
+    | Comparison::Between { hysteresis, .. }
+      if *hysteresis < 0.0 =>
+    {
+      let msg = format!("Scene {scene} uses a negative hysteresis.");
+      issues.push(Issue::error(msg, anchors));
+    }
+    Comparison::Between { min, max, .. } if min > max => {
+      let msg = format!("Scene {scene} compares against the empty range {min} to {max}.");
+      issues.push(Issue::error(msg, anchors));
+    }
+    _ => {}
+  }
+}
+
+fn check_location(location: &GeoLocation, issues: &mut Vec<Issue>) {
+  let anchors = |key: &str| vec![String::from("location:"), key.to_string()];
+  if !(-90.0..=90.0).contains(&location.latitude) {
+    let msg = format!("Latitude {} lies outside -90 to 90 degrees.", location.latitude);
+    issues.push(Issue::error(msg, anchors("latitude:")));
+  }
+  if !(-180.0..=180.0).contains(&location.longitude) {
+    let msg = format!("Longitude {} lies outside -180 to 180 degrees.", location.longitude);
+    issues.push(Issue::error(msg, anchors("longitude:")));
+  }
+}
+
+fn check_health(limits: &HealthLimits, issues: &mut Vec<Issue>) {
+  let anchors = |key: &str| vec![String::from("health:"), key.to_string()];
+  if !(0.0..=100.0).contains(&limits.low_battery) {
+    let msg = format!("A low battery at {}% lies outside 0 to 100%.", limits.low_battery);
+    issues.push(Issue::error(msg, anchors("low_battery:")));
+  }
+  if limits.unseen_hours < 0 {
+    let msg =
+      format!("Devices count as gone after {} hours, which is negative.", limits.unseen_hours);
+    issues.push(Issue::error(msg, anchors("unseen_hours:")));
+  }
+}
+
+fn check_effect(home: &Home, scene: &str, name: &str, effect: &Effect, issues: &mut Vec<Issue>) {
+  match effect {
+    Effect::LightCommand { target, command } => {
+      let anchors = vec![name.to_string(), target.to_str()];
+      if home.find_effective_light(target).is_none() {
+        let msg = format!("Scene {scene} controls {}, which is not a light.", target.to_str());
+        issues.push(Issue::error(msg, anchors));
+      } else if *command == LightCommand::ChangeState {
+        let msg = format!("Scene {scene} uses ChangeState, which does nothing; use SetState.");
+        issues.push(Issue::warning(msg, anchors));
+      }
+    }
+    Effect::SetState { target, state } => {
+      let anchors = vec![name.to_string(), target.to_str()];
+      if home.find_effective_light(target).is_none() {
+        let msg = format!("Scene {scene} controls {}, which is not a light.", target.to_str());
+        issues.push(Issue::error(msg, anchors));
+      } else if *state == TargetState::default() {
+        let msg = format!("Scene {scene} sets an empty state on {}.", target.to_str());
+        issues.push(Issue::warning(msg, anchors));
+      }
+      check_target_state(scene, name, state, issues);
+    }
+    Effect::Delay { duration } if *duration < Duration::zero() => {
+      let msg = format!("Scene {scene} waits for a negative duration.");
+      issues.push(Issue::error(msg, vec![name.to_string(), String::from("duration:")]));
+    }
+    Effect::Delay { .. } => {}
+    Effect::Sequence(effects) | Effect::Parallel(effects) | Effect::And(effects) => {
+      effects.iter().for_each(|e| check_effect(home, scene, name, e, issues))
+    }
+    Effect::Snapshot { target, .. } if home.find_effective_light(target).is_none() => {
+      let msg = format!("Scene {scene} captures {}, which is not a light.", target.to_str());
+      issues.push(Issue::error(msg, vec![name.to_string(), target.to_str()]));
+    }
+    Effect::Snapshot { .. } => {}
+    Effect::Restore { name: preset } if !restorable(home, preset) => {
+      let msg = format!("Scene {scene} restores {preset}, which is neither a preset nor captured.");
+      issues.push(Issue::warning(msg, vec![name.to_string(), format!("name: {preset}")]));
+    }
+    Effect::Restore { .. } => {}
+    Effect::SetEnabled { scene: other, .. } if !home.scenes.iter().any(|s| &s.name == other) => {
+      let msg = format!("Scene {scene} enables or disables {other}, which does not exist.");
+      issues.push(Issue::error(msg, vec![name.to_string(), format!("scene: {other}")]));
+    }
+    Effect::SetEnabled { .. } | Effect::Script(_) => {}
+    Effect::Repeat { times, effect } => {
+      let anchors = vec![name.to_string(), String::from("times:")];
+      if *times == 0 {
+        let msg = format!("Scene {scene} repeats an effect zero times.");
+        issues.push(Issue::warning(msg, anchors));
+      } else if *times > Effect::MAX_REPEAT {
+        let msg =
+          format!("Scene {scene} repeats an effect more than {} times.", Effect::MAX_REPEAT);
+        issues.push(Issue::error(msg, anchors));
+      }
+      check_effect(home, scene, name, effect, issues);
+    }
+  }
+}
+
+/// Whether a preset of that name exists or some scene captures one.
+fn restorable(home: &Home, preset: &str) -> bool {
+  fn captures(effect: &Effect, preset: &str) -> bool {
+    match effect {
+      Effect::Snapshot { name, .. } => name == preset,
+      Effect::Sequence(effects) | Effect::Parallel(effects) | Effect::And(effects) => {
+        effects.iter().any(|e| captures(e, preset))
+      }
+      Effect::Repeat { effect, .. } => captures(effect, preset),
+      Effect::LightCommand { .. }
+      | Effect::SetState { .. }
+      | Effect::Delay { .. }
+      | Effect::Restore { .. }
+      | Effect::SetEnabled { .. }
+      | Effect::Script(_) => false,
+    }
+  }
+  home.presets.iter().any(|p| p.name == preset)
+    || home.scenes.iter().any(|s| captures(&s.effect, preset))
+}
+
+fn check_target_state(scene: &str, name: &str, state: &TargetState, issues: &mut Vec<Issue>) {
+  let ranges = [
+    ("brightness", state.brightness, 100.0),
+    ("hue", state.hue, 360.0),
+    ("saturation", state.saturation, 100.0),
+  ];
+  for (key, value, max) in ranges {
+    if let Some(value) = value.filter(|v| !(0.0..=max).contains(v)) {
+      let msg = format!("Scene {scene} sets {key} to {value}, outside of 0 to {max}.");
+      issues.push(Issue::error(msg, vec![name.to_string(), format!("{key}:")]));
+    }
+  }
+  if state.transition.is_some_and(|t| t < 0) {
+    let msg = format!("Scene {scene} uses a negative transition.");
+    issues.push(Issue::error(msg, vec![name.to_string(), String::from("transition:")]));
+  }
+}
+
+fn find_device<'a>(home: &'a Home, topic: &Topic) -> Option<&'a Device> {
+  home.find_device(&topic.clone().with_mode(TopicMode::Blank))
+}
+
+/// Fields zigbee2mqtt reports for every device, regardless of its capabilities.
+const GENERIC_FIELDS: [&str; 2] = ["battery", "linkquality"];
+
+fn has_field(device: &Device, field: &str) -> bool {
+  GENERIC_FIELDS.contains(&field)
+    || device.model().capabilities().iter().any(|c| c.field() == field)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{check_scene_of, validate, Issue, Location, Severity};
+  use crate::{
+    home::Home,
+    scenes::scene::Scene,
+    simulation::fixture::{self, MOTION},
+  };
+
+  /// What is wrong with the home, as of the start of the tests.
+  fn messages(home: &str) -> Vec<String> {
+    let home: Home = serde_yaml::from_str(home).unwrap();
+    validate(&home, fixture::start()).into_iter().map(|i| i.message).collect()
+  }
+
+  #[test]
+  fn test_names_are_unique() {
+    let day = "{ name: Day, trigger: ManualOnly, effect: !Sequence [] }";
+    assert!(messages(&fixture::yaml(&format!("[{day}]"))).is_empty());
+    let twice = fixture::yaml(&format!("[{day}, {day}]"));
+    assert_eq!(messages(&twice), vec!["Scene Day is defined more than once."]);
+    let home = fixture::yaml("[]").replace("name: Door", "name: Motion");
+    assert_eq!(messages(&home), vec!["Device Motion is defined more than once in Hall."]);
+  }
+
+  #[test]
+  fn test_remote_buttons_suit_the_model() {
+    let home = fixture::yaml("[]").replace(r#""off": TurnOff"#, r#""arrow_left_click": TurnOff"#);
+    let expected = "Remote Dimmer (IkeaDimmer) has no button arrow_left_click.";
+    assert_eq!(messages(&home), vec![expected]);
+  }
+
+  #[test]
+  fn test_scene_targets_exist() {
+    let effect = "!LightCommand { target: zigbee2mqtt/Room/Attic, command: TurnOn }";
+    let home =
+      fixture::yaml(&format!("[{{ name: Attic, trigger: ManualOnly, effect: {effect} }}]"));
+    let expected = "Scene Attic controls zigbee2mqtt/Room/Attic, which is not a light.";
+    assert_eq!(messages(&home), vec![expected]);
+  }
+
+  #[test]
+  fn test_sensor_fields_exist() {
+    let reads = |field: &str| {
+      let trigger =
+        format!("!DeviceState {{ target: {MOTION}, field: {field}, op: !BoolComparison {{ pivot: true }} }}");
+      fixture::yaml(&format!("[{{ name: Humid, trigger: {trigger}, effect: !Sequence [] }}]"))
+    };
+    assert!(messages(&reads("occupancy")).is_empty());
+    let expected = format!("Scene Humid reads field humidity of {MOTION}, which it does not have.");
+    assert_eq!(messages(&reads("humidity")), vec![expected]);
+  }
+
+  #[test]
+  fn test_schedules_lie_ahead() {
+    let at = |time: &str| {
+      let trigger = format!("!Schedule {{ at: \"{time}\" }}");
+      fixture::yaml(&format!("[{{ name: Once, trigger: {trigger}, effect: !Sequence [] }}]"))
+    };
+    // The tests start at two in the afternoon.
+    assert!(messages(&at("2024-03-01T15:00:00")).is_empty());
+    let expected = "The schedule of scene Once lies in the past, so it never fires.";
+    assert_eq!(messages(&at("2024-03-01T13:00:00")), vec![expected]);
+  }
+
+  #[test]
+  fn test_scene_names_have_no_dots() {
+    let home: Home = serde_yaml::from_str("name: Test\nrooms: []\nscenes: []").unwrap();
+    let scene = |name: &str| -> Scene {
+      let yaml = format!("{{ name: {name}, trigger: ManualOnly, effect: !Sequence [] }}");
+      serde_yaml::from_str(&yaml).unwrap()
+    };
+    assert!(check_scene_of(&home, &scene("Hall"), fixture::start()).is_empty());
+    assert!(check_scene_of(&home, &scene("Hall.night"), fixture::start())
+      .iter()
+      .any(|d| d.is_error()));
+  }
+
+  #[test]
+  fn test_nested_repeats_count_together() {
+    let home: Home = serde_yaml::from_str("name: Test\nrooms: []\nscenes: []").unwrap();
+    let scene = |outer: u32, inner: u32| -> Scene {
+      let delay = "!Delay { duration: 1 }";
+      let inner = format!("!Repeat {{ times: {inner}, effect: {delay} }}");
+      let effect = format!("!Repeat {{ times: {outer}, effect: {inner} }}");
+      serde_yaml::from_str(&format!("{{ name: Blink, trigger: ManualOnly, effect: {effect} }}"))
+        .unwrap()
+    };
+    assert!(check_scene_of(&home, &scene(100, 100), fixture::start()).is_empty());
+    // Each level stays within its limit, but not the run.
+    assert!(check_scene_of(&home, &scene(1000, 1000), fixture::start())
+      .iter()
+      .any(|d| d.is_error()));
+  }
+
+  #[test]
+  fn test_locate_follows_anchors() {
+    let source = "scenes:\n  - name: A\n    target: x\n  - name: B\n    target: x\n";
+    let issue = Issue::error(String::new(), vec![String::from("name: B"), String::from("target")]);
+    let diagnostic = issue.locate("home.yml", source);
+    let expected = Location { file: String::from("home.yml"), line: 5, column: 5 };
+    assert_eq!(diagnostic.location, Some(expected));
+    assert_eq!(diagnostic.severity, Severity::Error);
+  }
+
+  #[test]
+  fn test_locate_missing_anchor() {
+    let issue = Issue::warning(String::new(), vec![String::from("nowhere")]);
+    assert_eq!(issue.locate("home.yml", "name: A\n").location, None);
+  }
+
+  #[test]
+  fn test_locate_never_guesses() {
+    let source = "scenes:\n  - name: Hall\n    target: y\n  - name: Hallway\n    target: x\n";
+    let line = |anchors: &[&str]| {
+      let issue = Issue::error(String::new(), anchors.iter().map(|a| a.to_string()).collect());
+      issue.locate("home.yml", source).location.map(|l| l.line)
+    };
+    assert_eq!(line(&["name: Hall", "target"]), Some(3));
+    assert_eq!(line(&["name: Hallway"]), Some(4));
+    // The next scene's target is not Hall's, and an anchor found twice leads nowhere.
+    assert_eq!(line(&["name: Hall", "target: x"]), None);
+    assert_eq!(line(&["target"]), None);
+    assert_eq!(line(&["name: Hall", "name: Hall"]), None);
+    let twice = "rooms:\n  - name: Hall\n  - name: Hall\n";
+    let issue = Issue::error(String::new(), vec![String::from("name: Hall"); 2]);
+    assert_eq!(issue.locate("home.yml", twice).location.map(|l| l.line), Some(3));
+  }
+}
@@ -0,0 +1,34 @@
+#![deny(
+    // missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications,
+    rustdoc::broken_intra_doc_links,
+)]
+#![allow(clippy::diverging_sub_expression)] // Triggered by `guard!`.
+
+use error::HomeBaseError;
+
+pub mod api;
+pub mod cli;
+pub mod clock;
+pub mod common;
+pub mod config;
+pub mod controller;
+pub mod convert;
+pub mod devices;
+pub mod error;
+pub mod home;
+pub mod mqtt;
+pub mod scenes;
+pub mod simulation;
+pub mod watcher;
+pub mod web_server;
+
+pub type Error = HomeBaseError;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,4 +1,4 @@
-use std::{borrow::Borrow, sync::Arc, thread, time::Duration};
+use std::{sync::Arc, thread, time::Duration};
 
 use crate::{
   api::{
@@ -9,28 +9,32 @@ use crate::{
     traits::Addressable,
   },
   convert::StateToMqtt,
-  devices::{
-    remote::{HueButton, IkeaDimmer, IkeaMulti, RemoteButton},
-    Device,
-  },
+  devices::Device,
   scenes::manager::SceneEvent,
-  Result,
+  Error, Result,
 };
-use paho_mqtt::{AsyncClient, AsyncReceiver, CreateOptionsBuilder, Message, QOS_1};
+use futures::StreamExt;
+use guard::guard;
 use serde_json::Value as JsonValue;
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 
+mod backend;
+pub mod recorder;
+
+pub use backend::{MessageStream, MqttBackend, MqttMessage, PahoBackend};
+use recorder::{Direction, SharedRecorder};
+
 #[allow(missing_debug_implementations)]
 pub struct MqttClient {
-  client: AsyncClient,
+  client: Box<dyn MqttBackend>,
   queue: UnboundedSender<Request>,
   scene_events: UnboundedSender<SceneEvent>,
+  recorder: Option<SharedRecorder>,
 }
 
 #[allow(missing_debug_implementations)]
-#[derive(Clone)]
 pub struct MqttReceiver {
-  stream: AsyncReceiver<Option<Message>>,
+  stream: MessageStream,
   client: ProtectedClient,
 }
 
@@ -42,75 +46,105 @@ pub async fn setup_client(
   queue: UnboundedSender<Request>,
   events: UnboundedSender<SceneEvent>,
 ) -> Result<(ProtectedClient, MqttReceiver)> {
-  let url = format!("mqtt://{host}:{port}");
-  let mut client = CreateOptionsBuilder::new().client_id("Mac").server_uri(url).create_client()?;
-  let stream = client.get_stream(None);
-  client.connect(None).await?;
-  let mqtt_client = MqttClient { client, queue, scene_events: events };
+  let (backend, stream) = PahoBackend::connect(host, port, "Mac").await?;
+  Ok(attach(Box::new(backend), stream, queue, events))
+}
+
+/// Wires an established connection to the request queue and the scene events.
+pub fn attach(
+  backend: Box<dyn MqttBackend>,
+  stream: MessageStream,
+  queue: UnboundedSender<Request>,
+  events: UnboundedSender<SceneEvent>,
+) -> (ProtectedClient, MqttReceiver) {
+  let mqtt_client = MqttClient { client: backend, queue, scene_events: events, recorder: None };
   let protected = Arc::new(Mutex::new(mqtt_client));
   let receiver = MqttReceiver { stream, client: protected.clone() };
-  Ok((protected, receiver))
+  (protected, receiver)
 }
 
 impl MqttReceiver {
-  pub async fn run(self) -> Result<()> {
+  pub async fn run(mut self) -> Result<()> {
     println!("Starting to receive.");
-    loop {
-      let msg = self.stream.recv().await;
+    while let Some(msg) = self.stream.next().await {
       match msg {
-        Ok(None) | Err(_) => {} //self.client.lock().await.attempt_reconnect().await,
-        Ok(Some(msg)) => self.client.lock().await.handle_message(msg).await,
+        None => {} //self.client.lock().await.attempt_reconnect().await,
+        Some(msg) => self.client.lock().await.handle_message(msg).await,
       }
     }
+    Err(Error::ConnectionLost)
   }
 }
 
 impl MqttClient {
+  /// Captures all traffic from now on.
+  pub fn record_to(&mut self, recorder: SharedRecorder) {
+    self.recorder = Some(recorder);
+  }
+
+  fn record(&self, direction: Direction, msg: &MqttMessage) {
+    if let Some(recorder) = &self.recorder {
+      recorder.lock().unwrap().record(direction, msg);
+    }
+  }
+
   pub async fn publish(&self, topic: Topic, payload: StateToMqtt) {
     assert_ne!(topic.mode(), TopicMode::Blank);
     let payload = payload.to_json_str(false);
     println!("Sent: {} to {}", &payload, topic.to_str());
-    let msg = Message::new(topic.to_str(), payload, QOS_1);
+    let msg = MqttMessage::new(topic.to_str(), payload);
+    self.record(Direction::Out, &msg);
     if self.client.publish(msg).await.is_err() {
       eprintln!("Failed to publish message.");
     }
   }
 
-  async fn handle_message(&self, msg: Message) {
-    println!("Handling a message. \n{msg}");
-    let target = msg.topic();
-    let target = Topic::try_from(target.to_string()).unwrap();
-    let payload: JsonValue = serde_json::from_str(msg.payload_str().borrow()).unwrap();
+  async fn handle_message(&self, msg: MqttMessage) {
+    println!("Handling a message. \n{}: {}", msg.topic, msg.payload);
+    self.record(Direction::In, &msg);
+    guard!(let Ok(target) = Topic::try_from(msg.topic) else {
+      println!("Received message on unknown topic.  Ignored.");
+      return;
+    });
     if target.kind() == TopicKind::Bridge {
-      println!("Received bridge event.  Ignored.")
-    } else if let Some(action) = payload.get("action") {
-      println!(
-        "Received remote action: {:?}",
-        serde_json::from_value::<IkeaDimmer>(action.clone())
-      );
-      if let Ok(dimmer) = serde_json::from_value::<IkeaDimmer>(action.clone()) {
-        let ra = RemoteAction { button: RemoteButton::IkeaDimmer(dimmer), target };
-        self.queue.send(Request::RemoteAction(ra)).unwrap();
-      } else if let Ok(multi) = serde_json::from_value::<IkeaMulti>(action.clone()) {
-        let ra = RemoteAction { button: RemoteButton::IkeaMulti(multi), target };
-        self.queue.send(Request::RemoteAction(ra)).unwrap();
-      } else if let Ok(multi) = serde_json::from_value::<HueButton>(action.clone()) {
-        let ra = RemoteAction { button: RemoteButton::HueButton(multi), target };
-        self.queue.send(Request::RemoteAction(ra)).unwrap();
+      println!("Received bridge event.  Ignored.");
+      return;
+    }
+    guard!(let Ok(payload) = serde_json::from_str::<JsonValue>(&msg.payload) else {
+      eprintln!("Received no JSON on {}: {}  Ignored.", target.to_str(), msg.payload);
+      return;
+    });
+    if let Some(action) = payload.get("action").and_then(JsonValue::as_str) {
+      println!("Received remote action: {action}");
+      // Remotes report their battery along with the action.
+      if !self.update_state(&target, &payload) {
+        return;
       }
+      let ra = RemoteAction { action: action.to_string(), target };
+      self.queue.send(Request::RemoteAction(ra)).unwrap();
     } else if target.device().is_some() {
       println!("Received device state update");
-      let parsed = serde_json::from_value(payload.clone()).unwrap();
-      let req = Request::DeviceCommand(DeviceCommand::UpdateState(parsed), target.clone());
-      self.queue.send(req).expect("Error handling.");
-      self.scene_events.send(SceneEvent::SensorUpdate(target, payload)).expect("Error handling.");
+      if self.update_state(&target, &payload) {
+        self.scene_events.send(SceneEvent::SensorUpdate(target, payload)).expect("Error handling.");
+      }
     }
   }
 
+  /// Hands the state a device reported to the executor.  Returns whether the payload was a state.
+  fn update_state(&self, target: &Topic, payload: &JsonValue) -> bool {
+    guard!(let Ok(state) = serde_json::from_value(payload.clone()) else {
+      eprintln!("Received no state on {}: {payload}  Ignored.", target.to_str());
+      return false;
+    });
+    let req = Request::DeviceCommand(DeviceCommand::UpdateState(state), target.clone());
+    self.queue.send(req).expect("Error handling.");
+    true
+  }
+
   #[allow(dead_code)]
   async fn attempt_reconnect(&self) {
     println!("Detected disconnect.  Attempting to reconnect now.");
-    while self.client.connect(None).await.is_err() {
+    while self.client.reconnect().await.is_err() {
       eprintln!("Failed to reconnect. Retrying....");
       thread::sleep(Duration::from_secs(3));
     }
@@ -133,12 +167,42 @@ impl MqttClient {
   }
 
   async fn subscribe_to(&self, topic: Topic) {
-    let _ = self.client.subscribe(&topic.to_str(), QOS_1).await;
+    let _ = self.client.subscribe(topic.to_str()).await;
+  }
+
+  pub async fn unsubscribe_from_all<I: IntoIterator<Item = Topic>>(&self, topics: I) {
+    for topic in topics {
+      println!("Unsubscribing from {}", &topic.to_str());
+      let _ = self.client.unsubscribe(topic.to_str()).await;
+    }
   }
 
   pub async fn disconnect(&self) {
-    if let Err(err) = self.client.disconnect(None).await {
-      eprintln!("Failed to disconnect with error: {err}.")
+    if let Err(err) = self.client.disconnect().await {
+      eprintln!("Failed to disconnect with error: {err:?}.")
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use futures::{stream, StreamExt};
+  use tokio::sync::mpsc::unbounded_channel;
+
+  use super::{attach, MqttMessage};
+  use crate::simulation::{fixture::FLOOR, FakeBroker};
+
+  #[tokio::test]
+  async fn test_payloads_that_are_no_state_are_ignored() {
+    let (connection, _) = FakeBroker::new().connect();
+    let (queue, mut requests) = unbounded_channel();
+    let (events, mut updates) = unbounded_channel();
+    let (client, _) = attach(Box::new(connection), stream::empty().boxed(), queue, events);
+    let client = client.lock().await;
+    for payload in ["offline", "42", r#"{ "state": "ON" }"#] {
+      client.handle_message(MqttMessage::new(FLOOR.to_string(), payload.to_string())).await;
     }
+    assert_eq!(std::iter::from_fn(|| requests.try_recv().ok()).count(), 1);
+    assert_eq!(std::iter::from_fn(|| updates.try_recv().ok()).count(), 1);
   }
 }
@@ -0,0 +1,72 @@
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
+use paho_mqtt::{AsyncClient, CreateOptionsBuilder, Message, QOS_1};
+
+use crate::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+  pub topic: String,
+  pub payload: String,
+}
+
+impl MqttMessage {
+  pub fn new(topic: String, payload: String) -> Self {
+    MqttMessage { topic, payload }
+  }
+}
+
+/// Incoming messages.  `None` signals a lost connection.
+pub type MessageStream = BoxStream<'static, Option<MqttMessage>>;
+
+/// The connection to a broker.  Implemented by paho's client and by the in-process broker the
+/// simulation uses for tests.
+pub trait MqttBackend: Send + Sync {
+  fn publish(&self, msg: MqttMessage) -> BoxFuture<'_, Result<()>>;
+  fn subscribe(&self, topic: String) -> BoxFuture<'_, Result<()>>;
+  fn unsubscribe(&self, topic: String) -> BoxFuture<'_, Result<()>>;
+  fn reconnect(&self) -> BoxFuture<'_, Result<()>>;
+  fn disconnect(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+#[allow(missing_debug_implementations)]
+pub struct PahoBackend {
+  client: AsyncClient,
+}
+
+impl PahoBackend {
+  pub async fn connect(host: &str, port: u16, id: &str) -> Result<(Self, MessageStream)> {
+    let url = format!("mqtt://{host}:{port}");
+    let mut client = CreateOptionsBuilder::new().client_id(id).server_uri(url).create_client()?;
+    let stream = client
+      .get_stream(None)
+      .map(|msg: Option<Message>| {
+        msg.map(|m| MqttMessage::new(m.topic().to_string(), m.payload_str().to_string()))
+      })
+      .boxed();
+    client.connect(None).await?;
+    Ok((PahoBackend { client }, stream))
+  }
+}
+
+impl MqttBackend for PahoBackend {
+  fn publish(&self, msg: MqttMessage) -> BoxFuture<'_, Result<()>> {
+    let msg = Message::new(msg.topic, msg.payload, QOS_1);
+    self.client.publish(msg).map(|r| r.map_err(Into::into)).boxed()
+  }
+
+  fn subscribe(&self, topic: String) -> BoxFuture<'_, Result<()>> {
+    self.client.subscribe(topic, QOS_1).map(|r| r.map(|_| ()).map_err(Into::into)).boxed()
+  }
+
+  fn unsubscribe(&self, topic: String) -> BoxFuture<'_, Result<()>> {
+    self.client.unsubscribe(topic).map(|r| r.map(|_| ()).map_err(Into::into)).boxed()
+  }
+
+  fn reconnect(&self) -> BoxFuture<'_, Result<()>> {
+    self.client.reconnect().map(|r| r.map(|_| ()).map_err(Into::into)).boxed()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+    self.client.disconnect(None).map(|r| r.map(|_| ()).map_err(Into::into)).boxed()
+  }
+}
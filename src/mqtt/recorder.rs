@@ -0,0 +1,160 @@
+use std::{
+  fs::{self, File, OpenOptions},
+  io::{self, Write},
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::SharedClock, config::RecordConfig, Result};
+
+use super::MqttMessage;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+  In,
+  Out,
+}
+
+/// One captured message, stored as one JSON line.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Recording {
+  pub time: DateTime<Local>,
+  pub direction: Direction,
+  pub topic: String,
+  pub payload: String,
+}
+
+impl Recording {
+  pub fn message(&self) -> MqttMessage {
+    MqttMessage::new(self.topic.clone(), self.payload.clone())
+  }
+}
+
+/// Writes all traffic to `<dir>/mqtt.jsonl`.  Once the file exceeds the configured size, it moves
+/// to `mqtt.1.jsonl`, older captures move up by one and the oldest beyond the limit is dropped.
+#[derive(Debug)]
+pub struct Recorder {
+  dir: PathBuf,
+  max_bytes: u64,
+  keep: usize,
+  file: Option<File>,
+  written: u64,
+  /// Stamps the recordings, so that a replay sees the times the controller saw.
+  clock: SharedClock,
+}
+
+pub type SharedRecorder = Arc<Mutex<Recorder>>;
+
+impl Recorder {
+  const NAME: &'static str = "mqtt";
+
+  pub fn new(config: &RecordConfig, clock: SharedClock) -> Result<Self> {
+    fs::create_dir_all(&config.dir)?;
+    let mut recorder = Recorder {
+      dir: PathBuf::from(&config.dir),
+      max_bytes: config.max_kb * 1024,
+      keep: config.keep,
+      file: None,
+      written: 0,
+      clock,
+    };
+    recorder.open()?;
+    Ok(recorder)
+  }
+
+  pub fn shared(self) -> SharedRecorder {
+    Arc::new(Mutex::new(self))
+  }
+
+  /// Path of the current capture (`index` 0) or of a rotated one.
+  pub fn path(&self, index: usize) -> PathBuf {
+    match index {
+      0 => self.dir.join(format!("{}.jsonl", Self::NAME)),
+      n => self.dir.join(format!("{}.{n}.jsonl", Self::NAME)),
+    }
+  }
+
+  pub fn record(&mut self, direction: Direction, msg: &MqttMessage) {
+    let recording = Recording {
+      time: self.clock.now(),
+      direction,
+      topic: msg.topic.clone(),
+      payload: msg.payload.clone(),
+    };
+    if let Err(err) = self.write(&recording) {
+      eprintln!("Failed to record message: {err:?}.");
+    }
+  }
+
+  fn write(&mut self, recording: &Recording) -> io::Result<()> {
+    if self.written >= self.max_bytes {
+      self.rotate()?;
+    }
+    let mut line = serde_json::to_string(recording)?;
+    line.push('\n');
+    if let Some(file) = &mut self.file {
+      file.write_all(line.as_bytes())?;
+      self.written += line.len() as u64;
+    }
+    Ok(())
+  }
+
+  fn open(&mut self) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(self.path(0))?;
+    self.written = file.metadata()?.len();
+    self.file = Some(file);
+    Ok(())
+  }
+
+  fn rotate(&mut self) -> io::Result<()> {
+    self.file = None;
+    let _ = fs::remove_file(self.path(self.keep)); // May not exist yet.
+    for index in (0..self.keep).rev() {
+      if self.path(index).exists() {
+        fs::rename(self.path(index), self.path(index + 1))?;
+      }
+    }
+    self.open()
+  }
+
+  /// Reads captures in the given order.  Lines that do not parse are skipped.
+  pub fn read(paths: &[String]) -> Result<Vec<Recording>> {
+    let mut recordings = vec![];
+    for path in paths {
+      let content = fs::read_to_string(path)?;
+      recordings.extend(content.lines().filter_map(|l| serde_json::from_str(l).ok()));
+    }
+    Ok(recordings)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{clock::SystemClock, config::RecordConfig, mqtt::MqttMessage};
+
+  use super::{Direction, Recorder};
+
+  #[test]
+  fn test_rotation() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_capture_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = RecordConfig { dir: dir.to_string_lossy().to_string(), max_kb: 1, keep: 2 };
+    let mut recorder = Recorder::new(&config, SystemClock::shared()).unwrap();
+    let msg = MqttMessage::new(String::from("zigbee2mqtt/Room/Hall/set"), "x".repeat(300));
+    for _ in 0..12 {
+      recorder.record(Direction::Out, &msg);
+    }
+    assert!(recorder.path(1).exists() && recorder.path(2).exists());
+    assert!(!recorder.path(3).exists());
+    let paths: Vec<String> =
+      (0..3).rev().map(|i| recorder.path(i).to_string_lossy().to_string()).collect();
+    let recordings = Recorder::read(&paths).unwrap();
+    assert!(recordings.len() < 12);
+    assert!(recordings.iter().all(|r| r.direction == Direction::Out && r.message() == msg));
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -1,22 +1,42 @@
-use std::rc::Rc;
+use std::{
+  collections::HashMap,
+  fmt::{Display, Formatter},
+  rc::Rc,
+  time::Duration as StdDuration,
+};
 
-use chrono::{Duration, Local, NaiveTime};
-use futures::future::join_all;
+use chrono::{DateTime, Duration, Local, NaiveTime};
 use guard::guard;
-use serde_json::Value as JsonValue;
-use tokio::sync::{
-  mpsc::{UnboundedReceiver, UnboundedSender},
-  Mutex,
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use tokio::{
+  select,
+  sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    oneshot::Sender,
+    Mutex,
+  },
+  time::{interval, MissedTickBehavior},
 };
 
 use crate::{
   api::{
-    request::{LightCommand, Request},
-    topic::Topic,
+    payload::JsonPayload,
+    request::{LightCommand, Request, SceneCommand},
+    topic::{Topic, TopicMode},
+    traits::{Addressable, DeviceCollection, EffectiveLightCollection},
   },
-  convert::RestApiPayload,
+  clock::SharedClock,
+  convert::{Origin, RestApiPayload},
+  devices::{DeviceTrait, HealthIssue},
   home::Home,
-  scenes::scene::*,
+  scenes::{
+    preset::Preset,
+    program::{Emitted, Env},
+    scene::*,
+    schedule::{RunLog, Scheduler},
+    sun::{self, GeoLocation},
+  },
   Result,
 };
 
@@ -25,12 +45,102 @@ pub struct SceneManager {
   home: Rc<Mutex<Home>>,
   queue: UnboundedSender<Request>,
   receiver: UnboundedReceiver<SceneEvent>,
+  clock: SharedClock,
+  scheduler: Scheduler,
+  tick: StdDuration,
+  memory: TriggerMemory,
+  /// Requests of running effects that wait for a delay, by scene.
+  pending: HashMap<String, Vec<(DateTime<Local>, Request)>>,
+  /// Issues of devices that were reported already, until the devices recover.
+  unhealthy: Vec<(Topic, HealthIssue)>,
+  /// Where the last runs of the scenes are logged, if anywhere.
+  run_log: Option<String>,
 }
 
+/// Requests of an effect with their offsets from the start of the effect.
+type Plan = Vec<(Duration, Request)>;
+
+/// What triggers remember between evaluations, keyed by scene and position in the trigger.
 #[derive(Debug, Clone)]
+struct TriggerMemory {
+  /// Whether each comparison held when it was last evaluated.  Needed for edges and hysteresis.
+  held: HashMap<String, bool>,
+  /// When running countdowns elapse.
+  deadlines: HashMap<String, DateTime<Local>>,
+  /// When throttled triggers last let an activation through.
+  passed: HashMap<String, DateTime<Local>>,
+  /// Silence counts from here until the target first reports.
+  started: DateTime<Local>,
+}
+
+impl TriggerMemory {
+  fn new(started: DateTime<Local>) -> Self {
+    let (held, deadlines, passed) = (HashMap::new(), HashMap::new(), HashMap::new());
+    TriggerMemory { held, deadlines, passed, started }
+  }
+
+  /// Removes and returns the countdowns that elapsed by `now`.
+  fn elapsed(&mut self, now: DateTime<Local>) -> Vec<String> {
+    let elapsed: Vec<String> =
+      self.deadlines.iter().filter(|(_, at)| **at <= now).map(|(path, _)| path.clone()).collect();
+    elapsed.iter().for_each(|path| _ = self.deadlines.remove(path));
+    elapsed
+  }
+
+  /// Drops what the triggers of the scene remember.
+  fn forget(&mut self, scene: &str) {
+    let of_scene = |path: &String| path == scene || path.starts_with(&format!("{scene}."));
+    self.held.retain(|path, _| !of_scene(path));
+    self.deadlines.retain(|path, _| !of_scene(path));
+    self.passed.retain(|path, _| !of_scene(path));
+  }
+}
+
+#[derive(Debug)]
 pub enum SceneEvent {
   SensorUpdate(Topic, JsonValue),
   ManualTrigger(String),
+  /// A schedule of the named scene came due.
+  Scheduled(String),
+  /// The countdown of the trigger at the given path elapsed.
+  Elapsed(String),
+  /// The device turned unhealthy.
+  Health(Topic, HealthIssue),
+  /// The named scene was created, updated or deleted.
+  Redefined(String),
+  /// Asks how the named scene would react to the event, or to the clock if there is none, at the
+  /// given time or now.  Nothing is run.
+  Explain {
+    scene: String,
+    event: Option<Box<SceneEvent>>,
+    at: Option<DateTime<Local>>,
+    reply: Sender<JsonPayload>,
+  },
+}
+
+impl Display for SceneEvent {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SceneEvent::SensorUpdate(topic, payload) => write!(f, "{} sent {payload}", topic.to_str()),
+      SceneEvent::ManualTrigger(_) => write!(f, "Triggered by hand"),
+      SceneEvent::Scheduled(_) => write!(f, "Schedule came due"),
+      SceneEvent::Elapsed(path) => write!(f, "Countdown of {path} elapsed"),
+      SceneEvent::Health(topic, issue) => write!(f, "{} {issue}", topic.to_str()),
+      SceneEvent::Redefined(name) => write!(f, "Scene {name} was redefined"),
+      SceneEvent::Explain { scene, .. } => write!(f, "Explaining scene {scene}"),
+    }
+  }
+}
+
+/// How a trigger evaluated, with the triggers it is made of.
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+  pub trigger: &'static str,
+  pub active: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub children: Vec<Explanation>,
 }
 
 impl SceneManager {
@@ -38,52 +148,627 @@ impl SceneManager {
     home: Rc<Mutex<Home>>,
     queue: UnboundedSender<Request>,
     receiver: UnboundedReceiver<SceneEvent>,
+    clock: SharedClock,
   ) -> Self {
-    Self { home, queue, receiver }
+    let scheduler = Scheduler::new(clock.now());
+    let memory = TriggerMemory::new(clock.now());
+    let pending = HashMap::new();
+    let (tick, unhealthy, run_log) = (Self::TICK, vec![], None);
+    Self { home, queue, receiver, clock, scheduler, tick, memory, pending, unhealthy, run_log }
+  }
+
+  /// How often schedules are checked.
+  const TICK: StdDuration = StdDuration::from_secs(1);
+
+  pub fn with_tick(mut self, tick: StdDuration) -> Self {
+    self.tick = tick;
+    self
+  }
+
+  /// Reads the last runs from the log at `path` on startup and keeps it up to date.
+  pub fn with_run_log(mut self, path: String) -> Self {
+    self.run_log = Some(path);
+    self
   }
 
   pub async fn run(mut self) -> Result<()> {
+    self.catch_up().await;
+    let mut ticks = interval(self.tick);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
     loop {
-      let event = self.receiver.recv().await.unwrap();
-      let home = self.home.lock().await;
-      join_all(home.scenes.iter().map(|scene| async {
-        let se = SceneEvaluator { _home: &home, event: &event };
-        se.eval_sensor_update(scene).await.into_iter().for_each(|r| self.queue.send(r).unwrap())
-      }))
-      .await;
+      select! {
+        event = self.receiver.recv() => self.handle(event.unwrap()).await,
+        _ = ticks.tick() => {
+          self.run_schedules().await;
+          self.run_countdowns().await;
+          self.run_pending().await;
+          self.run_health_checks().await;
+        }
+      }
+    }
+  }
+
+  /// Fires the scenes whose schedules came due since the last tick.
+  async fn run_schedules(&mut self) {
+    let now = self.clock.now();
+    let mut home = self.home.lock().await;
+    let due = self.scheduler.due(&home.scenes, home.location.as_ref(), now);
+    self.mark_run(&mut home, &due, now);
+    drop(home);
+    for name in due {
+      self.handle(SceneEvent::Scheduled(name)).await;
+    }
+  }
+
+  /// Fires the triggers whose countdowns elapsed since the last tick.
+  async fn run_countdowns(&mut self) {
+    for path in self.memory.elapsed(self.clock.now()) {
+      self.handle(SceneEvent::Elapsed(path)).await;
+    }
+  }
+
+  /// Sends the requests of running effects whose delays passed.
+  async fn run_pending(&mut self) {
+    let now = self.clock.now();
+    let mut home = self.home.lock().await;
+    let mut sent = vec![];
+    for (name, steps) in self.pending.iter_mut() {
+      let (due, later): (Vec<_>, Vec<_>) =
+        std::mem::take(steps).into_iter().partition(|(at, _)| *at <= now);
+      *steps = later;
+      // What is sent later belongs to the latest run of the scene.
+      let mut run = SceneRun::new(now, String::new());
+      for (_, request) in due {
+        Self::dispatch(&self.queue, &home, now, request, &mut run);
+      }
+      sent.push((name.clone(), run));
+    }
+    self.pending.retain(|_, steps| !steps.is_empty());
+    for (name, run) in sent {
+      let scene = home.scenes.iter_mut().find(|s| s.name == name);
+      if let Some(latest) = scene.and_then(|s| s.history.back_mut()) {
+        latest.effects.extend(run.effects);
+        latest.errors.extend(run.errors);
+      }
+    }
+  }
+
+  /// Fires an event for every issue a device developed since the last tick.
+  async fn run_health_checks(&mut self) {
+    let now = self.clock.now();
+    let home = self.home.lock().await;
+    let mut unhealthy = vec![];
+    for device in home.flatten_devices() {
+      let topic = device.topic(TopicMode::Blank);
+      for issue in device.health().issues(&home.health, self.memory.started, now) {
+        unhealthy.push((topic.clone(), issue));
+      }
+    }
+    drop(home);
+    let new: Vec<_> = unhealthy.iter().filter(|u| !self.unhealthy.contains(u)).cloned().collect();
+    self.unhealthy = unhealthy;
+    for (topic, issue) in new {
+      println!("{} {issue}.", topic.to_str());
+      self.handle(SceneEvent::Health(topic, issue)).await;
+    }
+  }
+
+  /// Sends a request of a scene, leaving out lights that were controlled manually, and records it
+  /// in the run.
+  fn dispatch(
+    queue: &UnboundedSender<Request>,
+    home: &Home,
+    now: DateTime<Local>,
+    request: Request,
+    run: &mut SceneRun,
+  ) {
+    let (requests, skipped) = Self::skip_overridden(home, now, request);
+    for light in skipped {
+      println!("Leaving {} to manual control.", light.to_str());
+      run.errors.push(format!("Left {} to manual control.", light.to_str()));
+    }
+    for request in requests {
+      run.effects.push(Self::describe(&request));
+      queue.send(request).unwrap();
+    }
+  }
+
+  /// The request without the lights that are overridden, and those lights.
+  fn skip_overridden(
+    home: &Home,
+    now: DateTime<Local>,
+    request: Request,
+  ) -> (Vec<Request>, Vec<Topic>) {
+    let lights = Self::lights_of(home, &request);
+    let (skipped, kept): (Vec<Topic>, Vec<Topic>) =
+      lights.into_iter().partition(|light| home.is_overridden(light, now));
+    if skipped.is_empty() {
+      return (vec![request], vec![]);
+    }
+    (Self::narrow(request, kept), skipped)
+  }
+
+  /// The lights the request controls.  Snapshots only read the lights, so they take none.
+  fn lights_of(home: &Home, request: &Request) -> Vec<Topic> {
+    let saved = |preset: &Preset| preset.lights.iter().map(|s| s.light.clone()).collect();
+    match request {
+      Request::LightCommand(_, RestApiPayload { topic: Some(target), .. }) => {
+        let light = home.find_effective_light(target);
+        light.map(|l| l.snapshot().into_iter().map(|s| s.light).collect()).unwrap_or_default()
+      }
+      Request::SceneCommand(SceneCommand::RestorePreset(name)) => {
+        home.preset(name).map(saved).unwrap_or_default()
+      }
+      Request::SceneCommand(SceneCommand::RestoreLights { lights, .. }) => lights.clone(),
+      _ => vec![],
+    }
+  }
+
+  /// The request for only some of the lights it controls.
+  fn narrow(request: Request, lights: Vec<Topic>) -> Vec<Request> {
+    match request {
+      Request::LightCommand(command, payload) => {
+        let payload = |light| RestApiPayload { topic: Some(light), ..payload.clone() };
+        lights.into_iter().map(|light| Request::LightCommand(command, payload(light))).collect()
+      }
+      Request::SceneCommand(
+        SceneCommand::RestorePreset(name) | SceneCommand::RestoreLights { name, .. },
+      ) if !lights.is_empty() => {
+        vec![Request::SceneCommand(SceneCommand::RestoreLights { name, lights })]
+      }
+      _ => vec![],
+    }
+  }
+
+  /// Makes up for runs missed while the controller was down, as far as the scenes ask for it.
+  async fn catch_up(&mut self) {
+    let now = self.clock.now();
+    let mut home = self.home.lock().await;
+    if let Some(path) = &self.run_log {
+      match RunLog::read(path) {
+        Ok(log) => log.restore(&mut home.scenes),
+        Err(err) => eprintln!("Cannot read the last runs from {path}: {err:?}"),
+      }
+    }
+    let missed = Scheduler::missed(&home.scenes, home.location.as_ref(), now);
+    self.mark_run(&mut home, &missed, now);
+    drop(home);
+    for name in missed {
+      println!("Making up for a missed run of scene {name}.");
+      self.handle(SceneEvent::Scheduled(name)).await;
+    }
+  }
+
+  /// Disabled scenes do not run, so their last run stays as it was.
+  fn mark_run(&self, home: &mut Home, names: &[String], now: DateTime<Local>) {
+    let scenes = home.scenes.iter_mut().filter(|s| s.enabled && names.contains(&s.name));
+    scenes.for_each(|s| s.last_run = Some(now));
+    guard!(let Some(path) = &self.run_log else { return });
+    if names.is_empty() {
+      return;
+    }
+    if let Err(err) = RunLog::of(&home.scenes).write(path) {
+      eprintln!("Cannot log the last runs to {path}: {err:?}");
+    }
+  }
+
+  async fn handle(&mut self, event: SceneEvent) {
+    match event {
+      SceneEvent::Redefined(name) => {
+        // The old definition neither finishes its run nor leaves state to the new one.
+        self.memory.forget(&name);
+        self.pending.remove(&name);
+        return;
+      }
+      SceneEvent::Explain { scene, event, at, reply } => {
+        let explanation = self.explain(&scene, event.map(|e| *e), at).await;
+        reply.send(explanation).expect("Failed to send response.");
+        return;
+      }
+      _ => {}
+    }
+    let mut home = self.home.lock().await;
+    let now = self.clock.now();
+    let mut runs = vec![];
+    for scene in home.scenes.iter().filter(|s| s.enabled) {
+      let memory = &mut self.memory;
+      let mut se = SceneEvaluator {
+        home: &home,
+        event: &event,
+        now,
+        memory,
+        trace: None,
+        note: None,
+        condition: false,
+      };
+      if let Some(plan) = se.eval_sensor_update(scene) {
+        runs.push((scene, plan));
+      }
+    }
+    let mut records = vec![];
+    for (scene, plan, lost) in Self::resolve_conflicts(&home, runs) {
+      let mut run = SceneRun::new(now, event.to_string());
+      run.errors = lost;
+      // A new run of the scene cancels what is left of the previous one.
+      let cancelled = self.pending.remove(&scene.name).map(|steps| steps.len());
+      if cancelled.is_some() {
+        println!("Scene {} was triggered again before it completed.", scene.name);
+      }
+      let (immediate, later): (Plan, Plan) =
+        plan.into_iter().partition(|(offset, _)| *offset <= Duration::zero());
+      for (_, request) in immediate {
+        Self::dispatch(&self.queue, &home, now, request, &mut run);
+      }
+      if !later.is_empty() {
+        let later = later.into_iter().map(|(offset, r)| (now + offset, r)).collect();
+        self.pending.insert(scene.name.clone(), later);
+      }
+      records.push((scene.name.clone(), run, cancelled));
+    }
+    for (name, run, cancelled) in records {
+      guard!(let Some(scene) = home.scenes.iter_mut().find(|s| s.name == name) else { continue });
+      if let (Some(cancelled), Some(previous)) = (cancelled, scene.history.back_mut()) {
+        previous
+          .errors
+          .push(format!("Cancelled by a new run with {cancelled} requests left to send."));
+      }
+      scene.last_triggered = Some(now);
+      scene.record(run);
+    }
+  }
+
+  /// Evaluates the scene like `handle` would, but on a copy of what the triggers remember and
+  /// without sending anything.  Conflicts with other scenes are left out.
+  async fn explain(
+    &self,
+    name: &str,
+    event: Option<SceneEvent>,
+    at: Option<DateTime<Local>>,
+  ) -> JsonPayload {
+    let home = self.home.lock().await;
+    let now = at.unwrap_or_else(|| self.clock.now());
+    guard!(let Some(scene) = home.scenes.iter().find(|s| s.name == name) else {
+      return JsonPayload::from(&json!({ "error": format!("Scene {name} does not exist.") }));
+    });
+    let event = event.unwrap_or_else(|| Self::clock_event(scene, home.location.as_ref(), now));
+    let mut memory = self.memory.clone();
+    let (trace, note, condition) = (Some(vec![]), None, false);
+    let mut se = SceneEvaluator {
+      home: &home,
+      event: &event,
+      now,
+      memory: &mut memory,
+      trace,
+      note,
+      condition,
+    };
+    let active = se.evaluate_trigger(&scene.name, &scene.trigger, &scene.name);
+    let trigger = se.trace.take().and_then(|mut trace| trace.pop());
+    let mut plan = vec![];
+    if active {
+      se.plan_effect(&scene.effect, Duration::zero(), &mut plan);
+      plan.sort_by_key(|(offset, _)| *offset);
+    }
+    let mut requests = vec![];
+    for (offset, request) in plan {
+      let seconds = offset.num_milliseconds() as f64 / 1000.0;
+      for request in Self::skip_overridden(&home, now + offset, request).0 {
+        requests.push(json!({ "offset": seconds, "request": Self::describe(&request) }));
+      }
+    }
+    JsonPayload::from(&json!({
+      "scene": scene.name,
+      "enabled": scene.enabled,
+      "event": event.to_string(),
+      "at": now.to_rfc3339(),
+      "active": active,
+      "trigger": trigger,
+      "requests": requests,
+    }))
+  }
+
+  /// What the clock brings the scene at `now`: a run of its schedule if one is due within the
+  /// minute, otherwise an event that only lets conditions and time windows hold.
+  fn clock_event(
+    scene: &Scene,
+    location: Option<&GeoLocation>,
+    now: DateTime<Local>,
+  ) -> SceneEvent {
+    let since = now - Duration::minutes(1);
+    let timers = scene.trigger.timers();
+    if timers.iter().any(|t| t.next_after(since, location).is_some_and(|at| at <= now)) {
+      return SceneEvent::Scheduled(scene.name.clone());
     }
+    SceneEvent::Elapsed(String::new()) // No countdown has an empty path.
+  }
+
+  fn describe(request: &Request) -> JsonValue {
+    match request {
+      Request::LightCommand(command, payload) => json!({
+        "LightCommand": {
+          "command": command,
+          "target": payload.topic,
+          "on": payload.on,
+          "value": payload.val.map(|v| v.to_rest().inner()),
+          "hue": payload.hue.map(|h| h.to_rest().inner()),
+          "saturation": payload.sat.map(|s| s.to_rest().inner()),
+          "color_temp": payload.color_temp,
+          "transition": payload.transition,
+        }
+      }),
+      Request::SceneCommand(command) => json!({ "SceneCommand": command }),
+      request => json!(format!("{request:?}")),
+    }
+  }
+
+  /// Narrows down or reports requests of scenes that control the same lights in one round,
+  /// according to the home's conflict policy.  Returns the runs with what each lost to others.
+  fn resolve_conflicts<'s>(
+    home: &Home,
+    runs: Vec<(&'s Scene, Plan)>,
+  ) -> Vec<(&'s Scene, Plan, Vec<String>)> {
+    let mut runs: Vec<_> = runs.into_iter().map(|(scene, plan)| (scene, plan, vec![])).collect();
+    if home.conflicts == ConflictPolicy::HighestPriority {
+      runs.sort_by_key(|(scene, _, _)| std::cmp::Reverse(scene.priority));
+    }
+    let mut claimed: Vec<(Topic, &str)> = vec![];
+    for (scene, plan, lost) in &mut runs {
+      let mut mine = vec![];
+      for (offset, request) in std::mem::take(plan) {
+        let lights = Self::lights_of(home, &request);
+        let owner = |light: &Topic| claimed.iter().find(|(l, _)| l == light).map(|(_, s)| *s);
+        let taken: Vec<_> = lights.iter().filter_map(|l| Some((l.clone(), owner(l)?))).collect();
+        if taken.is_empty() {
+          mine.extend(lights);
+          plan.push((offset, request));
+          continue;
+        }
+        match home.conflicts {
+          ConflictPolicy::HighestPriority => {
+            for (light, other) in taken.iter() {
+              let msg = format!("Left {} to scene {other}, which takes priority.", light.to_str());
+              println!("Scene {}: {msg}", scene.name);
+              lost.push(msg);
+            }
+            let free: Vec<_> = lights.into_iter().filter(|l| owner(l).is_none()).collect();
+            mine.extend(free.iter().cloned());
+            plan.extend(Self::narrow(request, free).into_iter().map(|r| (offset, r)));
+          }
+          ConflictPolicy::LastWriter => {
+            for (light, other) in taken.iter() {
+              println!("Scene {} overrides scene {other} on {}.", scene.name, light.to_str());
+            }
+            mine.extend(lights);
+            plan.push((offset, request));
+          }
+        }
+      }
+      claimed.extend(mine.into_iter().map(|light| (light, scene.name.as_str())));
+    }
+    runs
   }
 }
 
 struct SceneEvaluator<'a> {
-  _home: &'a Home, // Will be required for time-triggered state-based constraints.
+  home: &'a Home,
   event: &'a SceneEvent,
+  now: DateTime<Local>,
+  memory: &'a mut TriggerMemory,
+  /// Collects explanations of the evaluated triggers if set.
+  trace: Option<Vec<Explanation>>,
+  /// Why the trigger being evaluated holds or not, only noted while tracing.
+  note: Option<String>,
+  /// Whether the trigger being evaluated is a condition next to a trigger that fires the scene,
+  /// which is the only place a negation holds.
+  condition: bool,
 }
 
 impl<'a> SceneEvaluator<'a> {
-  pub async fn eval_sensor_update(self, scene: &Scene) -> Vec<Request> {
+  /// The plan of the scene's effect if the event triggers it.
+  pub fn eval_sensor_update(&mut self, scene: &Scene) -> Option<Plan> {
     let active = match self.event {
-      SceneEvent::SensorUpdate(_, _) => self.evaluate_trigger(&scene.trigger),
+      SceneEvent::SensorUpdate(_, _)
+      | SceneEvent::Scheduled(_)
+      | SceneEvent::Elapsed(_)
+      | SceneEvent::Health(_, _) => self.evaluate_trigger(&scene.name, &scene.trigger, &scene.name),
       SceneEvent::ManualTrigger(ref name) => name == &scene.name,
+      SceneEvent::Redefined(_) | SceneEvent::Explain { .. } => false,
     };
     if active {
       println!("Scene {} was triggered.", scene.name);
-      return self.execute_effect(&scene.effect);
+      let mut plan = vec![];
+      self.plan_effect(&scene.effect, Duration::zero(), &mut plan);
+      plan.sort_by_key(|(offset, _)| *offset);
+      return Some(plan);
+    }
+    None
+  }
+
+  /// `path` identifies the trigger within the scene, for remembering its comparisons.
+  fn evaluate_trigger(&mut self, scene: &str, trigger: &Trigger, path: &str) -> bool {
+    guard!(let Some(trace) = self.trace.as_mut() else {
+      return self.evaluate_node(scene, trigger, path);
+    });
+    // The triggers this one is made of explain themselves into a fresh list.
+    let siblings = std::mem::take(trace);
+    let active = self.evaluate_node(scene, trigger, path);
+    let trace = self.trace.as_mut().unwrap();
+    let children = std::mem::replace(trace, siblings);
+    let reason = self.note.take();
+    trace.push(Explanation { trigger: trigger.kind(), active, reason, children });
+    active
+  }
+
+  /// What scripts see of the evaluation.
+  fn env(&self) -> Env<'a> {
+    let event = match self.event {
+      SceneEvent::SensorUpdate(topic, payload) => {
+        json!({ "kind": "update", "topic": topic.to_str(), "payload": payload })
+      }
+      SceneEvent::ManualTrigger(_) => json!({ "kind": "manual" }),
+      SceneEvent::Scheduled(_) => json!({ "kind": "schedule" }),
+      SceneEvent::Elapsed(path) => json!({ "kind": "countdown", "path": path }),
+      SceneEvent::Health(topic, issue) => {
+        json!({ "kind": "health", "topic": topic.to_str(), "issue": issue })
+      }
+      SceneEvent::Redefined(_) | SceneEvent::Explain { .. } => json!({ "kind": "other" }),
+    };
+    Env { home: self.home, event, now: self.now }
+  }
+
+  /// Notes why the trigger being evaluated holds or not, if the evaluation is explained.
+  fn explain(&mut self, reason: impl FnOnce() -> String) {
+    if self.trace.is_some() {
+      self.note = Some(reason());
     }
-    vec![]
   }
 
-  fn evaluate_trigger(&self, trigger: &Trigger) -> bool {
+  fn evaluate_node(&mut self, scene: &str, trigger: &Trigger, path: &str) -> bool {
+    let condition = std::mem::take(&mut self.condition);
     match trigger {
-      Trigger::And(a, b) => self.evaluate_trigger(a.as_ref()) && self.evaluate_trigger(b.as_ref()),
-      Trigger::DeviceState(dst) => self.evaluate_update_trigger(dst),
+      Trigger::And(a, b) => self.evaluate_all(scene, &[a.as_ref(), b.as_ref()], path),
+      Trigger::Or(a, b) => self.evaluate_each(scene, [a.as_ref(), b], path).any(|b| b),
+      Trigger::All(triggers) => {
+        self.evaluate_all(scene, &triggers.iter().collect::<Vec<_>>(), path)
+      }
+      Trigger::Any(triggers) => self.evaluate_each(scene, triggers, path).any(|b| b),
+      Trigger::Held { target, field, op, duration } => {
+        self.evaluate_held(target, field, op, *duration, path)
+      }
+      Trigger::Silence { target, duration } => {
+        // The first evaluation arms the countdown, which counts from the start of the manager.
+        let first = self.memory.held.insert(path.to_string(), true).is_none();
+        let deadline = match self.event {
+          SceneEvent::SensorUpdate(updated, _) if updated == target => Some(self.now + *duration),
+          _ if first => Some(self.memory.started + *duration),
+          _ => None,
+        };
+        if let Some(deadline) = deadline {
+          self.memory.deadlines.insert(path.to_string(), deadline);
+        }
+        self.elapsed(path)
+      }
+      Trigger::Health { target, issue } => match self.event {
+        SceneEvent::Health(device, reported) => {
+          reported == issue && target.as_ref().is_none_or(|t| t == device)
+        }
+        _ => false,
+      },
+      Trigger::Debounce { trigger, duration } => {
+        // Only a new activation restarts the countdown, not a trigger that keeps holding or the
+        // countdown elapsing.
+        let current = self.evaluate_trigger(scene, trigger, &format!("{path}.0"));
+        let previous = self.memory.held.insert(path.to_string(), current).unwrap_or(false);
+        let own = matches!(self.event, SceneEvent::Elapsed(elapsed) if elapsed == path);
+        if current && !previous && !own {
+          self.memory.deadlines.insert(path.to_string(), self.now + *duration);
+        }
+        self.elapsed(path)
+      }
+      Trigger::Throttle { trigger, duration } => {
+        if !self.evaluate_trigger(scene, trigger, &format!("{path}.0")) {
+          return false;
+        }
+        match self.memory.passed.get(path).copied() {
+          Some(last) if self.now - last < *duration => {
+            self.explain(|| format!("Let an activation through at {last} already."));
+            false
+          }
+          _ => {
+            self.memory.passed.insert(path.to_string(), self.now);
+            true
+          }
+        }
+      }
+      Trigger::Not { trigger } => {
+        let holds = !self.evaluate_trigger(scene, trigger, &format!("{path}.0"));
+        if !condition {
+          // Otherwise every unrelated event would fire the scene.
+          self.explain(|| String::from("A negation only holds next to a trigger that fires."));
+          return false;
+        }
+        holds
+      }
+      Trigger::DeviceState(dst) => self.evaluate_update_trigger(dst, path),
+      Trigger::Condition(dst) => self.evaluate_condition(dst, path),
+      Trigger::Lights { target, check } => self.evaluate_lights(target, check, path),
+      Trigger::Reading { target, reading, op } => {
+        guard!(let Some(sensor) = self.home.find_sensor(target) else {
+          self.explain(|| format!("{} is no sensor.", target.to_str()));
+          return false;
+        });
+        let value = sensor.latest().and_then(|s| s.reading(*reading));
+        guard!(let Some(value) = value else {
+          self.explain(|| format!("{} did not report {reading:?} yet.", target.to_str()));
+          return false;
+        });
+        self.compare_value(op, Activation::Level, &value, path)
+      }
       Trigger::Time(TimeTrigger { from, duration }) => {
-        Self::evaluate_time_trigger(*from, *duration, Local::now().time())
+        let now = self.now;
+        self.explain(|| format!("It is {}.", now.time()));
+        Self::evaluate_time_trigger(*from, *duration, self.now.time())
+      }
+      Trigger::Schedule(_) | Trigger::Sun(_) => {
+        let due = matches!(self.event, SceneEvent::Scheduled(name) if name == scene);
+        if !due {
+          self.explain(|| String::from("The event is no run of the schedule."));
+        }
+        due
+      }
+      Trigger::SunWindow { from, to } => match &self.home.location {
+        Some(location) => {
+          let now = self.now;
+          self.explain(|| format!("It is {now}."));
+          sun::within(from, to, self.now, location)
+        }
+        None => {
+          self.explain(|| String::from("The home has no location."));
+          false
+        }
+      },
+      Trigger::Script(program) => match program.holds(&self.env()) {
+        Ok(holds) => holds,
+        Err(err) => {
+          eprintln!("Script of scene {scene} failed: {err}.");
+          self.explain(|| format!("The script failed: {err}."));
+          false
+        }
+      },
+      Trigger::ManualOnly => {
+        self.explain(|| String::from("The scene only runs when triggered by hand."));
+        false
       }
-      Trigger::ManualOnly => false,
     }
   }
 
+  /// Whether all triggers hold.  Negations among them count as conditions if any of the others
+  /// can fire the scene.
+  fn evaluate_all(&mut self, scene: &str, triggers: &[&Trigger], path: &str) -> bool {
+    let fires = triggers.iter().any(|t| !matches!(t, Trigger::Not { .. }));
+    let mut all = true;
+    for (i, trigger) in triggers.iter().enumerate() {
+      self.condition = fires;
+      all &= self.evaluate_trigger(scene, trigger, &format!("{path}.{i}"));
+    }
+    all
+  }
+
+  /// Evaluates every trigger, without short-circuiting, so that the memory of all comparisons
+  /// stays current.
+  fn evaluate_each<'t>(
+    &mut self,
+    scene: &str,
+    triggers: impl IntoIterator<Item = &'t Trigger>,
+    path: &str,
+  ) -> std::vec::IntoIter<bool> {
+    let mut results = vec![];
+    for (i, trigger) in triggers.into_iter().enumerate() {
+      results.push(self.evaluate_trigger(scene, trigger, &format!("{path}.{i}")));
+    }
+    results.into_iter()
+  }
+
   fn evaluate_time_trigger(from: NaiveTime, duration: Duration, now: NaiveTime) -> bool {
     if from < from + duration {
       from < now && now < from + duration
@@ -92,40 +777,722 @@ impl<'a> SceneEvaluator<'a> {
     }
   }
 
-  fn evaluate_update_trigger(&self, dst: &DeviceStateTrigger) -> bool {
-    guard!(let SceneEvent::SensorUpdate(updated, state) = self.event else { return false });
-    let DeviceStateTrigger { target, field, op } = dst;
-    if target != updated {
+  fn evaluate_update_trigger(&mut self, dst: &DeviceStateTrigger, path: &str) -> bool {
+    guard!(let SceneEvent::SensorUpdate(updated, state) = self.event else {
+      self.explain(|| String::from("The event is no update of a device."));
+      return false;
+    });
+    if &dst.target != updated {
+      self.explain(|| format!("The update is from {}.", updated.to_str()));
+      return false;
+    }
+    guard!(let Some(value) = state.get(&dst.field) else {
+      self.explain(|| format!("The update has no {}.", dst.field));
+      return false;
+    });
+    self.compare(dst, value, path)
+  }
+
+  fn evaluate_condition(&mut self, dst: &DeviceStateTrigger, path: &str) -> bool {
+    let target = dst.target.clone().with_mode(TopicMode::Blank);
+    guard!(let Some(device) = self.home.find_device(&target) else {
+      self.explain(|| format!("{} is no device.", target.to_str()));
       return false;
+    });
+    let state = device.query_state().to_json_value(false);
+    guard!(let Some(value) = state.get(&dst.field) else {
+      self.explain(|| format!("{} has no {}.", target.to_str(), dst.field));
+      return false;
+    });
+    self.compare(dst, value, path)
+  }
+
+  /// Starts the countdown when an update of the target starts to satisfy the comparison and
+  /// cancels it when an update stops to.
+  fn evaluate_held(
+    &mut self,
+    target: &Topic,
+    field: &str,
+    op: &Comparison,
+    duration: Duration,
+    path: &str,
+  ) -> bool {
+    if let SceneEvent::SensorUpdate(updated, state) = self.event {
+      if let (true, Some(value)) = (updated == target, state.get(field)) {
+        let previous = self.memory.held.get(path).copied().unwrap_or(false);
+        let current = op.holds(value, previous);
+        self.memory.held.insert(path.to_string(), current);
+        if !current {
+          self.memory.deadlines.remove(path);
+        } else if !previous {
+          self.memory.deadlines.insert(path.to_string(), self.now + duration);
+        }
+      }
     }
-    guard!(let Some(field) = state.get(field) else { return false });
-    let field = field;
-    match op {
-      Comparison::BoolComparison { pivot } => field.as_bool().map(|c| c == *pivot).unwrap_or(false),
-      Comparison::Equality { value } => &field.to_string() == value,
+    self.elapsed(path)
+  }
+
+  /// Whether the event is the countdown of this trigger elapsing.
+  fn elapsed(&mut self, path: &str) -> bool {
+    let elapsed = matches!(self.event, SceneEvent::Elapsed(elapsed) if elapsed == path);
+    let deadline = self.memory.deadlines.get(path).copied();
+    self.explain(|| match (elapsed, deadline) {
+      (true, _) => String::from("The countdown elapsed."),
+      (false, Some(deadline)) => format!("Counting down until {deadline}."),
+      (false, None) => String::from("No countdown is running."),
+    });
+    elapsed
+  }
+
+  fn evaluate_lights(&mut self, target: &Topic, check: &LightCheck, path: &str) -> bool {
+    guard!(let Some(light) = self.home.find_effective_light(target) else {
+      self.explain(|| format!("{} is no light.", target.to_str()));
+      return false;
+    });
+    let states = light.light_states();
+    let on = states.iter().filter(|s| s.on).count();
+    self.explain(|| format!("{on} of {} lights are on.", states.len()));
+    match check {
+      LightCheck::AnyOn => states.iter().any(|s| s.on),
+      LightCheck::AllOn => states.iter().all(|s| s.on),
+      LightCheck::AllOff => states.iter().all(|s| !s.on),
+      LightCheck::Brightness { op } => {
+        let on = states.iter().filter(|s| s.on);
+        let brightest = on.map(|s| s.color.val().to_rest().inner()).fold(0.0, f64::max);
+        self.compare_value(op, Activation::Level, &(brightest * 100.0).into(), path)
+      }
     }
   }
 
-  fn execute_effect(&self, effect: &Effect) -> Vec<Request> {
+  fn compare(&mut self, dst: &DeviceStateTrigger, value: &JsonValue, path: &str) -> bool {
+    self.compare_value(&dst.op, dst.mode, value, path)
+  }
+
+  fn compare_value(
+    &mut self,
+    op: &Comparison,
+    mode: Activation,
+    value: &JsonValue,
+    path: &str,
+  ) -> bool {
+    let previous = self.memory.held.get(path).copied().unwrap_or(false);
+    let current = op.holds(value, previous);
+    self.memory.held.insert(path.to_string(), current);
+    let fires = mode.fires(previous, current);
+    self.explain(|| match (current, fires) {
+      (true, true) => format!("{value} meets the comparison."),
+      (true, false) => format!("{value} meets the comparison, but met it already."),
+      (false, _) => format!("{value} misses the comparison."),
+    });
+    fires
+  }
+
+  /// Adds the requests of the effect, starting at `start`, to the plan and returns when the effect
+  /// ends.
+  fn plan_effect(&self, effect: &Effect, start: Duration, plan: &mut Plan) -> Duration {
     match effect {
-      Effect::LightCommand { target, command } => self.execute_light_command(target, *command),
-      Effect::And(effects) => effects.iter().flat_map(|e| self.execute_effect(e)).collect(),
+      Effect::LightCommand { target, command } => {
+        plan.push((start, self.execute_light_command(target, *command)));
+        start
+      }
+      Effect::SetState { target, state } => {
+        plan.push((start, Self::execute_set_state(target, state)));
+        start
+      }
+      Effect::Delay { duration } => start + *duration,
+      Effect::Sequence(effects) => {
+        effects.iter().fold(start, |at, e| self.plan_effect(e, at, plan))
+      }
+      Effect::Parallel(effects) | Effect::And(effects) => {
+        effects.iter().map(|e| self.plan_effect(e, start, plan)).max().unwrap_or(start)
+      }
+      Effect::Snapshot { name, target } => {
+        let command = SceneCommand::Snapshot { name: name.clone(), target: target.clone() };
+        plan.push((start, Request::SceneCommand(command)));
+        start
+      }
+      Effect::Restore { name } => {
+        plan.push((start, Request::SceneCommand(SceneCommand::RestorePreset(name.clone()))));
+        start
+      }
+      Effect::Script(program) => {
+        let emitted = program.emit(&self.env()).and_then(|(emitted, length)| {
+          let target = |e: &Emitted| match e {
+            Emitted::Command(_, target) | Emitted::SetState(target, _) => target.clone(),
+          };
+          let unknown = emitted
+            .iter()
+            .map(|(_, e)| target(e))
+            .find(|t| self.home.find_effective_light(t).is_none());
+          match unknown {
+            Some(unknown) => Err(format!("{} is no light", unknown.to_str())),
+            None if plan.len() + emitted.len() > Effect::MAX_STEPS as usize => {
+              Err(format!("the run would send more than {} requests", Effect::MAX_STEPS))
+            }
+            None => Ok((emitted, length)),
+          }
+        });
+        let (emitted, length) = match emitted {
+          Ok(emitted) => emitted,
+          Err(err) => {
+            eprintln!("Script failed and sends nothing: {err}.");
+            return start;
+          }
+        };
+        for (offset, emitted) in emitted {
+          let request = match emitted {
+            Emitted::Command(command, target) => self.execute_light_command(&target, command),
+            Emitted::SetState(target, state) => Self::execute_set_state(&target, &state),
+          };
+          plan.push((start + offset, request));
+        }
+        start + length
+      }
+      Effect::SetEnabled { scene, enabled } => {
+        let command = SceneCommand::SetEnabled { name: scene.clone(), enabled: *enabled };
+        plan.push((start, Request::SceneCommand(command)));
+        start
+      }
+      Effect::Repeat { times, effect } => {
+        (0..*times).fold(start, |at, _| self.plan_effect(effect, at, plan))
+      }
     }
   }
 
-  fn execute_light_command(&self, target: &Topic, command: LightCommand) -> Vec<Request> {
-    let mut payload = RestApiPayload { topic: Some(target.clone()), ..RestApiPayload::default() };
-    payload.topic = Some(target.clone());
-    assert_ne!(command, LightCommand::ChangeState);
-    vec![Request::LightCommand(command, payload)]
+  fn execute_light_command(&self, target: &Topic, command: LightCommand) -> Request {
+    let topic = Some(target.clone());
+    let payload = RestApiPayload { topic, ..RestApiPayload::new(Origin::Scene) };
+    Request::LightCommand(command, payload)
+  }
+
+  fn execute_set_state(target: &Topic, state: &TargetState) -> Request {
+    let payload = state.payload(target, Origin::Scene);
+    Request::LightCommand(LightCommand::ChangeState, payload)
   }
 }
 
 #[cfg(test)]
 mod test {
-  use chrono::{Duration, NaiveTime};
+  use std::rc::Rc;
+
+  use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone};
+  use serde_json::{json, Value as JsonValue};
+  use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Mutex,
+  };
+
+  use super::{SceneEvaluator, SceneEvent, SceneManager};
+  use crate::{
+    api::{
+      request::{LightCommand, Request, SceneCommand},
+      topic::Topic,
+      traits::DeviceCollection,
+    },
+    clock::{Clock, FakeClock},
+    devices::DeviceTrait,
+    scenes::scene::Scene,
+    simulation::fixture::{self, CEILING, DOOR, FLOOR, HALL, MOTION},
+  };
+
+  /// A scene manager driven by hand: nothing runs unless the test handles an event or ticks.
+  struct Bench {
+    manager: SceneManager,
+    requests: UnboundedReceiver<Request>,
+    clock: FakeClock,
+    _events: UnboundedSender<SceneEvent>,
+  }
+
+  impl Bench {
+    /// A manager of the test home with the given scenes.
+    fn new(scenes: &str) -> Self {
+      Self::at(scenes, fixture::start())
+    }
+
+    /// A manager started at `start`.
+    fn at(scenes: &str, start: DateTime<Local>) -> Self {
+      let clock = FakeClock::new(start);
+      let (queue, requests) = unbounded_channel();
+      let (events, receiver) = unbounded_channel();
+      let home = Rc::new(Mutex::new(fixture::home(scenes)));
+      let manager = SceneManager::new(home, queue, receiver, clock.shared());
+      Bench { manager, requests, clock, _events: events }
+    }
+
+    /// Lets the device report a state, like the receiver and executor would.
+    async fn update(&mut self, target: &str, state: JsonValue) {
+      let topic = fixture::topic(target);
+      let mut home = self.manager.home.lock().await;
+      let device = home.find_device_mut(&topic).unwrap();
+      device.update_state(serde_json::from_value(state.clone()).unwrap(), self.clock.now());
+      drop(home);
+      self.manager.handle(SceneEvent::SensorUpdate(topic, state)).await;
+    }
+
+    /// Does what the manager does every tick.
+    async fn tick(&mut self) {
+      self.manager.run_schedules().await;
+      self.manager.run_countdowns().await;
+      self.manager.run_pending().await;
+      self.manager.run_health_checks().await;
+    }
+
+    /// The requests sent since the last call.
+    fn sent(&mut self) -> Vec<Request> {
+      std::iter::from_fn(|| self.requests.try_recv().ok()).collect()
+    }
+
+    /// The light commands sent since the last call, with their targets.
+    fn commands(&mut self) -> Vec<(LightCommand, String)> {
+      let command = |request| match request {
+        Request::LightCommand(command, payload) => Some((command, payload.topic?.to_str())),
+        _ => None,
+      };
+      self.sent().into_iter().filter_map(command).collect()
+    }
+
+    async fn scene(&self, name: &str) -> Scene {
+      let home = self.manager.home.lock().await;
+      home.scenes.iter().find(|s| s.name == name).unwrap().clone()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_negation_is_a_condition() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Alone
+    trigger: !Not
+      trigger: !DeviceState
+        target: zigbee2mqtt/Device/Sensor/Hall/Motion
+        field: occupancy
+        op: !BoolComparison { pivot: true }
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOn }
+  - name: Paired
+    trigger: !And
+      - !DeviceState
+        target: zigbee2mqtt/Device/Sensor/Hall/Door
+        field: occupancy
+        op: !BoolComparison { pivot: true }
+      - !Not
+        trigger: !Lights { target: zigbee2mqtt/Room/Hall, check: AnyOn }
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+"#,
+    );
+    // An update of another device neither fires the negation by itself nor next to a trigger
+    // that does not fire.
+    bench.update(MOTION, json!({ "occupancy": false })).await;
+    bench.manager.handle(SceneEvent::Elapsed(String::from("Other"))).await;
+    assert!(bench.sent().is_empty());
+    bench.update(DOOR, json!({ "occupancy": true })).await;
+    assert_eq!(bench.sent().len(), 1, "The lights are off, so Paired runs.");
+  }
+
+  #[tokio::test]
+  async fn test_no_motion_for_ten_minutes() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Away
+    trigger: !Held
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: false }
+      duration: 600
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+"#,
+    );
+    bench.update(MOTION, json!({ "occupancy": false })).await;
+    bench.clock.advance(Duration::minutes(8));
+    // Motion cancels the countdown, the next update without motion starts it over.
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    bench.update(MOTION, json!({ "occupancy": false })).await;
+    bench.clock.advance(Duration::minutes(8));
+    bench.tick().await;
+    assert!(bench.sent().is_empty());
+    bench.clock.advance(Duration::minutes(2));
+    bench.tick().await;
+    let hall = HALL.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::TurnOff, hall)]);
+  }
+
+  #[tokio::test]
+  async fn test_debounce_fires_once() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Settled
+    trigger: !Debounce
+      trigger: !Condition
+        target: zigbee2mqtt/Device/Sensor/Hall/Motion
+        field: occupancy
+        op: !BoolComparison { pivot: true }
+        mode: Level
+      duration: 60
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+"#,
+    );
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    // Updates while the condition keeps holding do not push the countdown back.
+    bench.clock.advance(Duration::seconds(30));
+    bench.update(DOOR, json!({ "occupancy": true })).await;
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    bench.clock.advance(Duration::seconds(30));
+    bench.tick().await;
+    assert_eq!(bench.sent().len(), 1);
+    // Elapsing does not arm the countdown again.
+    for _ in 0..3 {
+      bench.clock.advance(Duration::seconds(60));
+      bench.tick().await;
+    }
+    assert!(bench.sent().is_empty());
+    bench.update(MOTION, json!({ "occupancy": false })).await;
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    bench.clock.advance(Duration::seconds(60));
+    bench.tick().await;
+    assert_eq!(bench.sent().len(), 1, "A new activation arms it again.");
+  }
+
+  #[tokio::test]
+  async fn test_schedule_fires_by_itself() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Night
+    trigger: !Schedule
+      cron: "30 23 * * *"
+      missed: RunOnce
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+  - name: Late
+    trigger: !Schedule
+      cron: "30 23 * * *"
+      missed: RunOnce
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOn }
+    enabled: false
+"#,
+    );
+    bench.clock.set(Local.with_ymd_and_hms(2024, 3, 1, 23, 29, 0).unwrap());
+    bench.tick().await;
+    assert!(bench.sent().is_empty());
+    bench.clock.advance(Duration::minutes(2));
+    bench.tick().await;
+    let hall = HALL.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::TurnOff, hall)]);
+    assert_eq!(bench.scene("Night").await.last_run, Some(bench.clock.now()));
+    assert_eq!(bench.scene("Late").await.last_run, None, "Disabled scenes do not run.");
+    bench.clock.advance(Duration::minutes(2));
+    bench.tick().await;
+    assert!(bench.sent().is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_missed_runs_survive_a_restart() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_runs_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("home.yml.runs").to_str().unwrap().to_string();
+    let scenes = r#"
+  - name: Night
+    trigger: !Schedule
+      cron: "30 23 * * *"
+      missed: RunOnce
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+"#;
+    let mut before = Bench::new(scenes);
+    before.manager = before.manager.with_run_log(log.clone());
+    before.clock.set(Local.with_ymd_and_hms(2024, 3, 1, 23, 29, 0).unwrap());
+    before.tick().await;
+    before.clock.advance(Duration::minutes(2));
+    before.tick().await;
+    assert_eq!(before.commands().len(), 1);
+    // Two nights later, a new controller knows nothing but what was logged.
+    let restart = Local.with_ymd_and_hms(2024, 3, 3, 23, 45, 0).unwrap();
+    let mut after = Bench::at(scenes, restart);
+    after.manager = after.manager.with_run_log(log.clone());
+    after.manager.catch_up().await;
+    assert_eq!(after.commands(), vec![(LightCommand::TurnOff, HALL.to_string())]);
+    assert_eq!(after.scene("Night").await.last_run, Some(restart));
+    after.tick().await;
+    after.manager.catch_up().await;
+    assert!(after.sent().is_empty(), "RunOnce makes up for both nights with a single run.");
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_condition_reads_other_devices() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Welcome
+    trigger: !And
+      - !DeviceState
+        target: zigbee2mqtt/Device/Sensor/Hall/Motion
+        field: occupancy
+        op: !BoolComparison { pivot: true }
+      - !Lights { target: zigbee2mqtt/Room/Hall, check: AllOff }
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOn }
+"#,
+    );
+    bench.update(FLOOR, json!({ "state": "ON", "brightness": 100 })).await;
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    assert!(bench.sent().is_empty());
+    bench.update(FLOOR, json!({ "state": "OFF", "brightness": 100 })).await;
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let hall = HALL.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::TurnOn, hall)]);
+  }
+
+  #[tokio::test]
+  async fn test_retrigger_cancels_sequence() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Alarm
+    trigger: ManualOnly
+    effect: !Sequence
+      - !SetState
+        target: zigbee2mqtt/Device/Light/Hall/Ceiling
+        state: { on: true, hue: 0, saturation: 100, transition: 0 }
+      - !Delay { duration: 60 }
+      - !SetState
+        target: zigbee2mqtt/Device/Light/Hall/Ceiling
+        state: { on: false }
+"#,
+    );
+    let alarm = || SceneEvent::ManualTrigger(String::from("Alarm"));
+    bench.manager.handle(alarm()).await;
+    let sent = bench.sent();
+    assert_eq!(sent.len(), 1);
+    let Request::LightCommand(LightCommand::ChangeState, payload) = &sent[0] else {
+      panic!("The alarm sets a state: {sent:?}")
+    };
+    assert_eq!((payload.on, payload.transition), (Some(true), Some(0)));
+    bench.clock.advance(Duration::seconds(30));
+    bench.manager.handle(alarm()).await;
+    assert_eq!(bench.sent().len(), 1);
+    bench.clock.advance(Duration::seconds(40));
+    bench.tick().await;
+    assert!(bench.sent().is_empty(), "The first run was cancelled.");
+    bench.clock.advance(Duration::seconds(20));
+    bench.tick().await;
+    let sent = bench.sent();
+    assert!(matches!(&sent[..], [Request::LightCommand(_, p)] if p.on == Some(false)), "{sent:?}");
+    let history = bench.scene("Alarm").await.history;
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].effects.len(), 1);
+    assert_eq!(history[0].errors, vec!["Cancelled by a new run with 1 requests left to send."]);
+    assert_eq!(history[1].event, "Triggered by hand");
+    assert_eq!(history[1].effects.len(), 2, "The delayed request joins the run.");
+    // Redefining the scene drops what is left of its run.
+    bench.manager.handle(alarm()).await;
+    bench.manager.handle(SceneEvent::Redefined(String::from("Alarm"))).await;
+    bench.clock.advance(Duration::seconds(60));
+    bench.tick().await;
+    assert_eq!(bench.sent().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_priority_decides_conflicts() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Bright
+    priority: 1
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !SetState
+      target: zigbee2mqtt/Device/Light/Hall/Floor
+      state: { on: true, brightness: 100 }
+  - name: Dark
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOff }
+"#,
+    );
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let (floor, ceiling) = (String::from(FLOOR), String::from(CEILING));
+    let expected = vec![(LightCommand::ChangeState, floor), (LightCommand::TurnOff, ceiling)];
+    assert_eq!(bench.commands(), expected, "Dark still turns off the light Bright leaves alone.");
+    let lost = &bench.scene("Dark").await.history[0].errors;
+    assert!(lost[0].contains("Bright, which takes priority"), "{lost:?}");
+    let mut home = bench.manager.home.lock().await;
+    home.scenes.iter_mut().find(|s| s.name == "Bright").unwrap().enabled = false;
+    drop(home);
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let hall = HALL.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::TurnOff, hall)]);
+  }
+
+  #[tokio::test]
+  async fn test_restores_leave_taken_lights_alone() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Bright
+    priority: 1
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !SetState
+      target: zigbee2mqtt/Device/Light/Hall/Floor
+      state: { on: true, brightness: 100 }
+  - name: Cozy
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !Restore { name: Evening }
+"#,
+    );
+    let mut home = bench.manager.home.lock().await;
+    home.save_preset("Evening".to_string(), fixture::topic(HALL), false).unwrap();
+    drop(home);
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let restore = |light: &str| {
+      let lights = vec![fixture::topic(light)];
+      SceneCommand::RestoreLights { name: "Evening".to_string(), lights }
+    };
+    let sent = bench.sent();
+    assert!(matches!(&sent[0], Request::LightCommand(LightCommand::ChangeState, _)));
+    assert!(matches!(&sent[1..], [Request::SceneCommand(c)] if *c == restore(CEILING)));
+    // Overrides hold back restores like any other request.
+    let mut home = bench.manager.home.lock().await;
+    home.scenes.iter_mut().find(|s| s.name == "Bright").unwrap().enabled = false;
+    home.mark_overridden(&fixture::topic(CEILING), bench.clock.now());
+    drop(home);
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    assert!(matches!(&bench.sent()[..], [Request::SceneCommand(c)] if *c == restore(FLOOR)));
+  }
+
+  #[tokio::test]
+  async fn test_scenes_leave_overridden_lights_alone() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Bright
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !SetState
+      target: zigbee2mqtt/Room/Hall
+      state: { brightness: 100 }
+"#,
+    );
+    let now = bench.clock.now();
+    bench.manager.home.lock().await.mark_overridden(&Topic::try_from(FLOOR.into()).unwrap(), now);
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let ceiling = CEILING.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::ChangeState, ceiling)]);
+    let errors = &bench.scene("Bright").await.history[0].errors;
+    assert_eq!(errors, &vec![format!("Left {FLOOR} to manual control.")]);
+    bench.clock.advance(Duration::minutes(31));
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let hall = HALL.to_string();
+    assert_eq!(bench.commands(), vec![(LightCommand::ChangeState, hall)], "The override expired.");
+  }
+
+  #[tokio::test]
+  async fn test_explain_scene() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Welcome
+    trigger: !And
+      - !DeviceState
+        target: zigbee2mqtt/Device/Sensor/Hall/Motion
+        field: occupancy
+        op: !BoolComparison { pivot: true }
+      - !Time
+        from: "18:00:00"
+        duration: 21600
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOn }
+"#,
+    );
+    async fn explain(bench: &Bench, hours: i64) -> JsonValue {
+      let motion = Topic::try_from(MOTION.to_string()).unwrap();
+      let update = SceneEvent::SensorUpdate(motion, json!({ "occupancy": true }));
+      let at = bench.clock.now() + Duration::hours(hours);
+      let explained = bench.manager.explain("Welcome", Some(update), Some(at)).await;
+      serde_json::from_str(explained.inner()).unwrap()
+    }
+    let now = explain(&bench, 0).await;
+    assert_eq!(now["active"], json!(false));
+    let children = &now["trigger"]["children"];
+    assert_eq!(children[0]["active"], json!(true), "{now}");
+    assert_eq!(children[1]["trigger"], json!("Time"));
+    assert_eq!(children[1]["active"], json!(false));
+    assert_eq!(now["requests"], json!([]));
+    let evening = explain(&bench, 6).await;
+    assert_eq!(evening["active"], json!(true));
+    assert_eq!(evening["requests"][0]["request"]["LightCommand"]["command"], json!("TurnOn"));
+    assert!(bench.sent().is_empty(), "Explaining runs nothing.");
+  }
 
-  use super::SceneEvaluator;
+  #[tokio::test]
+  async fn test_script_scene() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Nightlight
+    trigger: !Script |
+      let update = event();
+      update.kind == "update" && update.payload.occupancy && time().hour >= 22
+    effect: !Script |
+      let floor = "zigbee2mqtt/Device/Light/Hall/Floor";
+      let level = 10;
+      while level <= 30 {
+        set_state(floor, #{ on: true, brightness: level });
+        delay(1);
+        level = level + 10;
+      }
+  - name: Runaway
+    trigger: !Script "while true { }"
+    effect: !LightCommand { target: zigbee2mqtt/Room/Hall, command: TurnOn }
+"#,
+    );
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    assert!(bench.sent().is_empty(), "Nightlight waits for the night.");
+    bench.clock.set(Local.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap());
+    bench.update(MOTION, json!({ "occupancy": true })).await;
+    let mut sent = bench.sent();
+    for _ in 0..3 {
+      bench.clock.advance(Duration::seconds(1));
+      bench.tick().await;
+      sent.extend(bench.sent());
+    }
+    // The runaway script stops without stalling anything.
+    let levels: Vec<f64> = sent
+      .iter()
+      .filter_map(|r| match r {
+        Request::LightCommand(LightCommand::ChangeState, p) => p.val.map(|v| v.to_rest().inner()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!((sent.len(), levels.len()), (3, 3), "{sent:?}");
+    assert!(levels[0] < levels[1] && levels[1] < levels[2], "{levels:?}");
+  }
+
+  #[tokio::test]
+  async fn test_low_battery_triggers_scene() {
+    let mut bench = Bench::new(
+      r#"
+  - name: Battery
+    trigger: !Health
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      issue: LowBattery
+    effect: !SetState
+      target: zigbee2mqtt/Device/Light/Hall/Ceiling
+      state: { hue: 0, saturation: 100 }
+health: { low_battery: 15 }
+"#,
+    );
+    // The scene fires when the battery turns low, not on every report of a low battery.
+    let mut fired = 0;
+    for (battery, expected) in [(50, 0), (18, 0), (12, 1), (10, 1), (80, 1), (9, 2)] {
+      bench.update(MOTION, json!({ "occupancy": false, "battery": battery })).await;
+      bench.tick().await;
+      fired += bench.sent().len();
+      assert_eq!(fired, expected, "at {battery}%");
+    }
+    let home = bench.manager.home.lock().await;
+    let motion = home.find_device(&Topic::try_from(MOTION.to_string()).unwrap()).unwrap();
+    assert_eq!(motion.health().battery, Some(9.0));
+  }
 
   #[test]
   fn test_time_trigger_eval() {
@@ -1,2 +1,6 @@
 pub mod manager;
+pub mod preset;
+pub mod program;
 pub mod scene;
+pub mod schedule;
+pub mod sun;
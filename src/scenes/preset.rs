@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{api::topic::Topic, devices::LightSnapshot};
+
+/// The states of the lights under a topic, e.g. how the living room looks right now, saved under a
+/// name to be restored later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+  pub name: String,
+  pub target: Topic,
+  pub lights: Vec<LightSnapshot>,
+}
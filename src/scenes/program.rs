@@ -0,0 +1,271 @@
+use std::{
+  collections::HashMap,
+  str::FromStr,
+  sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+use rhai::{
+  packages::{Package, StandardPackage},
+  serde::{from_dynamic, to_dynamic},
+  Dynamic, Engine, EvalAltResult, Module, Shared, AST,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::{
+  api::{
+    request::LightCommand,
+    topic::{Topic, TopicMode},
+    traits::{Addressable, DeviceCollection},
+  },
+  devices::{Device, DeviceTrait},
+  home::Home,
+};
+
+use super::scene::TargetState;
+
+/// A [Rhai](https://rhai.rs) script for triggers and effects that the enums cannot express, e.g.
+///
+/// ```text
+/// let hall = state("zigbee2mqtt/Device/Sensor/Hall/Motion");
+/// if hall.occupancy && time().hour >= 22 {
+///   set_state("zigbee2mqtt/Room/Hall", #{ on: true, brightness: 10 });
+///   delay(300);
+///   command("TurnOff", "zigbee2mqtt/Room/Hall");
+/// }
+/// ```
+///
+/// A trigger holds if its script returns, or ends with, a truthy value.  Scripts read the home
+/// through `state(topic)`, `event()` and `time()`, and effects send requests through
+/// `command(name, topic)`, `set_state(topic, state)` and `delay(seconds)`.  A run stops after
+/// [`Program::FUEL`] operations, so a runaway script cannot stall the scene manager.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Program {
+  source: String,
+  ast: AST,
+}
+
+impl Program {
+  /// Operations a single run may take.
+  pub const FUEL: u64 = 10_000;
+  /// Longest string a script may build.
+  const MAX_STRING: usize = 4096;
+  /// Most items of the arrays and maps a script builds, nested ones included.
+  const MAX_ITEMS: usize = 1024;
+  /// Deepest nesting of expressions and of function calls.
+  const MAX_DEPTH: usize = 64;
+
+  /// Runs the script as a trigger, which cannot send anything.
+  pub fn holds(&self, env: &Env<'_>) -> Result<bool, String> {
+    Ok(truthy(&self.run(env, false)?.0))
+  }
+
+  /// Runs the script as an effect.  Returns what it sends, with offsets from its start, and how
+  /// long it takes in all.
+  pub fn emit(&self, env: &Env<'_>) -> Result<(Vec<(Duration, Emitted)>, Duration), String> {
+    let (_, output) = self.run(env, true)?;
+    Ok((output.sent, output.offset))
+  }
+
+  /// An engine with the limits of a run, but none of the functions that read or change the home.
+  fn engine() -> Engine {
+    static STANDARD: OnceLock<Shared<Module>> = OnceLock::new();
+    let standard = STANDARD.get_or_init(|| StandardPackage::new().as_shared_module());
+    let mut engine = Engine::new_raw();
+    engine
+      .register_global_module(standard.clone())
+      .set_max_operations(Self::FUEL)
+      .set_max_expr_depths(Self::MAX_DEPTH, Self::MAX_DEPTH)
+      .set_max_call_levels(Self::MAX_DEPTH)
+      .set_max_string_size(Self::MAX_STRING)
+      .set_max_array_size(Self::MAX_ITEMS)
+      .set_max_map_size(Self::MAX_ITEMS)
+      .on_print(|text| println!("Script: {text}"));
+    engine
+  }
+
+  /// Returns the value of the script and what it sent.
+  fn run(&self, env: &Env<'_>, effect: bool) -> Result<(JsonValue, Output), String> {
+    let mut engine = Self::engine();
+    let state =
+      |d: &Device| (d.topic(TopicMode::Blank).to_str(), d.query_state().to_json_value(false));
+    let states: HashMap<String, JsonValue> =
+      env.home.flatten_devices().into_iter().map(state).collect();
+    engine.register_fn("state", move |target: &str| -> Fallible<Dynamic> {
+      let target = topic(target)?.with_mode(TopicMode::Blank).to_str();
+      states.get(&target).map_or(Ok(Dynamic::UNIT), to_dynamic)
+    });
+    let event = to_dynamic(&env.event).map_err(|err| err.to_string())?;
+    engine.register_fn("event", move || event.clone());
+    let now = env.now;
+    let time = to_dynamic(json!({
+      "hour": now.hour(),
+      "minute": now.minute(),
+      "second": now.second(),
+      "weekday": now.weekday().number_from_monday(),
+      "timestamp": now.timestamp(),
+    }))
+    .map_err(|err| err.to_string())?;
+    engine.register_fn("time", move || time.clone());
+
+    let output = Arc::new(Mutex::new(Output { effect, ..Output::default() }));
+    let sink = output.clone();
+    engine.register_fn("command", move |command: &str, target: &str| -> Fallible<()> {
+      let command = serde_json::from_value(json!(command))
+        .map_err(|_| format!("{command} is no light command"))?;
+      sink.lock().unwrap().send("command", Emitted::Command(command, topic(target)?))
+    });
+    let sink = output.clone();
+    engine.register_fn("set_state", move |target: &str, state: Dynamic| -> Fallible<()> {
+      let state = serde_json::from_value(from_dynamic(&state)?)
+        .map_err(|err| format!("{state} is no state: {err}"))?;
+      sink.lock().unwrap().send("set_state", Emitted::SetState(topic(target)?, state))
+    });
+    let sink = output.clone();
+    engine.register_fn("delay", move |seconds: Dynamic| -> Fallible<()> {
+      let seconds = seconds.as_float().or_else(|_| seconds.as_int().map(|s| s as f64));
+      let seconds = seconds.map_err(|_| "delays take a number of seconds".to_string())?;
+      sink.lock().unwrap().delay(seconds)
+    });
+
+    let value = engine.eval_ast::<Dynamic>(&self.ast).map_err(|err| err.to_string())?;
+    let value = from_dynamic(&value).map_err(|err| err.to_string())?;
+    drop(engine);
+    let output = std::mem::take(&mut *output.lock().unwrap());
+    Ok((value, output))
+  }
+}
+
+impl FromStr for Program {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let ast = Self::engine().compile(s).map_err(|err| err.to_string())?;
+    Ok(Program { source: s.to_string(), ast })
+  }
+}
+
+impl TryFrom<String> for Program {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Program> for String {
+  fn from(value: Program) -> Self {
+    value.source
+  }
+}
+
+/// The result of a function that scripts call.
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+/// What a script sees of the world.
+#[derive(Debug)]
+pub struct Env<'a> {
+  pub home: &'a Home,
+  /// The event being evaluated, as returned by `event()`.
+  pub event: JsonValue,
+  pub now: DateTime<Local>,
+}
+
+/// A request sent by an effect script.
+#[derive(Debug, Clone)]
+pub enum Emitted {
+  Command(LightCommand, Topic),
+  SetState(Topic, TargetState),
+}
+
+/// What a run sent so far.
+#[derive(Debug, Default)]
+struct Output {
+  /// Triggers cannot send anything.
+  effect: bool,
+  sent: Vec<(Duration, Emitted)>,
+  /// Sum of the delays so far.
+  offset: Duration,
+}
+
+impl Output {
+  fn send(&mut self, name: &str, emitted: Emitted) -> Fallible<()> {
+    self.check(name)?;
+    self.sent.push((self.offset, emitted));
+    Ok(())
+  }
+
+  fn delay(&mut self, seconds: f64) -> Fallible<()> {
+    self.check("delay")?;
+    if !(0.0..=86400.0).contains(&seconds) {
+      return Err(format!("cannot delay for {seconds} seconds").into());
+    }
+    self.offset += Duration::milliseconds((seconds * 1000.0) as i64);
+    Ok(())
+  }
+
+  fn check(&self, name: &str) -> Fallible<()> {
+    match self.effect {
+      true => Ok(()),
+      false => Err(format!("only effects can call `{name}`").into()),
+    }
+  }
+}
+
+fn topic(value: &str) -> Fallible<Topic> {
+  Topic::try_from(value.to_string()).map_err(|_| format!("{value} is no topic").into())
+}
+
+fn truthy(value: &JsonValue) -> bool {
+  match value {
+    JsonValue::Null => false,
+    JsonValue::Bool(b) => *b,
+    JsonValue::Number(n) => n.as_f64() != Some(0.0),
+    JsonValue::String(s) => !s.is_empty(),
+    JsonValue::Array(_) | JsonValue::Object(_) => true,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::{Duration, Local, TimeZone};
+  use serde_json::json;
+
+  use crate::home::Home;
+
+  use super::{Emitted, Env, Program};
+
+  #[test]
+  fn test_scripts() {
+    let home: Home = serde_yaml::from_str("name: Test\nrooms: []\nscenes: []").unwrap();
+    let now = Local.with_ymd_and_hms(2024, 3, 1, 22, 30, 0).unwrap();
+    let env = Env { home: &home, event: json!({ "kind": "manual" }), now };
+    let run = |source: &str| source.parse::<Program>()?.run(&env, false).map(|(value, _)| value);
+    assert_eq!(run("let x = 2; x = x * 3 + 1; x == 7").unwrap(), json!(true));
+    assert_eq!(run(r#"if time().hour >= 22 { return "late"; } "early""#).unwrap(), json!("late"));
+    assert_eq!(run("let o = #{ a: [1, 2] }; o.a[1] + len(o.a)").unwrap(), json!(4));
+    assert_eq!(run(r#"event().kind == "manual" && !false"#).unwrap(), json!(true));
+    assert_eq!(run(r#""say \"hi\"""#).unwrap(), json!("say \"hi\""));
+    assert_eq!(run("let x = 1; if true { let x = 2; } x").unwrap(), json!(1), "Blocks scope.");
+    assert!(run("while true { }").unwrap_err().contains("Too many operations"));
+    let doubling = "let a = 0; let i = 0; while i < 40 { a = [a, a]; i += 1; }";
+    assert!(run(doubling).is_err());
+    let hall = r#"command("TurnOn", "zigbee2mqtt/Room/Hall")"#;
+    assert!(run(hall).is_err(), "Triggers cannot send.");
+    assert!("if true { 1".parse::<Program>().is_err());
+    let program: Program = r#"
+      set_state("zigbee2mqtt/Room/Hall", #{ on: true, brightness: 50 });
+      delay(1.5);
+      command("TurnOff", "zigbee2mqtt/Room/Hall");
+    "#
+    .parse()
+    .unwrap();
+    let (out, length) = program.emit(&env).unwrap();
+    assert_eq!(out.len(), 2);
+    assert_eq!(length, Duration::milliseconds(1500));
+    assert!(matches!(out[0].1, Emitted::SetState(_, state) if state.brightness == Some(50.0)));
+    assert_eq!(out[1].0.num_milliseconds(), 1500);
+  }
+}
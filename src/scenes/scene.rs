@@ -1,21 +1,217 @@
-use chrono::{Duration, NaiveTime};
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Local, NaiveTime};
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::{
+  api::{request::LightCommand, topic::Topic},
+  convert::{Hue, Origin, RestApiPayload, Sat, Val},
+  devices::{Capability, HealthIssue},
+};
 
-use crate::api::{request::LightCommand, topic::Topic};
+use super::{
+  program::Program,
+  schedule::{ScheduleTrigger, Timer},
+  sun::{SunTime, SunTrigger},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Scene {
   pub name: String,
   pub trigger: Trigger,
   pub effect: Effect,
+  /// Time of the last scheduled run, kept in the `RunLog` to make up for runs missed during
+  /// downtime.
+  #[serde(skip)]
+  pub last_run: Option<DateTime<Local>>,
+  /// Time the scene was last triggered by anything. Runtime state, reported by `Query::Scenes`.
+  #[serde(skip)]
+  pub last_triggered: Option<DateTime<Local>>,
+  #[serde(default = "Scene::enabled_by_default")]
+  pub enabled: bool,
+  /// Decides conflicts with other scenes under `ConflictPolicy::HighestPriority`.
+  #[serde(default)]
+  pub priority: i32,
+  /// The latest runs, oldest first. Runtime state, reported by `Query::SceneHistory`.
+  #[serde(skip)]
+  pub history: VecDeque<SceneRun>,
+}
+
+impl Scene {
+  /// How many runs the history keeps.
+  pub const HISTORY: usize = 20;
+
+  fn enabled_by_default() -> bool {
+    true
+  }
+
+  pub fn record(&mut self, run: SceneRun) {
+    if self.history.len() == Self::HISTORY {
+      self.history.pop_front();
+    }
+    self.history.push_back(run);
+  }
 }
 
+/// One run of a scene.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneRun {
+  pub at: DateTime<Local>,
+  /// What triggered the scene.
+  pub event: String,
+  /// Requests the effect sent, including those sent after delays.
+  pub effects: Vec<JsonValue>,
+  /// What kept the effect from sending requests.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub errors: Vec<String>,
+}
+
+impl SceneRun {
+  pub fn new(at: DateTime<Local>, event: String) -> Self {
+    SceneRun { at, event, effects: vec![], errors: vec![] }
+  }
+}
+
+/// How to proceed when several scenes control the same light in reaction to one event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+  /// Only the scene with the highest priority controls the light; the first one in the home file
+  /// among equals.
+  #[default]
+  HighestPriority,
+  /// All scenes control the light in the order of the home file, so the last one prevails.
+  LastWriter,
+}
+
+#[serde_with::serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Trigger {
+  /// Matches updates sent by the target device.
   DeviceState(DeviceStateTrigger),
+  /// Matches the last known state of the target device, whichever event is evaluated.
+  Condition(DeviceStateTrigger),
+  /// Matches the cached state of the lights of a light, group or room.
+  Lights {
+    target: Topic,
+    check: LightCheck,
+  },
+  /// Matches the last reading of a sensor.
+  Reading {
+    target: Topic,
+    reading: Capability,
+    op: Comparison,
+  },
+  /// Fires once a field of the target compared true for the duration, e.g. no occupancy for ten
+  /// minutes.  An update that breaks the comparison cancels the countdown.
+  Held {
+    target: Topic,
+    field: String,
+    op: Comparison,
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    duration: Duration,
+  },
+  /// Fires once the target did not report for the duration.  Every update restarts the countdown.
+  Silence {
+    target: Topic,
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    duration: Duration,
+  },
+  /// Fires when a device turns unhealthy, e.g. its battery runs low.  Any device if there is no
+  /// target.
+  Health {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<Topic>,
+    issue: HealthIssue,
+  },
+  /// Fires once the duration passed since the inner trigger last started to activate.  Every new
+  /// activation restarts the countdown.
+  Debounce {
+    trigger: Box<Trigger>,
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    duration: Duration,
+  },
+  /// Lets activations of the inner trigger through at most once per duration.
+  Throttle {
+    trigger: Box<Trigger>,
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    duration: Duration,
+  },
   And(Box<Trigger>, Box<Trigger>),
+  Or(Box<Trigger>, Box<Trigger>),
+  /// Holds while the inner trigger does not, as a condition of an `And` or `All` next to a
+  /// trigger that fires the scene.  It never fires the scene by itself.
+  Not {
+    trigger: Box<Trigger>,
+  },
+  Any(Vec<Trigger>),
+  All(Vec<Trigger>),
   Time(TimeTrigger),
+  Schedule(ScheduleTrigger),
+  /// Fires at a sun event every day.
+  Sun(SunTrigger),
+  /// Holds between two sun events, e.g. from dusk until dawn.
+  SunWindow {
+    from: SunTime,
+    to: SunTime,
+  },
   ManualOnly,
+  /// Holds if the script returns a truthy value.
+  Script(Program),
+}
+
+impl Trigger {
+  /// Name of the variant, for explaining evaluations.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Trigger::DeviceState(_) => "DeviceState",
+      Trigger::Condition(_) => "Condition",
+      Trigger::Lights { .. } => "Lights",
+      Trigger::Reading { .. } => "Reading",
+      Trigger::Held { .. } => "Held",
+      Trigger::Silence { .. } => "Silence",
+      Trigger::Health { .. } => "Health",
+      Trigger::Debounce { .. } => "Debounce",
+      Trigger::Throttle { .. } => "Throttle",
+      Trigger::And(_, _) => "And",
+      Trigger::Or(_, _) => "Or",
+      Trigger::Not { .. } => "Not",
+      Trigger::Any(_) => "Any",
+      Trigger::All(_) => "All",
+      Trigger::Time(_) => "Time",
+      Trigger::Schedule(_) => "Schedule",
+      Trigger::Sun(_) => "Sun",
+      Trigger::SunWindow { .. } => "SunWindow",
+      Trigger::ManualOnly => "ManualOnly",
+      Trigger::Script(_) => "Script",
+    }
+  }
+
+  /// All timers in the trigger, which fire the scene by themselves.
+  pub fn timers(&self) -> Vec<Timer<'_>> {
+    match self {
+      Trigger::And(a, b) | Trigger::Or(a, b) => [a.timers(), b.timers()].concat(),
+      Trigger::Any(triggers) | Trigger::All(triggers) => {
+        triggers.iter().flat_map(Trigger::timers).collect()
+      }
+      Trigger::Debounce { trigger, .. } | Trigger::Throttle { trigger, .. } => trigger.timers(),
+      Trigger::Schedule(schedule) => vec![Timer::Schedule(schedule)],
+      Trigger::Sun(sun) => vec![Timer::Sun(sun)],
+      // A negated timer does not fire the scene.
+      Trigger::Not { .. }
+      | Trigger::DeviceState(_)
+      | Trigger::Condition(_)
+      | Trigger::Lights { .. }
+      | Trigger::Held { .. }
+      | Trigger::Silence { .. }
+      | Trigger::Health { .. }
+      | Trigger::Reading { .. }
+      | Trigger::Time(_)
+      | Trigger::SunWindow { .. }
+      | Trigger::ManualOnly
+      | Trigger::Script(_) => vec![],
+    }
+  }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +219,36 @@ pub struct DeviceStateTrigger {
   pub target: Topic,
   pub field: String,
   pub op: Comparison,
+  #[serde(default)]
+  pub mode: Activation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LightCheck {
+  AnyOn,
+  AllOn,
+  AllOff,
+  /// Brightness in percent of the brightest light that is on, zero if none is.
+  Brightness {
+    op: Comparison,
+  },
+}
+
+/// Whether a comparison holds while it is true or only when it becomes true.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Activation {
+  #[default]
+  Level,
+  Edge,
+}
+
+impl Activation {
+  pub fn fires(&self, previous: bool, current: bool) -> bool {
+    match self {
+      Activation::Level => current,
+      Activation::Edge => current && !previous,
+    }
+  }
 }
 
 #[serde_with::serde_as]
@@ -35,12 +261,168 @@ pub struct TimeTrigger {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Comparison {
-  Equality { value: String },
-  BoolComparison { pivot: bool },
+  Equality {
+    value: String,
+  },
+  BoolComparison {
+    pivot: bool,
+  },
+  /// Holds above the threshold.  Once it holds, it keeps holding until the value drops below
+  /// `threshold - hysteresis`, so values jittering around the threshold do not flap.
+  Above {
+    threshold: f64,
+    #[serde(default)]
+    hysteresis: f64,
+  },
+  /// Holds below the threshold, until the value rises above `threshold + hysteresis`.
+  Below {
+    threshold: f64,
+    #[serde(default)]
+    hysteresis: f64,
+  },
+  /// Holds within `[min, max]`, until the value leaves the range widened by the hysteresis.
+  Between {
+    min: f64,
+    max: f64,
+    #[serde(default)]
+    hysteresis: f64,
+  },
 }
 
+impl Comparison {
+  /// Whether the comparison holds for `value`, given whether it held the last time.
+  pub fn holds(&self, value: &JsonValue, previous: bool) -> bool {
+    let slack = |hysteresis: f64| if previous { hysteresis } else { 0.0 };
+    match self {
+      Comparison::Equality { value: expected } => &value.to_string() == expected,
+      Comparison::BoolComparison { pivot } => value.as_bool() == Some(*pivot),
+      Comparison::Above { threshold, hysteresis } => {
+        value.as_f64().is_some_and(|v| v > threshold - slack(*hysteresis))
+      }
+      Comparison::Below { threshold, hysteresis } => {
+        value.as_f64().is_some_and(|v| v < threshold + slack(*hysteresis))
+      }
+      Comparison::Between { min, max, hysteresis } => value
+        .as_f64()
+        .is_some_and(|v| min - slack(*hysteresis) <= v && v <= max + slack(*hysteresis)),
+    }
+  }
+}
+
+#[serde_with::serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Effect {
-  LightCommand { target: Topic, command: LightCommand },
+  LightCommand {
+    target: Topic,
+    command: LightCommand,
+  },
+  /// Brings the target into a state.  What the state leaves out stays as it is.
+  SetState {
+    target: Topic,
+    state: TargetState,
+  },
+  /// Waits before the next effect of a sequence.  Delays are honoured to the tick of the scene
+  /// manager.
+  Delay {
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    duration: Duration,
+  },
+  /// One effect after another, each waiting for the delays of the previous ones.
+  Sequence(Vec<Effect>),
+  /// All effects at once.
+  Parallel(Vec<Effect>),
+  /// Same as `Parallel`.
   And(Vec<Effect>),
+  Repeat {
+    times: u32,
+    effect: Box<Effect>,
+  },
+  /// Captures the lights under the target to restore them later in the same run, e.g. after
+  /// flashing them for the doorbell.
+  Snapshot {
+    name: String,
+    target: Topic,
+  },
+  /// Restores a snapshot or a preset saved in the home.
+  Restore {
+    name: String,
+  },
+  /// Enables or disables a scene.
+  SetEnabled {
+    scene: String,
+    enabled: bool,
+  },
+  /// Sends what the script asks for.  A script that fails sends nothing.
+  Script(Program),
+}
+
+impl Effect {
+  pub const MAX_REPEAT: u32 = 1000;
+  /// Steps a run may take in all, however deeply repeats nest.
+  pub const MAX_STEPS: u64 = 10_000;
+
+  /// Steps a run takes: every effect counts once per repetition, a script once however much it
+  /// sends.  Caps what a run expands to before it is planned.
+  pub fn steps(&self) -> u64 {
+    match self {
+      Effect::Sequence(effects) | Effect::Parallel(effects) | Effect::And(effects) => {
+        effects.iter().map(Effect::steps).fold(1, u64::saturating_add)
+      }
+      Effect::Repeat { times, effect } => effect.steps().saturating_mul(u64::from(*times)),
+      _ => 1,
+    }
+  }
+}
+
+/// A state for lights to take.  Brightness and saturation are in percent, hue in degrees, color
+/// temperature in mireds and transition in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetState {
+  pub on: Option<bool>,
+  pub brightness: Option<f64>,
+  pub hue: Option<f64>,
+  pub saturation: Option<f64>,
+  pub color_temp: Option<u16>,
+  pub transition: Option<i8>,
+}
+
+impl TargetState {
+  /// The payload of a `ChangeState` command that sets `target` to this state.
+  pub fn payload(&self, target: &Topic, origin: Origin) -> RestApiPayload {
+    let color = self.hue.is_some() || self.saturation.is_some();
+    // A color needs all of hue, saturation and brightness, which default to full.
+    let full = |value: Option<f64>| if color { value.or(Some(100.0)) } else { value };
+    RestApiPayload {
+      topic: Some(target.clone()),
+      on: self.on,
+      val: full(self.brightness).map(|b| Val::from_rest(b / 100.0)),
+      hue: self.hue.or(if color { Some(0.0) } else { None }).map(Hue::from_mqtt),
+      sat: full(self.saturation).map(Sat::from_mqtt),
+      color_temp: self.color_temp,
+      transition: self.transition,
+      ..RestApiPayload::new(origin)
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use super::Comparison;
+
+  #[test]
+  fn test_hysteresis() {
+    let above = Comparison::Above { threshold: 65.0, hysteresis: 5.0 };
+    assert!(!above.holds(&json!(64), false));
+    assert!(above.holds(&json!(66), false));
+    assert!(above.holds(&json!(61), true));
+    assert!(!above.holds(&json!(59), true));
+    assert!(!above.holds(&json!("high"), true));
+    let between = Comparison::Between { min: 18.0, max: 22.0, hysteresis: 1.0 };
+    assert!(!between.holds(&json!(22.5), false));
+    assert!(between.holds(&json!(22.5), true));
+    assert!(!between.holds(&json!(16.5), true));
+  }
 }
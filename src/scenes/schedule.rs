@@ -0,0 +1,372 @@
+use std::{collections::BTreeMap, fs::File, io::ErrorKind, str::FromStr};
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::{
+  scene::Scene,
+  sun::{GeoLocation, SunTrigger},
+};
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+  /// Standard five-field cron expression in local time, e.g. `30 23 * * *`.
+  Cron(Cron),
+  /// Fires once, at the given local time.
+  At(NaiveDateTime),
+  /// Fires every so many seconds, counted from the Unix epoch.
+  Every(#[serde_as(as = "serde_with::DurationSeconds<i64>")] Duration),
+}
+
+/// What to do about runs that fell into a downtime of the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MissedRunPolicy {
+  #[default]
+  Skip,
+  RunOnce,
+  /// Fires once per missed run, up to [`ScheduleTrigger::MAX_MISSED`] times.
+  RunAll,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleTrigger {
+  #[serde(flatten)]
+  pub when: Schedule,
+  #[serde(default)]
+  pub missed: MissedRunPolicy,
+}
+
+impl ScheduleTrigger {
+  pub const MAX_MISSED: usize = 100;
+
+  /// The first run strictly after `time`.
+  pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+    match &self.when {
+      Schedule::Cron(cron) => cron.next_after(time),
+      Schedule::At(at) => Local.from_local_datetime(at).earliest().filter(|at| *at > time),
+      Schedule::Every(interval) if interval.num_seconds() > 0 => {
+        let interval = interval.num_seconds();
+        let next = (time.timestamp().div_euclid(interval) + 1) * interval;
+        Local.timestamp_opt(next, 0).earliest()
+      }
+      Schedule::Every(_) => None,
+    }
+  }
+}
+
+/// Something in a trigger that fires the scene by itself.
+#[derive(Debug, Clone, Copy)]
+pub enum Timer<'a> {
+  Schedule(&'a ScheduleTrigger),
+  Sun(&'a SunTrigger),
+}
+
+impl<'a> Timer<'a> {
+  /// The first run strictly after `time`.  Sun timers need the home's location.
+  pub fn next_after(
+    &self,
+    time: DateTime<Local>,
+    location: Option<&GeoLocation>,
+  ) -> Option<DateTime<Local>> {
+    match self {
+      Timer::Schedule(schedule) => schedule.next_after(time),
+      Timer::Sun(sun) => sun.at.next_after(time, location?),
+    }
+  }
+
+  fn missed_policy(&self) -> MissedRunPolicy {
+    match self {
+      Timer::Schedule(schedule) => schedule.missed,
+      Timer::Sun(sun) => sun.missed,
+    }
+  }
+
+  /// Number of runs in `(from, to]`, at most `limit`.
+  pub fn runs_between(
+    &self,
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+    limit: usize,
+    location: Option<&GeoLocation>,
+  ) -> usize {
+    let mut count = 0;
+    let mut time = from;
+    while count < limit {
+      match self.next_after(time, location) {
+        Some(next) if next <= to => {
+          count += 1;
+          time = next;
+        }
+        _ => break,
+      }
+    }
+    count
+  }
+
+  /// Number of times a run missed since `last_run` should be made up for.
+  pub fn missed_runs(
+    &self,
+    last_run: DateTime<Local>,
+    now: DateTime<Local>,
+    location: Option<&GeoLocation>,
+  ) -> usize {
+    match self.missed_policy() {
+      MissedRunPolicy::Skip => 0,
+      MissedRunPolicy::RunOnce => self.runs_between(last_run, now, 1, location),
+      MissedRunPolicy::RunAll => {
+        self.runs_between(last_run, now, ScheduleTrigger::MAX_MISSED, location)
+      }
+    }
+  }
+}
+
+/// Decides which scheduled scenes are due.  Scenes remember their last scheduled run, which lets
+/// the scheduler make up for runs missed while the controller was down.
+#[derive(Debug, Clone, Copy)]
+pub struct Scheduler {
+  last_check: DateTime<Local>,
+}
+
+impl Scheduler {
+  pub fn new(now: DateTime<Local>) -> Self {
+    Scheduler { last_check: now }
+  }
+
+  /// Names of the scenes with a run since the last check.  Several runs count as one.
+  pub fn due(
+    &mut self,
+    scenes: &[Scene],
+    location: Option<&GeoLocation>,
+    now: DateTime<Local>,
+  ) -> Vec<String> {
+    let since = std::mem::replace(&mut self.last_check, now);
+    let due = |t: &Timer| t.runs_between(since, now, 1, location) > 0;
+    scenes.iter().filter(|s| s.trigger.timers().iter().any(due)).map(|s| s.name.clone()).collect()
+  }
+
+  /// Names of the scenes to fire for runs missed before `now`, once per run to make up for.
+  pub fn missed(
+    scenes: &[Scene],
+    location: Option<&GeoLocation>,
+    now: DateTime<Local>,
+  ) -> Vec<String> {
+    let mut names = vec![];
+    for scene in scenes {
+      guard::guard!(let Some(last_run) = scene.last_run else { continue });
+      let timers = scene.trigger.timers();
+      let missed = timers.iter().map(|t| t.missed_runs(last_run, now, location)).max();
+      names.extend(std::iter::repeat_n(scene.name.clone(), missed.unwrap_or_default()));
+    }
+    names
+  }
+}
+
+/// The last scheduled run of every scene, kept in a file next to the home so that runs missed
+/// while the controller was down are made up for after a restart.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunLog(BTreeMap<String, DateTime<Local>>);
+
+impl RunLog {
+  /// Where the runs of the scenes in the home file at `home` are logged.
+  pub fn path(home: &str) -> String {
+    format!("{home}.runs")
+  }
+
+  /// A missing log means that no scene ran yet.
+  pub fn read(from: &str) -> crate::Result<Self> {
+    match std::fs::read_to_string(from) {
+      Ok(content) => Ok(serde_yaml::from_str(&content)?),
+      Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  /// Replaces the log at `to` in one step, like the home file.
+  pub fn write(&self, to: &str) -> crate::Result<()> {
+    let temporary = format!("{to}.tmp");
+    serde_yaml::to_writer(&File::create(&temporary)?, self)?;
+    std::fs::rename(temporary, to)?;
+    Ok(())
+  }
+
+  pub fn of(scenes: &[Scene]) -> Self {
+    RunLog(scenes.iter().filter_map(|s| Some((s.name.clone(), s.last_run?))).collect())
+  }
+
+  /// Scenes that are not in the log keep the last run they know of.
+  pub fn restore(&self, scenes: &mut [Scene]) {
+    for scene in scenes {
+      if let Some(last_run) = self.0.get(&scene.name) {
+        scene.last_run = Some(*last_run);
+      }
+    }
+  }
+}
+
+/// A parsed cron expression: minute, hour, day of month, month and day of week.  Fields take `*`,
+/// numbers, ranges `a-b`, steps `*/n` or `a-b/n`, and lists thereof.  Sunday is 0 or 7.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+  source: String,
+  minutes: u64,
+  hours: u64,
+  days: u64,
+  months: u64,
+  weekdays: u64,
+  any_day: bool,
+  any_weekday: bool,
+}
+
+impl Cron {
+  /// How far ahead to search before giving up, e.g. for February 30th.
+  const HORIZON_DAYS: i64 = 366 * 5;
+
+  pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+    let start = time.naive_local().date();
+    for offset in 0..Self::HORIZON_DAYS {
+      let date = start + Duration::days(offset);
+      if !self.matches_date(date) {
+        continue;
+      }
+      for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+        for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+          let naive = date.and_hms_opt(hour, minute, 0)?;
+          match Local.from_local_datetime(&naive).earliest() {
+            Some(candidate) if candidate > time => return Some(candidate),
+            _ => {} // Too early, or skipped by a DST change.
+          }
+        }
+      }
+    }
+    None
+  }
+
+  fn matches_date(&self, date: NaiveDate) -> bool {
+    if self.months & (1 << date.month()) == 0 {
+      return false;
+    }
+    let day = self.days & (1 << date.day()) != 0;
+    let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+    // As in cron: if both are restricted, either one suffices.
+    match (self.any_day, self.any_weekday) {
+      (true, true) => true,
+      (false, true) => day,
+      (true, false) => weekday,
+      (false, false) => day || weekday,
+    }
+  }
+
+  fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0;
+    for part in field.split(',') {
+      let (range, step) = match part.split_once('/') {
+        Some((range, step)) => {
+          let step = step.parse::<u32>().map_err(|_| format!("invalid step in `{part}`"))?;
+          (range, step.max(1))
+        }
+        None => (part, 1),
+      };
+      let number = |s: &str| {
+        s.parse::<u32>()
+          .ok()
+          .filter(|n| (min..=max).contains(n))
+          .ok_or_else(|| format!("`{s}` is not in {min}-{max}"))
+      };
+      let (from, to) = match range.split_once('-') {
+        _ if range == "*" => (min, max),
+        Some((from, to)) => (number(from)?, number(to)?),
+        None if step > 1 => (number(range)?, max),
+        None => (number(range)?, number(range)?),
+      };
+      if from > to {
+        return Err(format!("empty range `{range}`"));
+      }
+      (from..=to).step_by(step as usize).for_each(|n| mask |= 1 << n);
+    }
+    Ok(mask)
+  }
+}
+
+impl FromStr for Cron {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    guard::guard!(let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+      return Err(format!("`{s}` needs five fields: minute hour day month weekday"));
+    });
+    let mut weekday_mask = Self::parse_field(weekdays, 0, 7)?;
+    if weekday_mask & (1 << 7) != 0 {
+      weekday_mask |= 1; // Sunday.
+    }
+    Ok(Cron {
+      source: s.to_string(),
+      minutes: Self::parse_field(minutes, 0, 59)?,
+      hours: Self::parse_field(hours, 0, 23)?,
+      days: Self::parse_field(days, 1, 31)?,
+      months: Self::parse_field(months, 1, 12)?,
+      weekdays: weekday_mask,
+      any_day: *days == "*",
+      any_weekday: *weekdays == "*",
+    })
+  }
+}
+
+impl TryFrom<String> for Cron {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
+impl From<Cron> for String {
+  fn from(value: Cron) -> Self {
+    value.source
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::{DateTime, Local, TimeZone};
+
+  use super::{Cron, MissedRunPolicy, Schedule, ScheduleTrigger, Timer};
+
+  fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+    Local.with_ymd_and_hms(2024, 5, day, hour, minute, 0).unwrap()
+  }
+
+  #[test]
+  fn test_cron_next_run() {
+    let cron: Cron = "30 23 * * *".parse().unwrap();
+    assert_eq!(cron.next_after(at(1, 12, 0)), Some(at(1, 23, 30)));
+    assert_eq!(cron.next_after(at(1, 23, 30)), Some(at(2, 23, 30)));
+    // May 4th 2024 is a Saturday.
+    let weekdays: Cron = "*/15 8-9 * * 1-5".parse().unwrap();
+    assert_eq!(weekdays.next_after(at(3, 9, 50)), Some(at(6, 8, 0)));
+    assert_eq!(weekdays.next_after(at(6, 8, 0)), Some(at(6, 8, 15)));
+    assert!("0 0 30 2 *".parse::<Cron>().unwrap().next_after(at(1, 0, 0)).is_none());
+    assert!("61 * * * *".parse::<Cron>().is_err());
+    assert!("* * *".parse::<Cron>().is_err());
+  }
+
+  #[test]
+  fn test_missed_runs() {
+    let every = Schedule::Every(chrono::Duration::minutes(10));
+    let mut trigger = ScheduleTrigger { when: every, missed: MissedRunPolicy::Skip };
+    let missed =
+      |t: &ScheduleTrigger| Timer::Schedule(t).missed_runs(at(1, 12, 0), at(1, 13, 0), None);
+    assert_eq!(Timer::Schedule(&trigger).runs_between(at(1, 12, 0), at(1, 13, 0), 100, None), 6);
+    assert_eq!(missed(&trigger), 0);
+    trigger.missed = MissedRunPolicy::RunOnce;
+    assert_eq!(missed(&trigger), 1);
+    trigger.missed = MissedRunPolicy::RunAll;
+    assert_eq!(missed(&trigger), 6);
+    let once = ScheduleTrigger { when: Schedule::At(at(1, 12, 30).naive_local()), ..trigger };
+    assert_eq!(missed(&once), 1);
+    assert_eq!(once.next_after(at(1, 12, 30)), None);
+  }
+}
@@ -0,0 +1,205 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::schedule::MissedRunPolicy;
+
+/// Where the home is, for computing sunrise and sunset locally.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoLocation {
+  pub latitude: f64,
+  pub longitude: f64,
+  /// UTC offset such as `+01:00` that decides which day it is at the home.  Defaults to the
+  /// machine's offset.  Anything else, e.g. a zone name, does not load.
+  #[serde(default, with = "utc_offset")]
+  pub timezone: Option<FixedOffset>,
+}
+
+impl GeoLocation {
+  pub fn offset(&self, at: DateTime<Local>) -> FixedOffset {
+    self.timezone.unwrap_or(*at.offset())
+  }
+
+  /// The day at the home at the given time.
+  pub fn date(&self, at: DateTime<Local>) -> NaiveDate {
+    at.with_timezone(&self.offset(at)).date_naive()
+  }
+}
+
+/// Reads and writes offsets in the form `+01:00`.
+mod utc_offset {
+  use chrono::FixedOffset;
+  use serde::{de, Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(offset: &Option<FixedOffset>, s: S) -> Result<S::Ok, S::Error> {
+    match offset {
+      Some(offset) => s.serialize_some(&offset.to_string()),
+      None => s.serialize_none(),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<FixedOffset>, D::Error> {
+    guard::guard!(let Some(offset) = Option::<String>::deserialize(d)? else { return Ok(None) });
+    let msg = || format!("Timezone {offset} is not a UTC offset such as +01:00.");
+    offset.parse().map(Some).map_err(|_| de::Error::custom(msg()))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SunEvent {
+  Sunrise,
+  Sunset,
+  /// Begin of civil twilight in the morning.
+  Dawn,
+  /// End of civil twilight in the evening.
+  Dusk,
+}
+
+impl SunEvent {
+  /// Angle between the sun and the zenith at the event, including refraction.
+  fn zenith(&self) -> f64 {
+    match self {
+      SunEvent::Sunrise | SunEvent::Sunset => 90.833,
+      SunEvent::Dawn | SunEvent::Dusk => 96.0,
+    }
+  }
+
+  fn rising(&self) -> bool {
+    matches!(self, SunEvent::Sunrise | SunEvent::Dawn)
+  }
+
+  /// Time of the event on `date` at `location`, or `None` if the sun does not reach it that day,
+  /// e.g. during polar day.  Follows the sunrise algorithm of the Almanac for Computers, which is
+  /// accurate to about a minute.
+  pub fn time(&self, date: NaiveDate, location: &GeoLocation) -> Option<DateTime<Utc>> {
+    let sin = |degrees: f64| degrees.to_radians().sin();
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let lng_hour = location.longitude / 15.0;
+    let base = if self.rising() { 6.0 } else { 18.0 };
+    let t = date.ordinal() as f64 + (base - lng_hour) / 24.0;
+    let mean_anomaly = 0.9856 * t - 3.289;
+    let true_lng =
+      (mean_anomaly + 1.916 * sin(mean_anomaly) + 0.020 * sin(2.0 * mean_anomaly) + 282.634)
+        .rem_euclid(360.0);
+    let mut ascension =
+      (0.91764 * true_lng.to_radians().tan()).atan().to_degrees().rem_euclid(360.0);
+    ascension += (true_lng / 90.0).floor() * 90.0 - (ascension / 90.0).floor() * 90.0;
+    let ascension = ascension / 15.0;
+    let sin_dec = 0.39782 * sin(true_lng);
+    let cos_dec = sin_dec.asin().cos();
+    let cos_hour =
+      (cos(self.zenith()) - sin_dec * sin(location.latitude)) / (cos_dec * cos(location.latitude));
+    if !(-1.0..=1.0).contains(&cos_hour) {
+      return None;
+    }
+    let hour_angle = cos_hour.acos().to_degrees();
+    let hour_angle = if self.rising() { 360.0 - hour_angle } else { hour_angle } / 15.0;
+    let local_mean = hour_angle + ascension - 0.06571 * t - 6.622;
+    let universal = (local_mean - lng_hour).rem_euclid(24.0);
+    let time = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?)
+      + Duration::seconds((universal * 3600.0).round() as i64);
+    // The computation works in UTC; move to the requested day at the home.
+    let offset = location.offset(time.with_timezone(&Local));
+    let shift = (date - time.with_timezone(&offset).date_naive()).num_days();
+    Some(time + Duration::days(shift))
+  }
+}
+
+/// A sun event, shifted by an offset, e.g. half an hour before sunset.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SunTime {
+  pub event: SunEvent,
+  /// Seconds, negative for earlier.
+  #[serde(default = "Duration::zero")]
+  #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+  pub offset: Duration,
+}
+
+impl SunTime {
+  pub fn on(&self, date: NaiveDate, location: &GeoLocation) -> Option<DateTime<Local>> {
+    self.event.time(date, location).map(|t| (t + self.offset).with_timezone(&Local))
+  }
+
+  pub fn next_after(
+    &self,
+    time: DateTime<Local>,
+    location: &GeoLocation,
+  ) -> Option<DateTime<Local>> {
+    let date = location.date(time);
+    (-1..=2).filter_map(|days| self.on(date + Duration::days(days), location)).find(|at| *at > time)
+  }
+}
+
+/// Fires the scene at a sun time every day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SunTrigger {
+  #[serde(flatten)]
+  pub at: SunTime,
+  #[serde(default)]
+  pub missed: MissedRunPolicy,
+}
+
+/// Whether `now` lies between two sun times, e.g. from dusk until dawn.
+pub fn within(from: &SunTime, to: &SunTime, now: DateTime<Local>, location: &GeoLocation) -> bool {
+  let date = location.date(now);
+  guard::guard!(let (Some(start), Some(end)) = (from.on(date, location), to.on(date, location)) else {
+    return false;
+  });
+  if start <= end {
+    start <= now && now < end
+  } else {
+    now >= start || now < end // Across midnight.
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::{Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+
+  use super::{within, GeoLocation, SunEvent, SunTime};
+
+  #[test]
+  fn test_sun_times() {
+    let timezone = FixedOffset::east_opt(2 * 3600);
+    let berlin = GeoLocation { latitude: 52.52, longitude: 13.405, timezone };
+    let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    let close = |event: SunEvent, h: u32, m: u32| {
+      let expected = Utc.with_ymd_and_hms(2024, 6, 21, h, m, 0).unwrap();
+      let actual = event.time(midsummer, &berlin).unwrap();
+      assert!((actual - expected).num_minutes().abs() <= 3, "{event:?} at {actual}");
+    };
+    close(SunEvent::Sunrise, 2, 43);
+    close(SunEvent::Sunset, 19, 33);
+    close(SunEvent::Dusk, 20, 23);
+    let svalbard = GeoLocation { latitude: 78.2, longitude: 15.6, timezone: None };
+    assert_eq!(SunEvent::Sunset.time(midsummer, &svalbard), None);
+  }
+
+  #[test]
+  fn test_timezone_is_an_offset() {
+    let read = |timezone: &str| {
+      serde_yaml::from_str::<GeoLocation>(&format!("{{ latitude: 0, longitude: 0, {timezone} }}"))
+    };
+    let location = read("timezone: '+02:00'").unwrap();
+    assert_eq!(location.timezone, FixedOffset::east_opt(2 * 3600));
+    assert!(serde_yaml::to_string(&location).unwrap().contains("timezone: +02:00"));
+    assert_eq!(read("").unwrap().timezone, None);
+    let err = read("timezone: Europe/Berlin").unwrap_err().to_string();
+    assert!(err.contains("Timezone Europe/Berlin is not a UTC offset"), "{err}");
+  }
+
+  #[test]
+  fn test_sun_window() {
+    let berlin = GeoLocation { latitude: 52.52, longitude: 13.405, timezone: None };
+    let at =
+      |h: u32, m: u32| Utc.with_ymd_and_hms(2024, 6, 21, h, m, 0).unwrap().with_timezone(&Local);
+    let dusk = SunTime { event: SunEvent::Dusk, offset: Duration::zero() };
+    let dawn = SunTime { event: SunEvent::Dawn, offset: Duration::zero() };
+    assert!(within(&dusk, &dawn, at(23, 0), &berlin));
+    assert!(within(&dusk, &dawn, at(1, 0), &berlin));
+    assert!(!within(&dusk, &dawn, at(12, 0), &berlin));
+    let before_sunset = SunTime { event: SunEvent::Sunset, offset: Duration::minutes(-30) };
+    let next = before_sunset.next_after(at(12, 0), &berlin).unwrap().with_timezone(&Utc);
+    assert!((next - at(19, 3).with_timezone(&Utc)).num_minutes().abs() <= 3, "{next}");
+  }
+}
@@ -0,0 +1,96 @@
+//! Stand-ins for the outside world: an in-process MQTT broker and devices that answer like
+//! zigbee2mqtt does.  Tests run the controller against them, no broker or zigbee stick required.
+
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+  api::{topic::Topic, traits::DeviceCollection},
+  home::Home,
+  mqtt::{MessageStream, MqttBackend, MqttMessage},
+  Error, Result,
+};
+
+pub mod broker;
+pub mod control;
+pub mod device;
+#[cfg(test)]
+pub(crate) mod fixture;
+pub mod harness;
+pub mod replay;
+pub mod script;
+
+pub use broker::FakeBroker;
+pub use device::SimulatedDevice;
+pub use harness::TestHome;
+
+/// Impersonates a zigbee2mqtt installation with one simulated device per device of the home.
+/// Clones share the devices and the connection.
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct Zigbee2Mqtt {
+  devices: Arc<Mutex<Vec<SimulatedDevice>>>,
+  backend: Arc<dyn MqttBackend>,
+}
+
+impl Zigbee2Mqtt {
+  pub fn new(home: &Home, backend: Arc<dyn MqttBackend>) -> Self {
+    let devices = home.flatten_devices().into_iter().map(SimulatedDevice::from_device).collect();
+    Zigbee2Mqtt { devices: Arc::new(Mutex::new(devices)), backend }
+  }
+
+  /// A snapshot of all devices.
+  pub fn devices(&self) -> Vec<SimulatedDevice> {
+    self.devices.lock().unwrap().clone()
+  }
+
+  /// Subscribes to the `set` and `get` topics of all devices.
+  pub async fn subscribe(&self) -> Result<()> {
+    for topic in self.devices().iter().flat_map(SimulatedDevice::command_topics) {
+      self.backend.subscribe(topic.to_str()).await?;
+    }
+    Ok(())
+  }
+
+  pub async fn run(self, mut stream: MessageStream) -> Result<()> {
+    while let Some(msg) = stream.next().await {
+      guard::guard!(let Some(msg) = msg else { continue });
+      for response in self.handle(&msg) {
+        self.backend.publish(response).await?;
+      }
+    }
+    Err(Error::ConnectionLost)
+  }
+
+  /// Returns the messages zigbee2mqtt would publish in response to `msg`.
+  pub fn handle(&self, msg: &MqttMessage) -> Vec<MqttMessage> {
+    guard::guard!(let Ok(topic) = Topic::try_from(msg.topic.clone()) else { return vec![] });
+    let payload: JsonValue = serde_json::from_str(&msg.payload).unwrap_or_default();
+    let mut devices = self.devices.lock().unwrap();
+    devices
+      .iter_mut()
+      .filter(|d| d.addressed_by(&topic))
+      .flat_map(|d| d.handle(topic.mode(), &payload))
+      .collect()
+  }
+
+  /// Publishes a button press of the remote at `topic`.
+  pub async fn press(&self, topic: &Topic, action: &str) -> Result<()> {
+    let msg = self.with_device(topic, |d| d.press(action))?;
+    self.backend.publish(msg).await
+  }
+
+  /// Publishes a reading of the device at `topic`, e.g. `{"occupancy": true}`.
+  pub async fn read(&self, topic: &Topic, reading: &Map<String, JsonValue>) -> Result<()> {
+    let msg = self.with_device(topic, |d| d.read(reading))?;
+    self.backend.publish(msg).await
+  }
+
+  fn with_device<T>(&self, topic: &Topic, f: impl FnOnce(&mut SimulatedDevice) -> T) -> Result<T> {
+    let mut devices = self.devices.lock().unwrap();
+    let device = devices.iter_mut().find(|d| d.addressed_by(topic));
+    device.map(f).ok_or_else(|| Error::UnknownDevice(topic.to_str()))
+  }
+}
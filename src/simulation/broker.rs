@@ -0,0 +1,129 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::{
+  mqtt::{MessageStream, MqttBackend, MqttMessage},
+  Result,
+};
+
+/// An MQTT broker living in the same process.  Every client gets its own [`FakeConnection`];
+/// published messages are delivered to all matching subscriptions and kept in a history.
+#[derive(Debug, Clone, Default)]
+pub struct FakeBroker {
+  state: Arc<Mutex<BrokerState>>,
+}
+
+#[derive(Debug, Default)]
+struct BrokerState {
+  sessions: Vec<Session>,
+  history: Vec<MqttMessage>,
+}
+
+#[derive(Debug)]
+struct Session {
+  filters: Vec<String>,
+  outbox: UnboundedSender<MqttMessage>,
+}
+
+impl FakeBroker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn connect(&self) -> (FakeConnection, MessageStream) {
+    let (outbox, inbox) = unbounded_channel();
+    let mut state = self.state.lock().unwrap();
+    state.sessions.push(Session { filters: vec![], outbox });
+    let connection = FakeConnection { broker: self.clone(), session: state.sessions.len() - 1 };
+    let stream =
+      stream::unfold(
+        inbox,
+        |mut inbox| async move { inbox.recv().await.map(|msg| (Some(msg), inbox)) },
+      );
+    (connection, stream.boxed())
+  }
+
+  pub fn publish(&self, msg: MqttMessage) {
+    let mut state = self.state.lock().unwrap();
+    for session in &state.sessions {
+      if session.filters.iter().any(|f| Self::matches(f, &msg.topic)) {
+        let _ = session.outbox.send(msg.clone()); // The client may be gone already.
+      }
+    }
+    state.history.push(msg);
+  }
+
+  /// All messages published so far, in order.
+  pub fn history(&self) -> Vec<MqttMessage> {
+    self.state.lock().unwrap().history.clone()
+  }
+
+  pub fn clear_history(&self) {
+    self.state.lock().unwrap().history.clear();
+  }
+
+  /// Matches a topic against a filter with the `+` and `#` wildcards.
+  pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut topic = topic.split('/');
+    for level in filter.split('/') {
+      match (level, topic.next()) {
+        ("#", _) => return true,
+        ("+", Some(_)) => {}
+        (level, Some(t)) if level == t => {}
+        _ => return false,
+      }
+    }
+    topic.next().is_none()
+  }
+
+  fn with_session(&self, session: usize, f: impl FnOnce(&mut Session)) {
+    f(&mut self.state.lock().unwrap().sessions[session])
+  }
+}
+
+#[derive(Debug)]
+pub struct FakeConnection {
+  broker: FakeBroker,
+  session: usize,
+}
+
+impl MqttBackend for FakeConnection {
+  fn publish(&self, msg: MqttMessage) -> BoxFuture<'_, Result<()>> {
+    self.broker.publish(msg);
+    async { Ok(()) }.boxed()
+  }
+
+  fn subscribe(&self, topic: String) -> BoxFuture<'_, Result<()>> {
+    self.broker.with_session(self.session, |s| s.filters.push(topic));
+    async { Ok(()) }.boxed()
+  }
+
+  fn unsubscribe(&self, topic: String) -> BoxFuture<'_, Result<()>> {
+    self.broker.with_session(self.session, |s| s.filters.retain(|f| f != &topic));
+    async { Ok(()) }.boxed()
+  }
+
+  fn reconnect(&self) -> BoxFuture<'_, Result<()>> {
+    async { Ok(()) }.boxed()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'_, Result<()>> {
+    self.broker.with_session(self.session, |s| s.filters.clear());
+    async { Ok(()) }.boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::FakeBroker;
+
+  #[test]
+  fn test_filter_matching() {
+    assert!(FakeBroker::matches("zigbee2mqtt/+/Office/Desk", "zigbee2mqtt/Light/Office/Desk"));
+    assert!(FakeBroker::matches("zigbee2mqtt/#", "zigbee2mqtt/Room/Office/set"));
+    assert!(!FakeBroker::matches("zigbee2mqtt/Room/Office", "zigbee2mqtt/Room/Office/set"));
+    assert!(!FakeBroker::matches("zigbee2mqtt/Room/Office/set", "zigbee2mqtt/Room/Office"));
+  }
+}
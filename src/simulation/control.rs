@@ -0,0 +1,95 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr};
+
+use guard::guard;
+use hyper::{
+  service::{make_service_fn, service_fn},
+  Body, Method, Request as HyperRequest, Response, Server, StatusCode,
+};
+use serde_json::{json, Map, Value as JsonValue};
+use url::Url;
+
+use crate::{api::topic::Topic, Result};
+
+use super::Zigbee2Mqtt;
+
+/// Lets developers operate the simulated devices by hand:
+/// - `GET /devices` lists all devices and their state,
+/// - `GET /press?topic=<remote>&action=on` presses a button,
+/// - `GET /read?topic=<sensor>&occupancy=true` makes a sensor report a reading.
+#[allow(missing_debug_implementations)]
+pub struct ControlServer {
+  zigbee: Zigbee2Mqtt,
+  addr: SocketAddr,
+}
+
+impl ControlServer {
+  pub fn new(zigbee: Zigbee2Mqtt, addr: SocketAddr) -> Self {
+    Self { zigbee, addr }
+  }
+
+  pub async fn run(self) -> Result<Infallible> {
+    println!("Control API listening on {}.", self.addr);
+    let ControlServer { zigbee, addr } = self;
+    let make_svc = make_service_fn(move |_conn| {
+      let zigbee = zigbee.clone();
+      async move { Ok::<_, Infallible>(service_fn(move |req| Self::process(req, zigbee.clone()))) }
+    });
+    Server::bind(&addr).serve(make_svc).await?;
+    unreachable!("The server only stops with an error.")
+  }
+
+  async fn process(
+    req: HyperRequest<Body>,
+    zigbee: Zigbee2Mqtt,
+  ) -> std::result::Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET {
+      return Ok(Self::respond(StatusCode::BAD_REQUEST, "Only get requests are allowed."));
+    }
+    let url = Url::parse("http://localhost").and_then(|b| b.join(&req.uri().to_string())).unwrap();
+    let mut params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let topic = params.remove("topic").map(Topic::try_from);
+    let response = match (url.path(), topic) {
+      ("/devices", _) => {
+        let devices: Vec<JsonValue> = zigbee
+          .devices()
+          .iter()
+          .map(|d| json!({ "topic": d.topic(), "model": d.model(), "state": d.state() }))
+          .collect();
+        Self::respond(StatusCode::ACCEPTED, JsonValue::from(devices).to_string())
+      }
+      ("/press" | "/read", None) => Self::respond(StatusCode::BAD_REQUEST, "Needs a topic."),
+      ("/press" | "/read", Some(Err(_))) => {
+        Self::respond(StatusCode::BAD_REQUEST, "Invalid topic.")
+      }
+      ("/press", Some(Ok(topic))) => {
+        guard!(let Some(action) = params.get("action") else {
+          return Ok(Self::respond(StatusCode::BAD_REQUEST, "Needs an action."));
+        });
+        Self::outcome(zigbee.press(&topic, action).await)
+      }
+      ("/read", Some(Ok(topic))) => {
+        // Values are JSON where possible, so `occupancy=true` is a boolean and `humidity=40` a number.
+        let reading: Map<String, JsonValue> = params
+          .into_iter()
+          .map(|(k, v)| (k, serde_json::from_str(&v).unwrap_or(JsonValue::String(v))))
+          .collect();
+        Self::outcome(zigbee.read(&topic, &reading).await)
+      }
+      _ => Self::respond(StatusCode::NOT_FOUND, "Unknown path."),
+    };
+    Ok(response)
+  }
+
+  fn outcome(result: Result<()>) -> Response<Body> {
+    match result {
+      Ok(()) => Self::respond(StatusCode::ACCEPTED, "Success"),
+      Err(err) => Self::respond(StatusCode::BAD_REQUEST, format!("{err:?}")),
+    }
+  }
+
+  fn respond(status: StatusCode, msg: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(msg.into());
+    *response.status_mut() = status;
+    response
+  }
+}
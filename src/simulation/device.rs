@@ -0,0 +1,175 @@
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::{
+  api::{
+    topic::{DeviceKind, Topic, TopicMode},
+    traits::Addressable,
+  },
+  devices::{Capability, Device, DeviceModel, DeviceTrait},
+  mqtt::MqttMessage,
+};
+
+/// A device as zigbee2mqtt presents it: it takes commands on its `set` topic, answers on `get` and
+/// reports its full state on the blank topic.  Only fields the model is capable of are reported.
+#[derive(Debug, Clone)]
+pub struct SimulatedDevice {
+  topic: Topic,
+  model: DeviceModel,
+  state: Map<String, JsonValue>,
+}
+
+impl SimulatedDevice {
+  pub fn new(topic: Topic, model: DeviceModel) -> Self {
+    let mut state = Map::new();
+    for capability in model.capabilities() {
+      let initial = match capability {
+        Capability::State => json!("OFF"),
+        Capability::Brightness => json!(84.0),
+        Capability::Color => json!({ "hue": 0.0, "saturation": 0.0 }),
+        Capability::Humidity => json!(50.0),
+        Capability::Temperature => json!(21.0),
+        Capability::Occupancy => json!(false),
+        Capability::Illuminance => json!(200.0),
+        Capability::Transition => continue,
+      };
+      state.insert(capability.field().to_string(), initial);
+    }
+    if matches!(model.kind(), DeviceKind::Sensor | DeviceKind::Remote) {
+      state.insert(String::from("battery"), json!(100));
+    }
+    state.insert(String::from("linkquality"), json!(120));
+    SimulatedDevice { topic: topic.with_mode(TopicMode::Blank), model, state }
+  }
+
+  pub fn from_device(device: &Device) -> Self {
+    Self::new(device.topic(TopicMode::Blank), device.model())
+  }
+
+  pub fn topic(&self) -> &Topic {
+    &self.topic
+  }
+
+  pub fn model(&self) -> DeviceModel {
+    self.model
+  }
+
+  pub fn state(&self) -> &Map<String, JsonValue> {
+    &self.state
+  }
+
+  pub fn command_topics(&self) -> [Topic; 2] {
+    [self.topic.clone().with_mode(TopicMode::Set), self.topic.clone().with_mode(TopicMode::Get)]
+  }
+
+  pub fn addressed_by(&self, topic: &Topic) -> bool {
+    topic.clone().with_mode(TopicMode::Blank) == self.topic
+  }
+
+  /// Reacts to a message on one of the device's topics and returns what zigbee2mqtt would publish.
+  pub fn handle(&mut self, mode: TopicMode, payload: &JsonValue) -> Vec<MqttMessage> {
+    match mode {
+      TopicMode::Set => {
+        self.apply(payload);
+        vec![self.report()]
+      }
+      TopicMode::Get => vec![self.report()],
+      TopicMode::Blank => vec![], // Our own reports.
+    }
+  }
+
+  /// The full state on the device's blank topic.
+  pub fn report(&self) -> MqttMessage {
+    MqttMessage::new(self.topic.to_str(), JsonValue::Object(self.state.clone()).to_string())
+  }
+
+  /// A button press as a remote reports it, e.g. `on` or `brightness_move_up`.
+  pub fn press(&mut self, action: &str) -> MqttMessage {
+    MqttMessage::new(self.topic.to_str(), json!({ "action": action }).to_string())
+  }
+
+  /// Takes over readings such as `{"occupancy": true}` and reports the resulting state.
+  pub fn read(&mut self, reading: &Map<String, JsonValue>) -> MqttMessage {
+    for (field, value) in reading {
+      if self.state.contains_key(field) {
+        self.state.insert(field.clone(), value.clone());
+      }
+    }
+    self.report()
+  }
+
+  fn apply(&mut self, payload: &JsonValue) {
+    let capable = |c| self.model.capable_of(c);
+    let mut on = None;
+    let mut brightness = self.brightness();
+    if let Some(state) = payload.get("state").and_then(JsonValue::as_str) {
+      on = match state {
+        "ON" => Some(true),
+        "OFF" => Some(false),
+        "TOGGLE" => Some(self.state.get("state") != Some(&json!("ON"))),
+        _ => None,
+      };
+    }
+    if let Some(value) = payload.get("brightness").and_then(JsonValue::as_f64) {
+      brightness = value;
+      on = on.or(Some(value > 0.0)); // zigbee2mqtt turns lights on when setting a brightness.
+    }
+    for key in ["brightness_move", "brightness_step"] {
+      if let Some(delta) = payload.get(key).and_then(JsonValue::as_f64) {
+        brightness += delta; // Moves happen instantly rather than over time.
+      }
+    }
+    if let Some(color) = payload.get("color") {
+      let read = |short: &str, long: &str| {
+        color.get(short).or_else(|| color.get(long)).and_then(JsonValue::as_f64)
+      };
+      if let (Some(hue), Some(saturation)) = (read("h", "hue"), read("s", "saturation")) {
+        if capable(Capability::Color) {
+          self.state.insert(String::from("color"), json!({ "hue": hue, "saturation": saturation }));
+        }
+      }
+      brightness = read("v", "brightness").unwrap_or(brightness);
+    }
+    if capable(Capability::Brightness) {
+      self.state.insert(String::from("brightness"), json!(brightness.clamp(1.0, 254.0)));
+    }
+    if let (Some(on), true) = (on, capable(Capability::State)) {
+      self.state.insert(String::from("state"), json!(if on { "ON" } else { "OFF" }));
+    }
+  }
+
+  fn brightness(&self) -> f64 {
+    self.state.get("brightness").and_then(JsonValue::as_f64).unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use crate::{
+    api::topic::{DeviceKind, Topic, TopicMode},
+    devices::DeviceModel,
+  };
+
+  use super::SimulatedDevice;
+
+  #[test]
+  fn test_light_follows_commands() {
+    let topic = Topic::Device {
+      device: DeviceKind::Light,
+      room: String::from("Office"),
+      groups: vec![],
+      name: String::from("Desk"),
+      mode: TopicMode::Blank,
+    };
+    let mut light = SimulatedDevice::new(topic, DeviceModel::IkeaDimmable);
+    let reports = light.handle(TopicMode::Set, &json!({"state": "ON", "transition": 3}));
+    assert_eq!(reports.len(), 1);
+    assert_eq!(light.state()["state"], json!("ON"));
+    light.handle(TopicMode::Set, &json!({"brightness_move": 400}));
+    assert_eq!(light.state()["brightness"], json!(254.0));
+    light.handle(TopicMode::Set, &json!({"state": "TOGGLE"}));
+    assert_eq!(light.state()["state"], json!("OFF"));
+    assert!(light.state().get("color").is_none());
+  }
+}
@@ -0,0 +1,55 @@
+//! The home and the moment the tests of all modules start from.
+
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::{api::topic::Topic, home::Home};
+
+/// A hall with two lights, two motion sensors and a dimmer for both lights.  The scenes follow.
+pub const HOME: &str = r#"
+name: Test
+rooms:
+  - name: Hall
+    icon: door
+    lights:
+      name: Main
+      room: Hall
+      subgroups: []
+      atomics:
+        - { name: Ceiling, model: HueColor, icon: bulb, room: Hall }
+        - { name: Floor, model: IkeaDimmable, icon: bulb, room: Hall }
+    sensors:
+      - { name: Motion, model: IkeaMotion, icon: sensor, room: Hall }
+      - { name: Door, model: IkeaMotion, icon: sensor, room: Hall }
+    remotes:
+      - name: Dimmer
+        model: IkeaDimmer
+        icon: remote
+        controls: zigbee2mqtt/Group/Hall/Main
+        room: Hall
+        actions: { "on": TurnOn, "off": TurnOff }
+scenes:"#;
+
+pub const HALL: &str = "zigbee2mqtt/Room/Hall";
+pub const CEILING: &str = "zigbee2mqtt/Device/Light/Hall/Ceiling";
+pub const FLOOR: &str = "zigbee2mqtt/Device/Light/Hall/Floor";
+pub const MOTION: &str = "zigbee2mqtt/Device/Sensor/Hall/Motion";
+pub const DOOR: &str = "zigbee2mqtt/Device/Sensor/Hall/Door";
+pub const DIMMER: &str = "zigbee2mqtt/Device/Remote/Hall/Dimmer";
+
+/// The test home with `scenes`, a YAML list such as `[]`.
+pub fn yaml(scenes: &str) -> String {
+  format!("{HOME} {scenes}\n")
+}
+
+pub fn home(scenes: &str) -> Home {
+  serde_yaml::from_str(&yaml(scenes)).unwrap()
+}
+
+/// A Friday afternoon, well clear of midnight and noon.
+pub fn start() -> DateTime<Local> {
+  Local.with_ymd_and_hms(2024, 3, 1, 14, 0, 0).unwrap()
+}
+
+pub fn topic(topic: &str) -> Topic {
+  Topic::try_from(topic.to_string()).unwrap()
+}
@@ -0,0 +1,217 @@
+use std::{rc::Rc, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Local};
+use futures::{future::LocalBoxFuture, FutureExt};
+use serde_json::Value as JsonValue;
+use tokio::{
+  join,
+  sync::{
+    mpsc::{unbounded_channel, UnboundedSender},
+    Mutex,
+  },
+};
+
+use crate::{
+  api::{request::Request, topic::Topic, Executor},
+  clock::FakeClock,
+  controller::Controller,
+  home::Home,
+  mqtt::{self, recorder::SharedRecorder, MqttMessage, ProtectedClient},
+  scenes::manager::{SceneEvent, SceneManager},
+  Result,
+};
+
+use super::{FakeBroker, Zigbee2Mqtt};
+
+/// A complete installation in one process: the controller's receiver, executor and scene manager
+/// talk to a [`FakeBroker`], behind which every device of the home is simulated.  Nothing runs
+/// unless the test drives it with [`TestHome::run_for`].
+#[allow(missing_debug_implementations)]
+pub struct TestHome {
+  pub broker: FakeBroker,
+  pub home: Rc<Mutex<Home>>,
+  /// The controller's time.  Stands still unless the test moves it.
+  pub clock: FakeClock,
+  queue: UnboundedSender<Request>,
+  events: UnboundedSender<SceneEvent>,
+  running: LocalBoxFuture<'static, ()>,
+  zigbee: Zigbee2Mqtt,
+  client: ProtectedClient,
+}
+
+impl TestHome {
+  pub async fn new(home: Home) -> Result<Self> {
+    Self::build(home, true, Local::now()).await
+  }
+
+  /// Nothing answers the controller; the test plays the devices' part itself, e.g. in a replay.
+  /// The controller's time starts at `start`.
+  pub async fn without_devices(home: Home, start: DateTime<Local>) -> Result<Self> {
+    Self::build(home, false, start).await
+  }
+
+  async fn build(home: Home, simulate_devices: bool, start: DateTime<Local>) -> Result<Self> {
+    let broker = FakeBroker::new();
+    let (connection, stream) = broker.connect();
+    let zigbee = Zigbee2Mqtt::new(&home, Arc::new(connection));
+    if simulate_devices {
+      zigbee.subscribe().await?;
+    }
+    let simulated = zigbee.clone().run(stream);
+
+    let (q_send, q_recv) = unbounded_channel();
+    let (scene_send, scene_recv) = unbounded_channel();
+    let (connection, stream) = broker.connect();
+    let (client, receiver) =
+      mqtt::attach(Box::new(connection), stream, q_send.clone(), scene_send.clone());
+    Controller::subscribe(&client, &home, q_send.clone()).await;
+
+    let home = Rc::new(Mutex::new(home));
+    let clock = FakeClock::new(start);
+    let executor =
+      Executor::new(q_recv, scene_send.clone(), client.clone(), home.clone(), clock.shared());
+    let scene_manager = SceneManager::new(home.clone(), q_send.clone(), scene_recv, clock.shared())
+      .with_tick(Duration::from_millis(5));
+    let running = async move {
+      let (recv, exec, scenes, devices) =
+        join!(receiver.run(), executor.run(), scene_manager.run(), simulated);
+      eprintln!("Simulation stopped: {recv:?} {exec:?} {scenes:?} {devices:?}");
+    }
+    .boxed_local();
+
+    Ok(TestHome { broker, home, queue: q_send, events: scene_send, running, zigbee, client, clock })
+  }
+
+  pub async fn from_yaml(yaml: &str) -> Result<Self> {
+    Self::new(serde_yaml::from_str(yaml)?).await
+  }
+
+  /// Lets all components work for `duration`.
+  pub async fn run_for(&mut self, duration: Duration) {
+    let _ = tokio::time::timeout(duration, &mut self.running).await;
+  }
+
+  /// Runs long enough for a request to travel through broker, controller and devices.
+  pub async fn settle(&mut self) {
+    self.run_for(Duration::from_millis(50)).await
+  }
+
+  /// Captures the controller's traffic.
+  pub async fn record_to(&self, recorder: SharedRecorder) {
+    self.client.lock().await.record_to(recorder);
+  }
+
+  pub fn send(&self, request: Request) {
+    self.queue.send(request).expect("The simulation stopped.");
+  }
+
+  pub fn trigger(&self, event: SceneEvent) {
+    self.events.send(event).expect("The simulation stopped.");
+  }
+
+  /// Lets the simulated `remote` report a button press, e.g. `on`.
+  pub async fn press(&self, remote: &Topic, action: &str) -> Result<()> {
+    self.zigbee.press(remote, action).await
+  }
+
+  /// Lets the simulated `device` report a reading, e.g. `{"occupancy": true}`.
+  pub async fn read(&self, device: &Topic, reading: JsonValue) -> Result<()> {
+    let reading = reading.as_object().cloned().unwrap_or_default();
+    self.zigbee.read(device, &reading).await
+  }
+
+  /// Publishes a raw message as if some other client sent it.
+  pub fn publish(&self, topic: &Topic, payload: JsonValue) {
+    self.broker.publish(MqttMessage::new(topic.to_str(), payload.to_string()));
+  }
+
+  /// Payloads published to `topic` so far.
+  pub fn published_to(&self, topic: &Topic) -> Vec<JsonValue> {
+    let topic = topic.to_str();
+    self
+      .broker
+      .history()
+      .into_iter()
+      .filter(|m| m.topic == topic)
+      .filter_map(|m| serde_json::from_str(&m.payload).ok())
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::{Duration, Local, TimeZone};
+  use serde_json::json;
+
+  use crate::{
+    api::traits::QueryableHome,
+    clock::Clock,
+    simulation::fixture::{self, topic, CEILING, DIMMER, FLOOR, MOTION},
+  };
+
+  use super::TestHome;
+
+  /// Lights up the hall on motion in the evening, turns it off at night.
+  const SCENES: &str = r#"
+  - name: Welcome
+    trigger: !And
+      - !DeviceState
+        target: zigbee2mqtt/Device/Sensor/Hall/Motion
+        field: occupancy
+        op: !BoolComparison
+          pivot: true
+      - !Time
+        from: "18:00:00"
+        duration: 21600
+    effect: !LightCommand
+      target: zigbee2mqtt/Room/Hall
+      command: TurnOn
+  - name: Night
+    trigger: !Schedule
+      cron: "30 23 * * *"
+      missed: RunOnce
+    effect: !LightCommand
+      target: zigbee2mqtt/Room/Hall
+      command: TurnOff
+"#;
+
+  #[tokio::test]
+  async fn test_remote_turns_on_group() {
+    let mut sim = TestHome::from_yaml(&fixture::yaml(SCENES)).await.unwrap();
+    sim.settle().await;
+    sim.press(&topic(DIMMER), "on").await.unwrap();
+    sim.settle().await;
+    for light in [CEILING, FLOOR] {
+      let sent = sim.published_to(&topic(&format!("{light}/set")));
+      assert_eq!(sent.len(), 1, "{light} received {sent:?}");
+      assert_eq!(sent[0]["state"], json!("ON"));
+      assert_eq!(sent[0]["transition"], json!(3));
+      let home = sim.home.lock().await;
+      let reported = home.query_device(topic(&format!("{light}/get")));
+      assert_eq!(reported.to_json_value(false)["state"], json!("ON"));
+      assert!(home.is_overridden(&topic(light), sim.clock.now()), "Scenes leave {light} alone.");
+    }
+  }
+
+  #[tokio::test]
+  async fn test_motion_triggers_scene() {
+    let mut sim = TestHome::from_yaml(&fixture::yaml(SCENES)).await.unwrap();
+    sim.settle().await;
+    let (motion, floor) = (topic(MOTION), topic(&format!("{FLOOR}/set")));
+    sim.clock.set(Local.with_ymd_and_hms(2024, 3, 1, 17, 0, 0).unwrap());
+    sim.read(&motion, json!({ "occupancy": false, "battery": 90 })).await.unwrap();
+    sim.settle().await;
+    sim.read(&motion, json!({ "occupancy": true })).await.unwrap();
+    sim.settle().await;
+    assert!(sim.published_to(&floor).is_empty(), "The scene is only active in the evening.");
+    sim.clock.advance(Duration::hours(3));
+    sim.read(&motion, json!({ "occupancy": true })).await.unwrap();
+    sim.settle().await;
+    let sent = sim.published_to(&floor);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["state"], json!("ON"));
+    // At 8 p.m., lights turn on at about a third of their brightness.
+    let brightness = sent[0]["brightness"].as_f64().unwrap();
+    assert!((85.0..90.0).contains(&brightness), "Brightness was {brightness}.");
+  }
+}
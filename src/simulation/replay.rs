@@ -0,0 +1,210 @@
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Local};
+use serde_json::Value as JsonValue;
+
+use crate::{
+  clock::Clock,
+  home::Home,
+  mqtt::{
+    recorder::{Direction, Recorder, Recording},
+    MqttMessage,
+  },
+  Result,
+};
+
+use super::TestHome;
+
+/// Outgoing messages that differ from the capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+  pub time: Option<DateTime<Local>>,
+  /// The incoming message that caused them, or `None` at startup.
+  pub cause: Option<MqttMessage>,
+  pub expected: Vec<MqttMessage>,
+  pub actual: Vec<MqttMessage>,
+}
+
+impl Display for Mismatch {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match (&self.time, &self.cause) {
+      (Some(time), Some(cause)) => writeln!(f, "{time}: after {}: {}", cause.topic, cause.payload)?,
+      _ => writeln!(f, "At startup:")?,
+    }
+    let mut list = |label: &str, msgs: &[MqttMessage]| {
+      writeln!(f, "  {label}:")?;
+      msgs.iter().try_for_each(|m| writeln!(f, "    {}: {}", m.topic, m.payload))
+    };
+    list("expected", &self.expected)?;
+    list("actual", &self.actual)
+  }
+}
+
+/// An incoming message and what the controller sent until the next one came in.
+#[derive(Debug, Clone)]
+struct Segment {
+  cause: Option<Recording>,
+  expected: Vec<Recording>,
+}
+
+/// Feeds the incoming messages of a capture to a controller connected to an in-process broker and
+/// compares what it publishes to the capture.  Nothing answers but the capture, and the
+/// controller's clock jumps through the times of the recorded messages instead of waiting.
+#[derive(Debug, Clone)]
+pub struct Replay {
+  segments: Vec<Segment>,
+}
+
+impl Replay {
+  pub fn new(recordings: Vec<Recording>) -> Self {
+    let mut segments = vec![Segment { cause: None, expected: vec![] }];
+    for recording in recordings {
+      match recording.direction {
+        Direction::In => segments.push(Segment { cause: Some(recording), expected: vec![] }),
+        Direction::Out => segments.last_mut().unwrap().expected.push(recording),
+      }
+    }
+    Replay { segments }
+  }
+
+  pub fn read(paths: &[String]) -> Result<Self> {
+    Ok(Self::new(Recorder::read(paths)?))
+  }
+
+  /// Number of incoming messages.
+  pub fn len(&self) -> usize {
+    self.segments.len() - 1
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub async fn run(&self, home: Home) -> Result<Vec<Mismatch>> {
+    let times = self.segments.iter().flat_map(|s| s.cause.iter().chain(&s.expected));
+    let start = times.map(|r| r.time).next().unwrap_or_else(Local::now);
+    let mut sim = TestHome::without_devices(home, start).await?;
+    let mut mismatches = vec![];
+    let ends = self.segments.iter().skip(1).map(|s| s.cause.as_ref().map(|c| c.time));
+    for (segment, end) in self.segments.iter().zip(ends.chain([None])) {
+      sim.broker.clear_history();
+      let cause = segment.cause.as_ref().map(Recording::message);
+      if let Some(cause) = &segment.cause {
+        // The controller sees the time at which the message originally came in.
+        sim.clock.set(cause.time);
+        sim.broker.publish(cause.message());
+      }
+      sim.settle().await;
+      // Timers, schedules and delays send on their own until the next message comes in, so the
+      // clock steps through the times of what was sent and then on to the next message.
+      for time in segment.expected.iter().map(|r| r.time).chain(end) {
+        if time > sim.clock.now() {
+          sim.clock.set(time);
+          sim.settle().await;
+        }
+      }
+      let skip = usize::from(cause.is_some()); // The broker saw the incoming message first.
+      let actual: Vec<MqttMessage> = sim.broker.history().into_iter().skip(skip).collect();
+      let expected: Vec<MqttMessage> = segment.expected.iter().map(Recording::message).collect();
+      if !Self::same(&expected, &actual) {
+        let time = segment.cause.as_ref().map(|c| c.time);
+        mismatches.push(Mismatch { time, cause, expected, actual });
+      }
+    }
+    Ok(mismatches)
+  }
+
+  /// Compares payloads as JSON, so formatting does not matter.
+  fn same(expected: &[MqttMessage], actual: &[MqttMessage]) -> bool {
+    let parse = |m: &MqttMessage| {
+      (m.topic.clone(), serde_json::from_str::<JsonValue>(&m.payload).unwrap_or_default())
+    };
+    expected.len() == actual.len() && expected.iter().map(parse).eq(actual.iter().map(parse))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use chrono::Duration;
+  use serde_json::json;
+
+  use crate::{
+    clock::Clock,
+    config::RecordConfig,
+    mqtt::recorder::{Direction, Recorder},
+    simulation::{
+      fixture::{self, topic, DIMMER, FLOOR, MOTION},
+      TestHome,
+    },
+  };
+
+  use super::Replay;
+
+  #[tokio::test]
+  async fn test_replay_reproduces_capture() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_replay_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = RecordConfig { dir: dir.to_string_lossy().to_string(), ..Default::default() };
+    let mut sim = TestHome::from_yaml(&fixture::yaml("[]")).await.unwrap();
+    let recorder = Recorder::new(&config, sim.clock.shared()).unwrap();
+    let path = recorder.path(0).to_string_lossy().to_string();
+
+    sim.record_to(recorder.shared()).await;
+    sim.settle().await;
+    sim.publish(&topic(FLOOR), json!({ "state": "ON", "brightness": 100 }));
+    sim.settle().await;
+    sim.press(&topic(DIMMER), "off").await.unwrap();
+    sim.settle().await;
+
+    let mut recordings = Recorder::read(&[path]).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    assert!(recordings.iter().any(|r| r.direction == Direction::Out && r.payload.contains("OFF")));
+    let replay = Replay::new(recordings.clone());
+    assert!(!replay.is_empty());
+    let home = fixture::home("[]");
+    assert_eq!(replay.run(home).await.unwrap(), vec![]);
+
+    let off = recordings.iter_mut().find(|r| r.payload.contains("\"OFF\"")).unwrap();
+    off.payload = off.payload.replace("OFF", "ON");
+    let home = fixture::home("[]");
+    let mismatches = Replay::new(recordings).run(home).await.unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert!(mismatches[0].cause.as_ref().unwrap().payload.contains("\"off\""));
+  }
+
+  #[tokio::test]
+  async fn test_replay_steps_through_delays() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_delays_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = RecordConfig { dir: dir.to_string_lossy().to_string(), ..Default::default() };
+    let blink = r#"
+  - name: Blink
+    trigger: !DeviceState
+      target: zigbee2mqtt/Device/Sensor/Hall/Motion
+      field: occupancy
+      op: !BoolComparison { pivot: true }
+    effect: !Sequence
+      - !SetState { target: zigbee2mqtt/Device/Light/Hall/Ceiling, state: { on: true } }
+      - !Delay { duration: 60 }
+      - !SetState { target: zigbee2mqtt/Device/Light/Hall/Ceiling, state: { on: false } }
+"#;
+    let mut sim = TestHome::from_yaml(&fixture::yaml(blink)).await.unwrap();
+    sim.clock.set(fixture::start());
+    let recorder = Recorder::new(&config, sim.clock.shared()).unwrap();
+    let path = recorder.path(0).to_string_lossy().to_string();
+    sim.record_to(recorder.shared()).await;
+    sim.settle().await;
+    sim.read(&topic(MOTION), json!({ "occupancy": true })).await.unwrap();
+    sim.settle().await;
+    sim.clock.advance(Duration::seconds(61));
+    sim.settle().await;
+
+    let recordings = Recorder::read(&[path]).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    let off =
+      recordings.iter().find(|r| r.direction == Direction::Out && r.payload.contains("OFF"));
+    assert!(off.is_some_and(|r| r.time == sim.clock.now()), "Stamped with the controller's time.");
+    let home = fixture::home(blink);
+    assert_eq!(Replay::new(recordings).run(home).await.unwrap(), vec![]);
+  }
+}
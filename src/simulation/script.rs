@@ -0,0 +1,230 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::{
+  api::{
+    topic::{Topic, TopicMode},
+    traits::{Addressable, DeviceCollection},
+  },
+  devices::{Capability, DeviceModel},
+  home::Home,
+  Result,
+};
+
+use super::{SimulatedDevice, Zigbee2Mqtt};
+
+/// Device events played in order, e.g.
+///
+/// ```yaml
+/// repeat: true
+/// steps:
+///   - after: 5
+///     device: zigbee2mqtt/Device/Sensor/Hall/Motion
+///     read: { occupancy: true }
+///   - after: 2
+///     device: zigbee2mqtt/Device/Remote/Hall/Dimmer
+///     press: "on"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Script {
+  #[serde(default)]
+  pub repeat: bool,
+  pub steps: Vec<ScriptStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptStep {
+  /// Seconds to wait after the previous step.
+  #[serde(default)]
+  pub after: f64,
+  pub device: Topic,
+  #[serde(default)]
+  pub read: Option<Map<String, JsonValue>>,
+  #[serde(default)]
+  pub press: Option<String>,
+}
+
+impl Script {
+  pub fn read(path: &str) -> Result<Script> {
+    Ok(serde_yaml::from_str(&std::fs::read_to_string(path)?)?)
+  }
+
+  pub async fn play(self, zigbee: Zigbee2Mqtt) -> Result<()> {
+    loop {
+      for step in &self.steps {
+        tokio::time::sleep(Duration::from_secs_f64(step.after)).await;
+        step.perform(&zigbee).await?;
+      }
+      if !self.repeat || self.steps.is_empty() {
+        return Ok(());
+      }
+    }
+  }
+}
+
+impl ScriptStep {
+  pub async fn perform(&self, zigbee: &Zigbee2Mqtt) -> Result<()> {
+    if let Some(reading) = &self.read {
+      zigbee.read(&self.device, reading).await?;
+    }
+    if let Some(action) = &self.press {
+      zigbee.press(&self.device, action).await?;
+    }
+    Ok(())
+  }
+}
+
+/// Xorshift generator.  Plenty for plausible noise, and reproducible given a seed.
+#[allow(missing_copy_implementations)] // Avoid accidentally repeating numbers.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    Rng { state: seed.max(1) }
+  }
+
+  pub fn from_time() -> Self {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    Self::new(nanos as u64)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state << 13;
+    self.state ^= self.state >> 7;
+    self.state ^= self.state << 17;
+    self.state
+  }
+
+  /// A number in [0, 1).
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// A number in [0, n).
+  pub fn below(&mut self, n: usize) -> usize {
+    (self.next_u64() % n.max(1) as u64) as usize
+  }
+}
+
+/// Every `interval`, either a sensor reports a reading close to its previous one or a remote
+/// reports a press of one of the buttons the home configures for it.
+#[derive(Debug, Clone)]
+pub struct RandomEvents {
+  interval: Duration,
+  rng: Rng,
+  buttons: Vec<(Topic, Vec<String>)>,
+}
+
+impl RandomEvents {
+  pub fn new(home: &Home, interval: Duration, rng: Rng) -> Self {
+    let buttons = home
+      .flatten_remotes()
+      .into_iter()
+      .map(|r| (r.topic(TopicMode::Blank), r.buttons().iter().map(ToString::to_string).collect()))
+      .collect();
+    RandomEvents { interval, rng, buttons }
+  }
+
+  pub async fn run(mut self, zigbee: Zigbee2Mqtt) -> Result<()> {
+    loop {
+      tokio::time::sleep(self.interval).await;
+      if let Some(step) = self.next_step(&zigbee.devices()) {
+        step.perform(&zigbee).await?;
+      }
+    }
+  }
+
+  fn next_step(&mut self, devices: &[SimulatedDevice]) -> Option<ScriptStep> {
+    let candidates: Vec<&SimulatedDevice> = devices
+      .iter()
+      .filter(|d| Self::is_sensor(d.model()) || self.buttons_of(d.topic()).is_some())
+      .collect();
+    if candidates.is_empty() {
+      return None;
+    }
+    let device = candidates[self.rng.below(candidates.len())];
+    let mut step =
+      ScriptStep { after: 0.0, device: device.topic().clone(), read: None, press: None };
+    if let Some(buttons) = self.buttons_of(device.topic()).cloned() {
+      step.press = Some(buttons[self.rng.below(buttons.len())].clone());
+    } else {
+      step.read = Some(self.drift(device.model(), device.state()));
+    }
+    Some(step)
+  }
+
+  fn buttons_of(&self, topic: &Topic) -> Option<&Vec<String>> {
+    self.buttons.iter().find(|(t, b)| t == topic && !b.is_empty()).map(|(_, b)| b)
+  }
+
+  fn is_sensor(model: DeviceModel) -> bool {
+    [Capability::Humidity, Capability::Temperature, Capability::Occupancy]
+      .into_iter()
+      .any(|c| model.capable_of(c))
+  }
+
+  /// A reading that differs slightly from `state`.
+  fn drift(
+    &mut self,
+    model: DeviceModel,
+    state: &Map<String, JsonValue>,
+  ) -> Map<String, JsonValue> {
+    let mut reading = Map::new();
+    let mut walk = |field: &str, step: f64, min: f64, max: f64, rng: &mut Rng| {
+      let current = state.get(field).and_then(JsonValue::as_f64).unwrap_or((min + max) / 2.0);
+      let next = (current + (rng.next_f64() * 2.0 - 1.0) * step).clamp(min, max);
+      reading.insert(field.to_string(), json!((next * 10.0).round() / 10.0));
+    };
+    if model.capable_of(Capability::Humidity) {
+      walk("humidity", 2.0, 20.0, 90.0, &mut self.rng);
+    }
+    if model.capable_of(Capability::Temperature) {
+      walk("temperature", 0.5, 10.0, 35.0, &mut self.rng);
+    }
+    if model.capable_of(Capability::Occupancy) {
+      let current = state.get("occupancy").and_then(JsonValue::as_bool).unwrap_or_default();
+      reading.insert(String::from("occupancy"), json!(current != (self.rng.next_f64() < 0.3)));
+    }
+    reading
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::time::Duration;
+
+  use serde_json::{json, Map};
+
+  use crate::devices::DeviceModel;
+
+  use super::{RandomEvents, Rng, Script};
+
+  #[test]
+  fn test_parse_script() {
+    let yaml = "repeat: true\nsteps:\n  - after: 1.5\n    device: zigbee2mqtt/Device/Sensor/Hall/Motion\n    read: { occupancy: true }\n  - device: zigbee2mqtt/Device/Remote/Hall/Dimmer\n    press: \"on\"\n";
+    let script: Script = serde_yaml::from_str(yaml).unwrap();
+    assert!(script.repeat);
+    assert_eq!(script.steps[0].read.as_ref().unwrap()["occupancy"], json!(true));
+    assert_eq!(script.steps[1].after, 0.0);
+    assert_eq!(script.steps[1].press.as_deref(), Some("on"));
+  }
+
+  #[test]
+  fn test_drift_stays_plausible() {
+    let mut events = RandomEvents { interval: Duration::ZERO, rng: Rng::new(7), buttons: vec![] };
+    let mut state = Map::new();
+    for _ in 0..1000 {
+      state = events.drift(DeviceModel::TuyaHumidity, &state);
+      let humidity = state["humidity"].as_f64().unwrap();
+      assert!((20.0..=90.0).contains(&humidity));
+      assert!(state.get("occupancy").is_none());
+    }
+    let mut other = Rng::new(7);
+    assert_eq!(Rng::new(7).next_u64(), other.next_u64());
+  }
+}
@@ -0,0 +1,162 @@
+use std::{cell::RefCell, collections::HashMap, convert::Infallible, rc::Rc, time::SystemTime};
+
+use chrono::Local;
+use tokio::{sync::mpsc::UnboundedSender, time};
+
+use crate::{
+  api::request::{General, Request},
+  config::GlobalConfig,
+  home::Home,
+  Result,
+};
+
+/// Files the controller wrote itself, with the stamp they got, so that the watcher does not
+/// reload them.
+#[derive(Debug, Clone, Default)]
+pub struct OwnWrites(Rc<RefCell<HashMap<String, SystemTime>>>);
+
+impl OwnWrites {
+  /// Remembers the current stamp of `path` as one of the controller's own.
+  pub fn note(&self, path: &str) {
+    if let Some(stamp) = ConfigWatcher::stamp(path) {
+      self.0.borrow_mut().insert(path.to_string(), stamp);
+    }
+  }
+
+  /// Whether the file at `path` is still as the controller wrote it.
+  pub fn wrote(&self, path: &str) -> bool {
+    let stamp = ConfigWatcher::stamp(path);
+    stamp.is_some() && self.0.borrow().get(path) == stamp.as_ref()
+  }
+}
+
+/// Polls the global config and the home file and hot-reloads them once they change on disk.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+  config_path: String,
+  config: GlobalConfig,
+  config_stamp: Option<SystemTime>,
+  home_stamp: Option<SystemTime>,
+  own_writes: OwnWrites,
+  queue: UnboundedSender<Request>,
+}
+
+impl ConfigWatcher {
+  const INTERVAL: time::Duration = time::Duration::from_secs(2);
+
+  pub fn new(config_path: String, config: GlobalConfig, queue: UnboundedSender<Request>) -> Self {
+    let config_stamp = Self::stamp(&config_path);
+    let home_stamp = Self::stamp(&config.home.dir);
+    Self { config_path, config, config_stamp, home_stamp, own_writes: OwnWrites::default(), queue }
+  }
+
+  /// Where the controller notes the files it writes while running.
+  pub fn own_writes(&self) -> OwnWrites {
+    self.own_writes.clone()
+  }
+
+  pub async fn run(mut self) -> Result<Infallible> {
+    println!("Watching {} and {} for changes.", self.config_path, self.config.home.dir);
+    let mut interval = time::interval(Self::INTERVAL);
+    loop {
+      interval.tick().await;
+      let home_moved = self.check_config();
+      self.check_home(home_moved);
+    }
+  }
+
+  /// Returns whether the config now points to a different home file.
+  fn check_config(&mut self) -> bool {
+    let stamp = Self::stamp(&self.config_path);
+    if stamp == self.config_stamp {
+      return false;
+    }
+    self.config_stamp = stamp;
+    let config = match GlobalConfig::load(&self.config_path, self.config.overrides.clone()) {
+      Ok(config) => config,
+      Err(err) => {
+        eprintln!("Rejected change to {}: {:?}.  Keeping old config.", self.config_path, err);
+        return false;
+      }
+    };
+    if config.mosquitto != self.config.mosquitto {
+      eprintln!("Mosquitto settings changed.  Restart the controller to reconnect.");
+    }
+    if config.web != self.config.web {
+      eprintln!("Web settings changed.  Restart the controller to serve them.");
+    }
+    if config.record != self.config.record {
+      eprintln!("Capture settings changed.  Restart the controller to apply them.");
+    }
+    let home_moved = config.home != self.config.home;
+    if home_moved {
+      println!("Home file moved to {}.", config.home.dir);
+    }
+    self.config = config;
+    home_moved
+  }
+
+  fn check_home(&mut self, force: bool) {
+    let stamp = Self::stamp(&self.config.home.dir);
+    if stamp == self.home_stamp && !force {
+      return;
+    }
+    self.home_stamp = stamp;
+    if !force && self.own_writes.wrote(&self.config.home.dir) {
+      return;
+    }
+    match Home::load(&self.config.home.dir, Local::now()) {
+      Ok(home) => {
+        let path = self.config.home.dir.clone();
+        let req = Request::General(General::Reload { home: Box::new(home), path });
+        self.queue.send(req).expect("Cannot send.");
+      }
+      Err(err) => {
+        eprintln!("Rejected change to {}: {:?}.  Keeping old home.", self.config.home.dir, err)
+      }
+    }
+  }
+
+  fn stamp(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::{
+    fs::File,
+    time::{Duration, SystemTime},
+  };
+
+  use tokio::sync::mpsc::unbounded_channel;
+
+  use super::ConfigWatcher;
+  use crate::config::GlobalConfig;
+
+  #[test]
+  fn test_own_writes_are_not_reloaded() {
+    let dir = std::env::temp_dir().join(format!("rusty_home_watch_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("home.yml").to_string_lossy().to_string();
+    let write = |secs: u64| {
+      std::fs::write(&path, "name: Test\nrooms: []\nscenes: []\n").unwrap();
+      let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+      File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    };
+    write(1);
+    let overrides = [("mosquitto.ip", "x"), ("home.dir", path.as_str())];
+    let overrides = overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    let config = GlobalConfig::load(&dir.join("config.yml").to_string_lossy(), overrides).unwrap();
+    let (send, mut recv) = unbounded_channel();
+    let mut watcher = ConfigWatcher::new(config.path.clone(), config, send);
+    write(2);
+    watcher.own_writes().note(&path);
+    watcher.check_home(false);
+    assert!(recv.try_recv().is_err(), "The controller wrote the file itself.");
+    write(3);
+    watcher.check_home(false);
+    assert!(recv.try_recv().is_ok());
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
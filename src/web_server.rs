@@ -1,7 +1,7 @@
+use chrono::{DateTime, Local};
 use guard::guard;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request as HyperRequest, Response, Server, StatusCode};
-use local_ip_address::local_ip;
 use serde_json::json;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -12,26 +12,28 @@ use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
 use url::Url;
 
-use crate::api::request::{LightCommand, Query, Request, SceneCommand};
+use crate::api::request::{HomeEdit, LightCommand, Query, Request, SceneCommand};
 use crate::api::topic::Topic;
-use crate::convert::{Hue, RestApiPayload, Sat, Val};
+use crate::convert::{Hue, Origin, RestApiPayload, Sat, Val};
+use crate::home::validation::Diagnostic;
+use crate::scenes::scene::Scene;
 use crate::Result;
 
 #[derive(Debug)]
 pub struct WebServer {
   queue: UnboundedSender<Request>,
+  addr: SocketAddr,
 }
 
 impl WebServer {
-  pub fn new(queue: UnboundedSender<Request>) -> Self {
+  pub fn new(queue: UnboundedSender<Request>, addr: SocketAddr) -> Self {
     println!("WebServer created.");
-    Self { queue }
+    Self { queue, addr }
   }
 
   pub async fn run(self) -> Result<Infallible> {
-    println!("Running web server.");
-    let WebServer { queue } = self;
-    let addr = SocketAddr::new(local_ip().unwrap(), 8088);
+    println!("Running web server on {}.", self.addr);
+    let WebServer { queue, addr } = self;
     let make_svc = make_service_fn(move |_conn| {
       let clone = queue.clone();
       async move { Ok::<_, Infallible>(service_fn(move |req| Self::process(req, clone.clone()))) }
@@ -49,10 +51,13 @@ impl WebServer {
     queue: UnboundedSender<Request>,
   ) -> std::result::Result<Response<Body>, Infallible> {
     println!("\nReceived web request: {}", req.uri());
-    if req.method() != Method::GET {
-      println!("Bad request: Not a get-request.");
-      return Ok(Self::bad_request("Only get requests are allowed."));
+    // Scene definitions are posted as JSON and deleted with a delete-request, everything else is
+    // a get-request.
+    if ![Method::GET, Method::POST, Method::DELETE].contains(req.method()) {
+      println!("Bad request: Neither a get-, a post- nor a delete-request.");
+      return Ok(Self::bad_request("Only get, post and delete requests are allowed."));
     }
+    let method = req.method().clone();
     let url =
       Url::parse("http://localhost:8088").and_then(|b| b.join(&req.uri().to_string())).unwrap();
     let mut segments = url.path_segments().unwrap();
@@ -60,7 +65,7 @@ impl WebServer {
     let response = match category {
       "query" => Self::handle_query(segments, &url, queue).await,
       "command" => Self::handle_command(segments, &url, queue).await,
-      "scene" => Self::handle_scene(segments, &url, queue).await,
+      "scene" => Self::handle_scene(segments, &url, method, req.into_body(), queue).await,
       "test" => Self::accepted("Test successful.".to_string()),
       _ => todo!(),
     };
@@ -70,18 +75,76 @@ impl WebServer {
   async fn handle_scene(
     mut segments: Split<'_, char>,
     url: &Url,
+    method: Method,
+    body: Body,
     queue: UnboundedSender<Request>,
   ) -> Response<Body> {
     guard!(let Some(command) = segments.next() else { return Self::bad_request("Scene triggers need a command.") });
-    if command != "TriggerScene" {
-      return Self::bad_request("Unknown subcommand.");
-    }
     let payload = Self::transform_query(url);
-    guard!(let Some(name) = payload.name else { return Self::bad_request("Scene triggers need a name.") });
-    queue.send(Request::SceneCommand(SceneCommand::Trigger(name))).unwrap();
+    // Edits change the home, so a get-request must not make them.
+    match (command, method) {
+      ("CreateScene" | "UpdateScene", Method::POST)
+      | ("DeleteScene", Method::POST | Method::DELETE) => {
+        return Self::edit_scene(command, payload.name, body, queue).await;
+      }
+      ("CreateScene" | "UpdateScene", _) => {
+        return Self::respond(StatusCode::METHOD_NOT_ALLOWED, "Scenes are posted.".to_string());
+      }
+      ("DeleteScene", _) => {
+        let msg = "Scenes are deleted with a delete- or post-request.".to_string();
+        return Self::respond(StatusCode::METHOD_NOT_ALLOWED, msg);
+      }
+      _ => {}
+    }
+    if command == "ClearOverrides" {
+      queue.send(Request::SceneCommand(SceneCommand::ClearOverrides(payload.topic))).unwrap();
+      return Self::accepted("Success".to_string());
+    }
+    guard!(let Some(name) = payload.name else { return Self::bad_request("Scene commands need a name.") });
+    let command = match (command, payload.topic) {
+      ("TriggerScene", _) => SceneCommand::Trigger(name),
+      ("SavePreset", Some(target)) => SceneCommand::SavePreset { name, target },
+      ("SavePreset", None) => return Self::bad_request("Presets need a topic."),
+      ("RestorePreset", _) => SceneCommand::RestorePreset(name),
+      ("DeletePreset", _) => SceneCommand::DeletePreset(name),
+      ("EnableScene", _) => SceneCommand::SetEnabled { name, enabled: true },
+      ("DisableScene", _) => SceneCommand::SetEnabled { name, enabled: false },
+      ("ToggleScene", _) => SceneCommand::ToggleEnabled(name),
+      _ => return Self::bad_request("Unknown subcommand."),
+    };
+    queue.send(Request::SceneCommand(command)).unwrap();
     Self::accepted("Success".to_string())
   }
 
+  async fn edit_scene(
+    command: &str,
+    name: Option<String>,
+    body: Body,
+    queue: UnboundedSender<Request>,
+  ) -> Response<Body> {
+    let edit = match (command, name) {
+      ("DeleteScene", Some(name)) => HomeEdit::DeleteScene(name),
+      ("DeleteScene", None) => return Self::bad_request("Scene commands need a name."),
+      (command, _) => {
+        guard!(let Ok(body) = hyper::body::to_bytes(body).await else { return Self::bad_request("Cannot read body.") });
+        guard!(let Ok(scene) = serde_json::from_slice::<Scene>(&body) else { return Self::bad_request("Body is not a scene.") });
+        match command {
+          "CreateScene" => HomeEdit::CreateScene(Box::new(scene)),
+          _ => HomeEdit::UpdateScene(Box::new(scene)),
+        }
+      }
+    };
+    let (sender, receiver) = oneshot::channel();
+    queue.send(Request::HomeEdit(edit, sender)).expect("Error handling");
+    let diagnostics: Vec<Diagnostic> = receiver.await.unwrap();
+    let resp = json!(diagnostics).to_string();
+    if diagnostics.iter().any(Diagnostic::is_error) {
+      println!("Rejected scene edit: {resp}");
+      return Self::respond(StatusCode::UNPROCESSABLE_ENTITY, resp);
+    }
+    Self::accepted(resp)
+  }
+
   async fn handle_command(
     mut segments: Split<'_, char>,
     url: &Url,
@@ -111,6 +174,17 @@ impl WebServer {
         let payload = Self::transform_query(url);
         Request::Query(Query::DeviceHistory(payload.topic.unwrap()), sender)
       }
+      Some("Overrides") => Request::Query(Query::Overrides, sender),
+      Some("Scenes") => Request::Query(Query::Scenes, sender),
+      Some("Health") => Request::Query(Query::Health, sender),
+      Some("SceneHistory") => match Self::transform_query(url).name {
+        Some(name) => Request::Query(Query::SceneHistory(name), sender),
+        None => return Self::bad_request("Scene histories need a name."),
+      },
+      Some("Explain") => match Self::explain_query(url) {
+        Ok(query) => Request::Query(query, sender),
+        Err(msg) => return Self::bad_request(msg),
+      },
       None => return Self::bad_request("Queries need a subcommand."),
       Some(_) => return Self::bad_request("Unknown subcommand."),
     };
@@ -124,17 +198,40 @@ impl WebServer {
     Self::accepted(resp)
   }
 
+  /// Reads `name`, optionally `topic` and `payload` of a hypothetical update, and `at`, an RFC 3339
+  /// time.
+  fn explain_query(url: &Url) -> std::result::Result<Query, &'static str> {
+    let map: HashMap<Cow<'_, str>, Cow<'_, str>> = url.query_pairs().collect();
+    guard!(let Some(scene) = map.get("name").map(|n| n.to_string()) else { return Err("Explanations need a name.") });
+    let update = match (map.get("topic"), map.get("payload")) {
+      (Some(topic), Some(payload)) => {
+        let topic = Topic::try_from(topic.to_string()).map_err(|_| "Invalid topic.")?;
+        let payload = serde_json::from_str(payload).map_err(|_| "Payload is no JSON.")?;
+        Some((topic, payload))
+      }
+      (None, None) => None,
+      _ => return Err("Updates need a topic and a payload."),
+    };
+    let at = match map.get("at") {
+      Some(at) => {
+        Some(DateTime::parse_from_rfc3339(at).map_err(|_| "Invalid time.")?.with_timezone(&Local))
+      }
+      None => None,
+    };
+    Ok(Query::Explain { scene, update, at })
+  }
+
   fn bad_request(msg: &'static str) -> Response<Body> {
-    let mut response = Response::new(Body::empty());
-    *response.status_mut() = StatusCode::BAD_REQUEST;
-    *response.body_mut() = Body::from(msg);
-    response
+    Self::respond(StatusCode::BAD_REQUEST, msg.to_string())
   }
 
   fn accepted(msg: String) -> Response<Body> {
-    let mut response = Response::new(Body::empty());
-    *response.status_mut() = StatusCode::ACCEPTED;
-    *response.body_mut() = Body::from(msg);
+    Self::respond(StatusCode::ACCEPTED, msg)
+  }
+
+  fn respond(status: StatusCode, msg: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(msg));
+    *response.status_mut() = status;
     response
   }
 
@@ -145,6 +242,40 @@ impl WebServer {
     let hue = map.get("hue").map(|b| b.parse().unwrap()).map(Hue::from_rest);
     let sat = map.get("saturation").map(|b| b.parse().unwrap()).map(Sat::from_rest);
     let name = map.get("name").map(|b| b.to_string());
-    RestApiPayload { topic, val, hue, sat, name }
+    RestApiPayload { topic, val, hue, sat, name, ..RestApiPayload::new(Origin::Manual) }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use hyper::{Body, Method, Request as HyperRequest, StatusCode};
+  use tokio::sync::mpsc::unbounded_channel;
+
+  use super::WebServer;
+  use crate::{
+    api::request::{HomeEdit, Request},
+    home::validation::Diagnostic,
+  };
+
+  fn request(method: Method) -> HyperRequest<Body> {
+    let uri = "/scene/DeleteScene?name=Day";
+    HyperRequest::builder().method(method).uri(uri).body(Body::empty()).unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_edits_need_a_post_or_delete_request() {
+    let (queue, mut requests) = unbounded_channel();
+    let response = WebServer::process(request(Method::GET), queue.clone()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert!(requests.try_recv().is_err(), "Nothing was deleted.");
+    let reject = async {
+      let Some(Request::HomeEdit(HomeEdit::DeleteScene(name), reply)) = requests.recv().await
+      else {
+        panic!("The scene is to be deleted.")
+      };
+      reply.send(vec![Diagnostic::error(format!("Scene {name} does not exist."))]).unwrap();
+    };
+    let (response, ()) = tokio::join!(WebServer::process(request(Method::DELETE), queue), reject);
+    assert_eq!(response.unwrap().status(), StatusCode::UNPROCESSABLE_ENTITY);
   }
 }